@@ -1,13 +1,16 @@
 mod default;
+pub mod profile;
 
+use std::collections::BTreeMap;
 use std::default::Default;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
 use crate::opts::Opts;
 
 use default::DEFAULT_CONFIG_SETTINGS;
+use profile::{ProfileRule, ProfileState, Profiles, DEFAULT_PROFILE};
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(transparent)]
@@ -23,6 +26,21 @@ impl Default for PluginSettings {
             );
         google_cal.insert(String::from("accounts"), google_cal_accounts_val);
 
+        let mut ics_cal: toml::value::Table = toml::map::Map::new();
+        ics_cal.insert(String::from("paths"), toml::Value::Array(vec![]));
+        ics_cal.insert(String::from("emails"), toml::Value::Array(vec![]));
+
+        let mut caldav: toml::value::Table = toml::map::Map::new();
+        caldav.insert(String::from("accounts"), toml::Value::Array(vec![]));
+
+        let mut microsoft_cal: toml::value::Table = toml::map::Map::new();
+        microsoft_cal
+            .insert(String::from("accounts"), toml::Value::Array(vec![]));
+        microsoft_cal.insert(
+            String::from("client_id"),
+            toml::Value::String(String::new()),
+        );
+
         let x11_window_title_checker: toml::value::Table =
             toml::map::Map::new();
 
@@ -32,6 +50,14 @@ impl Default for PluginSettings {
             String::from("google_calendar"),
             toml::Value::Table(google_cal),
         );
+        plugin_settings_table
+            .insert(String::from("ics_calendar"), toml::Value::Table(ics_cal));
+        plugin_settings_table
+            .insert(String::from("caldav"), toml::Value::Table(caldav));
+        plugin_settings_table.insert(
+            String::from("microsoft_calendar"),
+            toml::Value::Table(microsoft_cal),
+        );
         plugin_settings_table.insert(
             String::from("x11_window_title_checker"),
             toml::Value::Table(x11_window_title_checker),
@@ -41,6 +67,269 @@ impl Default for PluginSettings {
     }
 }
 
+/// What to do when breaks have been put off for longer than
+/// `seconds_between_breaks`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compensation {
+    /// Make the next break longer.
+    LengthenBreak,
+    /// Make the work period after the next break shorter.
+    ShortenWorkPeriod,
+}
+
+/// How strictly a break is enforced.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Enforcement {
+    /// Only send a desktop notification.
+    Notify,
+    /// Cover every monitor with a translucent window that can be dismissed,
+    /// and leave the keyboard and mouse alone.
+    Overlay,
+    /// Cover every monitor and grab the keyboard and mouse.
+    #[default]
+    Lock,
+    /// Lock the screen with the system screen locker, through `loginctl
+    /// lock-session`.
+    LockSession,
+}
+
+/// Whether plugins are asked before a break starts.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum PluginPolicy {
+    /// Plugins can put the break off, for example during a meeting.
+    #[default]
+    Check,
+    /// The break always starts on time.
+    Ignore,
+}
+
+/// How breaks are scheduled.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Schedule {
+    /// Each break tier has a break every `seconds_between_breaks`.
+    Interval,
+    /// Work periods alternate with short breaks, and every few work periods
+    /// are followed by a long break instead.
+    Pomodoro,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct PomodoroSettings {
+    #[serde(default = "default_pomodoro_work_seconds")]
+    pub work_seconds: u32,
+    #[serde(default = "default_pomodoro_short_break_seconds")]
+    pub short_break_seconds: u32,
+    #[serde(default = "default_pomodoro_long_break_seconds")]
+    pub long_break_seconds: u32,
+    #[serde(default = "default_pomodoro_cycles_before_long_break")]
+    pub cycles_before_long_break: u32,
+    #[serde(default = "default_pomodoro_reset_cycle_after_idle_seconds")]
+    pub reset_cycle_after_idle_seconds: u32,
+}
+
+const fn default_pomodoro_work_seconds() -> u32 {
+    60 * 25
+}
+
+const fn default_pomodoro_short_break_seconds() -> u32 {
+    60 * 5
+}
+
+const fn default_pomodoro_long_break_seconds() -> u32 {
+    60 * 15
+}
+
+const fn default_pomodoro_cycles_before_long_break() -> u32 {
+    4
+}
+
+const fn default_pomodoro_reset_cycle_after_idle_seconds() -> u32 {
+    60 * 30
+}
+
+impl Default for PomodoroSettings {
+    fn default() -> Self {
+        Self {
+            work_seconds: default_pomodoro_work_seconds(),
+            short_break_seconds: default_pomodoro_short_break_seconds(),
+            long_break_seconds: default_pomodoro_long_break_seconds(),
+            cycles_before_long_break: default_pomodoro_cycles_before_long_break(
+            ),
+            reset_cycle_after_idle_seconds:
+                default_pomodoro_reset_cycle_after_idle_seconds(),
+        }
+    }
+}
+
+/// What the user has to do to end a break early.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChallengeKind {
+    /// Press `key` `clicks_to_end_break_early` times.
+    PressKey,
+    /// Type `sentence` exactly.
+    TypeSentence,
+    /// Solve `problems` arithmetic problems.
+    Arithmetic,
+    /// Hold `key` down for `hold_seconds`.
+    HoldKey,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ChallengeSettings {
+    #[serde(default = "default_challenge_kind")]
+    pub kind: ChallengeKind,
+    /// The name of the key for the `press-key` and `hold-key` challenges,
+    /// like `space` or `Return`.
+    #[serde(default = "default_challenge_key")]
+    pub key: String,
+    #[serde(default = "default_challenge_sentence")]
+    pub sentence: String,
+    #[serde(default = "default_challenge_problems")]
+    pub problems: u32,
+    #[serde(default = "default_challenge_hold_seconds")]
+    pub hold_seconds: u32,
+    /// Whether each break ended early makes the challenge harder for the
+    /// rest of the day.
+    #[serde(default = "default_challenge_increase_difficulty")]
+    pub increase_difficulty: bool,
+}
+
+const fn default_challenge_kind() -> ChallengeKind {
+    ChallengeKind::PressKey
+}
+
+fn default_challenge_key() -> String {
+    String::from("space")
+}
+
+fn default_challenge_sentence() -> String {
+    String::from("I am choosing to skip my break, even though I need it.")
+}
+
+const fn default_challenge_problems() -> u32 {
+    3
+}
+
+const fn default_challenge_hold_seconds() -> u32 {
+    10
+}
+
+const fn default_challenge_increase_difficulty() -> bool {
+    false
+}
+
+impl Default for ChallengeSettings {
+    fn default() -> Self {
+        Self {
+            kind: default_challenge_kind(),
+            key: default_challenge_key(),
+            sentence: default_challenge_sentence(),
+            problems: default_challenge_problems(),
+            hold_seconds: default_challenge_hold_seconds(),
+            increase_difficulty: default_challenge_increase_difficulty(),
+        }
+    }
+}
+
+/// Something to do during a break, like a stretch or an eye exercise.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Activity {
+    pub name: String,
+    #[serde(default)]
+    pub text: String,
+    /// The path of an image to show on the break screen.
+    pub image: Option<PathBuf>,
+    #[serde(default)]
+    pub steps: Vec<ActivityStep>,
+    /// The names of the break tiers this activity is for.  If this is empty,
+    /// the activity is for all breaks.
+    #[serde(default)]
+    pub tiers: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ActivityStep {
+    pub text: String,
+    pub seconds: u32,
+}
+
+/// How the activity for each break is picked.  Either way, an activity isn't
+/// picked again until all the other activities have been.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActivityRotation {
+    /// In the order they are in the config file.
+    InOrder,
+    /// In a random order.
+    Shuffle,
+}
+
+/// One kind of break, with its own cadence.  For example, short eye breaks
+/// every 20 minutes and long breaks every hour.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct BreakTier {
+    pub name: String,
+    pub seconds_between_breaks: u32,
+    pub break_duration_seconds: u32,
+    #[serde(default = "default_clicks_to_end_break_early")]
+    pub clicks_to_end_break_early: u32,
+    #[serde(default)]
+    pub enforcement: Enforcement,
+    #[serde(default)]
+    pub plugins: PluginPolicy,
+    /// Whether breaks happen at multiples of `seconds_between_breaks` after
+    /// midnight, instead of `seconds_between_breaks` after the last break.
+    #[serde(default)]
+    pub align_to_clock: bool,
+    /// How far the breaks are moved from those multiples when
+    /// `align_to_clock` is on.
+    #[serde(default)]
+    pub clock_offset_seconds: u32,
+}
+
+/// A break at a fixed time on the clock, like lunch.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct FixedBreak {
+    pub name: String,
+    /// When the break happens, like `12:30` for every day at 12:30, or `*:50`
+    /// for every hour at 50 minutes past.
+    pub at: String,
+    pub break_duration_seconds: u32,
+    #[serde(default = "default_clicks_to_end_break_early")]
+    pub clicks_to_end_break_early: u32,
+    #[serde(default)]
+    pub enforcement: Enforcement,
+    #[serde(default)]
+    pub plugins: PluginPolicy,
+}
+
+impl FixedBreak {
+    /// The break tier for this fixed break.  The time between breaks comes
+    /// from `at` instead.
+    pub fn break_tier(&self) -> BreakTier {
+        BreakTier {
+            name: self.name.clone(),
+            seconds_between_breaks: 0,
+            break_duration_seconds: self.break_duration_seconds,
+            clicks_to_end_break_early: self.clicks_to_end_break_early,
+            enforcement: self.enforcement,
+            plugins: self.plugins,
+            align_to_clock: false,
+            clock_offset_seconds: 0,
+        }
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Settings {
     #[serde(default = "default_break_duration_seconds")]
@@ -53,6 +342,52 @@ pub struct Settings {
     pub idle_detection_enabled: bool,
     #[serde(default = "default_idle_detection_seconds")]
     pub idle_detection_seconds: u32,
+    #[serde(default = "default_wait_for_input_after_break")]
+    pub wait_for_input_after_break: bool,
+    #[serde(default = "default_calendar_aware_breaks")]
+    pub calendar_aware_breaks: bool,
+    #[serde(default = "default_min_break_duration_seconds")]
+    pub min_break_duration_seconds: u32,
+    #[serde(default = "default_max_seconds_to_pull_break_forward")]
+    pub max_seconds_to_pull_break_forward: u32,
+    #[serde(default = "default_compensation")]
+    pub compensation: Compensation,
+    #[serde(default = "default_compensation_ratio")]
+    pub compensation_ratio: f64,
+    #[serde(default = "default_max_compensation_seconds")]
+    pub max_compensation_seconds: u32,
+    #[serde(default = "default_align_to_clock")]
+    pub align_to_clock: bool,
+    #[serde(default = "default_clock_offset_seconds")]
+    pub clock_offset_seconds: u32,
+    #[serde(default = "default_enforcement")]
+    pub enforcement: Enforcement,
+    #[serde(default)]
+    pub allowed_windows: Vec<String>,
+    #[serde(default = "default_warning_before_break_seconds")]
+    pub warning_before_break_seconds: u32,
+    #[serde(default = "default_postpone_break_seconds")]
+    pub postpone_break_seconds: u32,
+    #[serde(default = "default_dim_before_break_seconds")]
+    pub dim_before_break_seconds: u32,
+    #[serde(default = "default_dim_brightness")]
+    pub dim_brightness: f64,
+    #[serde(default = "default_dim_desaturate")]
+    pub dim_desaturate: bool,
+    #[serde(default, rename = "break_tier")]
+    pub break_tiers: Vec<BreakTier>,
+    #[serde(default, rename = "fixed_break")]
+    pub fixed_breaks: Vec<FixedBreak>,
+    #[serde(default = "default_schedule")]
+    pub schedule: Schedule,
+    #[serde(default)]
+    pub pomodoro: PomodoroSettings,
+    #[serde(default)]
+    pub challenge: ChallengeSettings,
+    #[serde(default, rename = "activity")]
+    pub activities: Vec<Activity>,
+    #[serde(default = "default_activity_rotation")]
+    pub activity_rotation: ActivityRotation,
     #[serde(rename = "plugin")]
     pub all_plugin_settings: PluginSettings,
 }
@@ -77,6 +412,74 @@ const fn default_idle_detection_seconds() -> u32 {
     480
 }
 
+const fn default_wait_for_input_after_break() -> bool {
+    false
+}
+
+const fn default_calendar_aware_breaks() -> bool {
+    true
+}
+
+const fn default_min_break_duration_seconds() -> u32 {
+    60 * 5
+}
+
+const fn default_max_seconds_to_pull_break_forward() -> u32 {
+    60 * 15
+}
+
+const fn default_compensation() -> Compensation {
+    Compensation::LengthenBreak
+}
+
+const fn default_compensation_ratio() -> f64 {
+    0.5
+}
+
+const fn default_max_compensation_seconds() -> u32 {
+    60 * 10
+}
+
+const fn default_align_to_clock() -> bool {
+    false
+}
+
+const fn default_clock_offset_seconds() -> u32 {
+    0
+}
+
+const fn default_enforcement() -> Enforcement {
+    Enforcement::Lock
+}
+
+const fn default_warning_before_break_seconds() -> u32 {
+    60
+}
+
+const fn default_postpone_break_seconds() -> u32 {
+    60 * 5
+}
+
+const fn default_activity_rotation() -> ActivityRotation {
+    ActivityRotation::Shuffle
+}
+
+const fn default_dim_before_break_seconds() -> u32 {
+    0
+}
+
+const fn default_dim_brightness() -> f64 {
+    0.5
+}
+
+const fn default_dim_desaturate() -> bool {
+    false
+}
+
+const fn default_schedule() -> Schedule {
+    Schedule::Interval
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Self {
@@ -86,15 +489,93 @@ impl Default for Settings {
             all_plugin_settings: PluginSettings::default(),
             idle_detection_enabled: default_idle_detection_enabled(),
             idle_detection_seconds: default_idle_detection_seconds(),
+            wait_for_input_after_break: default_wait_for_input_after_break(),
+            calendar_aware_breaks: default_calendar_aware_breaks(),
+            min_break_duration_seconds: default_min_break_duration_seconds(),
+            max_seconds_to_pull_break_forward:
+                default_max_seconds_to_pull_break_forward(),
+            compensation: default_compensation(),
+            compensation_ratio: default_compensation_ratio(),
+            max_compensation_seconds: default_max_compensation_seconds(),
+            align_to_clock: default_align_to_clock(),
+            clock_offset_seconds: default_clock_offset_seconds(),
+            enforcement: default_enforcement(),
+            allowed_windows: vec![],
+            warning_before_break_seconds: default_warning_before_break_seconds(
+            ),
+            postpone_break_seconds: default_postpone_break_seconds(),
+            dim_before_break_seconds: default_dim_before_break_seconds(),
+            dim_brightness: default_dim_brightness(),
+            dim_desaturate: default_dim_desaturate(),
+            break_tiers: vec![],
+            fixed_breaks: vec![],
+            schedule: default_schedule(),
+            pomodoro: PomodoroSettings::default(),
+            challenge: ChallengeSettings::default(),
+            activities: vec![],
+            activity_rotation: default_activity_rotation(),
         }
     }
 }
 
+impl Settings {
+    /// The configured break tiers, sorted from the shortest break to the
+    /// longest.  If no tiers are configured, there is a single tier made from
+    /// `break_duration_seconds`, `seconds_between_breaks`,
+    /// `clicks_to_end_break_early`, `enforcement`, `align_to_clock`, and
+    /// `clock_offset_seconds`.
+    ///
+    /// In Pomodoro mode, there is a tier for the short breaks and a tier for
+    /// the long breaks.
+    pub fn break_tiers(&self) -> Vec<BreakTier> {
+        let pomodoro_tier = |name: &str, break_duration_seconds| BreakTier {
+            name: String::from(name),
+            seconds_between_breaks: self.pomodoro.work_seconds,
+            break_duration_seconds,
+            clicks_to_end_break_early: self.clicks_to_end_break_early,
+            enforcement: self.enforcement,
+            plugins: PluginPolicy::default(),
+            align_to_clock: false,
+            clock_offset_seconds: 0,
+        };
+
+        let mut break_tiers = if self.schedule == Schedule::Pomodoro {
+            vec![
+                pomodoro_tier("short break", self.pomodoro.short_break_seconds),
+                pomodoro_tier("long break", self.pomodoro.long_break_seconds),
+            ]
+        } else if self.break_tiers.is_empty() {
+            vec![BreakTier {
+                name: String::from("break"),
+                seconds_between_breaks: self.seconds_between_breaks,
+                break_duration_seconds: self.break_duration_seconds,
+                clicks_to_end_break_early: self.clicks_to_end_break_early,
+                enforcement: self.enforcement,
+                plugins: PluginPolicy::default(),
+                align_to_clock: self.align_to_clock,
+                clock_offset_seconds: self.clock_offset_seconds,
+            }]
+        } else {
+            self.break_tiers.clone()
+        };
+        break_tiers.sort_by_key(|tier| tier.break_duration_seconds);
+        break_tiers
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Config {
     pub file_path: PathBuf,
     pub cache_dir: PathBuf,
     pub settings: Settings,
+    /// The names of all the profiles, starting with the default profile.
+    pub profile_names: Vec<String>,
+    /// The profile that `settings` come from.
+    pub active_profile: String,
+    pub profile_rules: Vec<ProfileRule>,
+    /// The settings of each profile, all checked when the config file is
+    /// loaded.
+    pub profile_settings: BTreeMap<String, Settings>,
 }
 
 impl Config {
@@ -129,7 +610,7 @@ impl Config {
 
         // Try reading the config file to see whether it exists or not.
         let res_config_file = std::fs::read_to_string(&config_file_path);
-        let settings = match res_config_file {
+        let config_file = match res_config_file {
             // TODO: I should probably check here the reason we are getting an
             // error.  If there is a bad permission on the file, then I
             // should probably just error out fast instead of trying to
@@ -148,31 +629,84 @@ impl Config {
                             err
                         ),
                 }
-                Settings::default()
+                String::from(DEFAULT_CONFIG_SETTINGS)
             }
-            Ok(config_file) => {
-                let res_settings = toml::from_str(&config_file);
-                match res_settings {
-                    Err(err) => {
-                        panic!(
-                            "Can't parse config file at {:?} because of the following error: {}",
-                            config_file_path,
-                            err
-                        )
-                    }
-                    Ok(settings) => settings,
-                }
+            Ok(config_file) => config_file,
+        };
+
+        let parse_err = |err: &dyn std::fmt::Display| -> ! {
+            panic!(
+                "Can't parse config file at {:?} because of the following error: {}",
+                config_file_path,
+                err
+            )
+        };
+        let mut config_table: toml::value::Table =
+            toml::from_str(&config_file).unwrap_or_else(|err| parse_err(&err));
+        let profiles = Profiles::take_from(&mut config_table)
+            .unwrap_or_else(|err| parse_err(&err));
+        // Check every profile now, so that a broken profile isn't only found
+        // when switching to it.
+        let profile_settings: BTreeMap<String, Settings> = profiles
+            .names()
+            .into_iter()
+            .map(|name| {
+                let mut profile_table = config_table.clone();
+                profiles.apply(&name, &mut profile_table);
+                let settings = toml::Value::Table(profile_table)
+                    .try_into()
+                    .unwrap_or_else(|err| {
+                        parse_err(&format!(
+                            "in the profile {:?}: {}",
+                            name, err
+                        ))
+                    });
+                (name, settings)
+            })
+            .collect();
+        let active_profile = match ProfileState::load(&cache_dir).active {
+            Some(active) if profiles.exists(&active) => active,
+            Some(active) => {
+                println!(
+                    "The active profile {:?} is not in the config file, so using the default profile",
+                    active
+                );
+                String::from(DEFAULT_PROFILE)
             }
+            None => String::from(DEFAULT_PROFILE),
         };
+        let settings = profile_settings[&active_profile].clone();
 
         let config = Self {
             file_path: config_file_path,
             cache_dir,
             settings,
+            profile_names: profiles.names(),
+            active_profile,
+            profile_rules: profiles.rules,
+            profile_settings,
         };
 
         Ok(config)
     }
+
+    /// This config, with the settings of the profile called `name`.
+    pub fn with_profile(&self, name: &str) -> Option<Self> {
+        let settings = self.profile_settings.get(name)?.clone();
+        Some(Self {
+            settings,
+            active_profile: String::from(name),
+            ..self.clone()
+        })
+    }
+
+    /// The directory with the config file, where `ui.glade` and `style.css`
+    /// can be put to change the look of the break screen.
+    pub fn conf_dir(&self) -> &Path {
+        self.file_path
+            .parent()
+            .expect("The config file should always be in a directory")
+    }
 }
 
 #[cfg(test)]
@@ -187,4 +721,46 @@ mod tests {
 
         assert_eq!(settings_from_default_instance, settings_from_default_const);
     }
+
+    #[test]
+    fn test_break_tiers() {
+        let settings: Settings = toml::from_str(indoc::indoc!(
+            r#"
+            [[break_tier]]
+            name = "long break"
+            seconds_between_breaks = 3600
+            break_duration_seconds = 600
+
+            [[break_tier]]
+            name = "eye break"
+            seconds_between_breaks = 1200
+            break_duration_seconds = 20
+            clicks_to_end_break_early = 10
+            enforcement = "overlay"
+            plugins = "ignore"
+
+            [plugin]
+            "#
+        ))
+        .unwrap();
+
+        let break_tiers = settings.break_tiers();
+        assert_eq!(break_tiers.len(), 2);
+        assert_eq!(break_tiers[0].name, "eye break");
+        assert_eq!(break_tiers[0].enforcement, Enforcement::Overlay);
+        assert_eq!(break_tiers[0].plugins, PluginPolicy::Ignore);
+        assert_eq!(break_tiers[1].name, "long break");
+        assert_eq!(
+            break_tiers[1].clicks_to_end_break_early,
+            default_clicks_to_end_break_early()
+        );
+        assert_eq!(break_tiers[1].enforcement, Enforcement::Lock);
+
+        let break_tiers = Settings::default().break_tiers();
+        assert_eq!(break_tiers.len(), 1);
+        assert_eq!(
+            break_tiers[0].seconds_between_breaks,
+            default_seconds_between_breaks()
+        );
+    }
 }
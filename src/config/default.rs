@@ -25,10 +25,366 @@ pub const DEFAULT_CONFIG_SETTINGS: &str = indoc!(
     # another seconds_between_breaks until starting another break.
     idle_detection_seconds = 480 # 8 minutes
 
+    # Whether to wait for you to come back before starting the next work
+    # period.  If set to true, the time until the next break only starts
+    # counting down at the first key press or mouse movement after a break,
+    # so that time spent away from the computer isn't counted as work.
+    wait_for_input_after_break = false
+
+    # Whether or not to plan breaks around the meetings on your calendars.
+    #
+    # If set to true (the default), a break that would run into a meeting is
+    # pulled forward so that it ends when the meeting starts, as long as that
+    # is at most `max_seconds_to_pull_break_forward` early.  Otherwise, the
+    # break is moved to the first free time after the meeting, and shortened
+    # if it only fits between back-to-back meetings.
+    #
+    # If set to false, a break that would run into a meeting is just put off
+    # until the meeting is over.
+    calendar_aware_breaks = true
+
+    # The shortest break to take when a break is shortened to fit between
+    # meetings.  Free time shorter than this is skipped.
+    min_break_duration_seconds = 300 # 5 minutes
+
+    # How much earlier than it is due a break can be pulled forward to fit
+    # before a meeting.
+    max_seconds_to_pull_break_forward = 900 # 15 minutes
+
+    # What to do when plugins have put a break off for longer than
+    # `seconds_between_breaks`, for example during a long meeting.
+    #
+    # \"lengthen-break\" (the default) makes the next break longer.
+    # \"shorten-work-period\" makes the work period after the next break
+    # shorter instead.  A break that can't be made longer because of a
+    # meeting right after it also shortens the next work period.
+    compensation = \"lengthen-break\"
+
+    # How much compensation to give for each second past
+    # `seconds_between_breaks`.  With 0.5, working 20 minutes too long gives a
+    # break that is 10 minutes longer.  Set to 0 to turn compensation off.
+    compensation_ratio = 0.5
+
+    # The most compensation to give, no matter how long breaks were put off.
+    max_compensation_seconds = 600 # 10 minutes
+
+    # Whether breaks happen at multiples of `seconds_between_breaks` after
+    # midnight, instead of `seconds_between_breaks` after the last break.  For
+    # example, with breaks every hour, they happen on the hour.  The times are
+    # on the local clock, so they stay put when daylight saving time starts or
+    # ends.
+    align_to_clock = false
+
+    # How far to move breaks from those multiples when `align_to_clock` is
+    # on.  For example, 3000 with breaks every hour makes them happen at 50
+    # minutes past the hour.
+    clock_offset_seconds = 0
+
+    # How strictly breaks are enforced.
+    #
+    # \"notify\" only sends a desktop notification.
+    #
+    # \"overlay\" covers the screen with a translucent window, without
+    # grabbing the keyboard and mouse.  The window can be dismissed with Escape
+    # or a click.
+    #
+    # \"lock\" (the default) covers the screen and grabs the keyboard and
+    # mouse.
+    #
+    # \"lock-session\" locks the screen with the system screen locker, through
+    # `loginctl lock-session`.  If that fails, \"lock\" is used instead.
+    #
+    # Either way, the break lasts `break_duration_seconds`, and the next work
+    # period starts after it.
+    enforcement = \"lock\"
+
+    # Windows that stay usable during a break, like a softphone or an incident
+    # pager, picked by their WM_CLASS.  Each entry matches either part of the
+    # WM_CLASS, ignoring case, so `[\"zoiper\"]` matches a window with the
+    # WM_CLASS \"zoiper\", \"Zoiper\".  You can find a window's WM_CLASS with
+    # `xprop WM_CLASS`.
+    #
+    # These windows are raised above the break screen.  During a locked break,
+    # the keyboard and mouse are let go while the mouse is over one of them and
+    # it has the keyboard focus.  Each change of focus and each time one is
+    # used is logged in `enforcement.log` in the cache directory.
+    allowed_windows = []
+
+    # How many seconds before a break to show a banner counting down to it.
+    # The banner stays on top of other windows, and has buttons to postpone
+    # the break or to start it now.  The warning is also sent as a desktop
+    # notification.  Set to 0 to turn the warning off.
+    warning_before_break_seconds = 60
+
+    # How many seconds the postpone button on the warning banner puts the
+    # break off for.
+    postpone_break_seconds = 300 # 5 minutes
+
+    # How many seconds before a break to start dimming the screens.  The
+    # screens fade to `dim_brightness` by the time the break starts, and are
+    # restored when it starts or is postponed.  Set to 0 (the default) to turn
+    # dimming off.
+    dim_before_break_seconds = 0
+
+    # How bright the screens are at the end of the fade, from 0 (black) to 1
+    # (not dimmed).
+    dim_brightness = 0.5
+
+    # Whether to also wash the colors out while dimming.
+    dim_desaturate = false
+
+    # How breaks are scheduled.
+    #
+    # \"interval\" (the default) starts a break every `seconds_between_breaks`,
+    # or uses the break tiers below.
+    #
+    # \"pomodoro\" alternates work periods with short breaks, and uses a long
+    # break after every `cycles_before_long_break` work periods.  The current
+    # work period is shown in the systray icon tooltip and on the break screen.
+    # The settings for this are in the `[pomodoro]` section below.
+    schedule = \"interval\"
+
+    # How to pick the activity for each break: \"shuffle\" (the default) picks
+    # them in a random order, and \"in-order\" picks them in the order of
+    # the `[[activity]]` entries below.  Either way, an activity isn't picked
+    # again until all the others have been.
+    activity_rotation = \"shuffle\"
+
+    # Instead of a single kind of break, you can have several break tiers, each
+    # with its own cadence.  For example, 20-second eye breaks every 20 minutes,
+    # and 10-minute breaks every hour.  When a break runs, the countdowns of
+    # all the tiers with breaks that are no longer than it start over.
+    #
+    # If any tiers are given, `break_duration_seconds`,
+    # `seconds_between_breaks`, and `clicks_to_end_break_early` above are
+    # ignored.
+    #
+    # `enforcement` works like the setting above.  Each tier has its own, and
+    # it defaults to \"lock\".
+    #
+    # `plugins` is either \"check\" (the default), which lets plugins put the
+    # break off during meetings and video calls, or \"ignore\", which always
+    # starts the break on time.
+    #
+    # [[break_tier]]
+    # name = \"eye break\"
+    # seconds_between_breaks = 1200 # 20 minutes
+    # break_duration_seconds = 20
+    # clicks_to_end_break_early = 20
+    # enforcement = \"overlay\"
+    # plugins = \"ignore\"
+    #
+    # [[break_tier]]
+    # name = \"long break\"
+    # seconds_between_breaks = 3600 # 60 minutes
+    # break_duration_seconds = 600 # 10 minutes
+
+    # Breaks can also happen at fixed times, like lunch.  `at` is either a
+    # time like \"12:30\", for a break every day at that time, or a minute like
+    # \"*:50\", for a break every hour at that minute.  If plugins put a fixed
+    # break off, it is tried again every 5 minutes for an hour.  A short break
+    # that is due shortly before a longer one is skipped.  Fixed breaks
+    # happen in Pomodoro mode too.
+    #
+    # `align_to_clock` and `clock_offset_seconds` can also be given for each
+    # break tier.  `clicks_to_end_break_early`, `enforcement`, and `plugins`
+    # work like they do for break tiers.
+    #
+    # [[fixed_break]]
+    # name = \"lunch\"
+    # at = \"12:30\"
+    # break_duration_seconds = 1800 # 30 minutes
+
+    # Activities to do during breaks, like stretches or eye exercises.  Each
+    # break shows one of them on the break screen: its name, its `text`, an
+    # optional `image`, and its `steps`, one at a time, each for its number of
+    # `seconds`.  `tiers` limits an activity to the breaks of the break tiers
+    # with those names.  Without any activities, the break screen shows no
+    # activity.
+    #
+    # [[activity]]
+    # name = \"Neck stretch\"
+    # text = \"Sit up straight and relax your shoulders.\"
+    # image = \"/home/me/pictures/neck-stretch.png\"
+    # steps = [
+    #   { text = \"Tilt your head to the left\", seconds = 30 },
+    #   { text = \"Tilt your head to the right\", seconds = 30 },
+    # ]
+    #
+    # [[activity]]
+    # name = \"Look into the distance\"
+    # text = \"Look at something at least 6 meters away.\"
+    # tiers = [\"eye break\"]
+
+    [pomodoro]
+    work_seconds = 1500 # 25 minutes
+    short_break_seconds = 300 # 5 minutes
+    long_break_seconds = 900 # 15 minutes
+    cycles_before_long_break = 4
+
+    # If you are idle for this long, the work periods are counted from the
+    # beginning again.  This only works when idle detection is enabled.  Set
+    # to 0 to never count from the beginning again.
+    reset_cycle_after_idle_seconds = 1800 # 30 minutes
+
+    # What you have to do to end a break early.  `kind` is one of:
+    #
+    # - \"press-key\" (the default): press `key` `clicks_to_end_break_early`
+    #   times.
+    # - \"type-sentence\": type `sentence` exactly.  A typo starts the sentence
+    #   over.
+    # - \"arithmetic\": solve `problems` arithmetic problems.  Type the answer
+    #   and press Enter.
+    # - \"hold-key\": hold `key` down for `hold_seconds` without letting go.
+    [challenge]
+    kind = \"press-key\"
+
+    # The key for the \"press-key\" and \"hold-key\" challenges.  This is a GDK
+    # key name, like \"space\", \"Return\", or \"a\".
+    key = \"space\"
+
+    sentence = \"I am choosing to skip my break, even though I need it.\"
+    problems = 3
+    hold_seconds = 10
+
+    # If this is true, each break that is ended early makes the challenge
+    # harder for the rest of the day: more presses, more sentences, more and
+    # harder problems, or a longer hold.
+    increase_difficulty = false
+
     [plugin.google_calendar]
     # A list of strings, one for each Google account you want to authenticate with.
+    # Only the primary calendar of each account is checked.  To check other
+    # calendars, use a table instead of a string:
+    #
+    # { email = \"me@example.com\", calendars = [\"primary\", \"team@group.calendar.google.com\"] }
+    #
+    # `calendars` is a list of calendar IDs.  \"primary\" means the primary
+    # calendar of the account, and \"all\" means every calendar in the
+    # account's calendar list.
     accounts = []
 
+    # How many minutes before and after the start of a break to check for
+    # events.  An event that ended a few minutes ago may have run over, and
+    # there is no point starting a break if a meeting is about to start.
+    # look_behind_minutes = 10
+    # look_ahead_minutes = 20
+
+    # Events are synced in the background into a cache in the cache
+    # directory, and breaks are checked against this cache.  This is how often
+    # (in minutes) to sync.  If the network is down, breaks still happen based
+    # on the last synced events.
+    # sync_interval_minutes = 5
+
+    # The client secret JSON file of your own OAuth client, downloaded from
+    # the Google Cloud Console.  By default, break-time's own client is used.
+    # client_secret_file = \"~/.config/break-time/client_secret.json\"
+
+    # How `break-time google-calendar login` gets the authorization code from
+    # Google.  \"redirect\" opens a web server on localhost for the browser to
+    # be redirected to, \"copy-paste\" has you paste the code shown in the
+    # browser, and \"device\" has you enter a code at google.com/device (this
+    # needs a client_secret_file for a \"TVs and Limited Input devices\"
+    # client).
+    # auth_flow = \"redirect\"
+
+    # Events that shouldn't stop a break from happening.  All of these are
+    # false by default.
+    # ignore_all_day_events = false
+    # ignore_free_events = false # events marked as \"free\" instead of \"busy\"
+    # ignore_working_location_events = false
+    # ignore_focus_time_events = false
+    # ignore_events_without_other_attendees = false
+
+    # Events whose summary matches one of these patterns are ignored.  Case is
+    # ignored, `*` matches any number of characters, and `?` matches a single
+    # character.  For example: [\"*lunch*\", \"commute\"]
+    # ignore_summary_patterns = []
+
+    [plugin.ics_calendar]
+    # A list of paths to .ics files, or directories containing .ics files (for
+    # instance, a vdirsyncer storage directory or a calendar exported from
+    # Thunderbird).  Directories are searched recursively.  Files are re-read
+    # whenever they change on disk.
+    paths = []
+
+    # A list of your own email addresses.  These are used to find you in the
+    # attendee list of an event, so that events you have declined don't stop a
+    # break from happening.
+    emails = []
+
+    [plugin.caldav]
+    # A list of CalDAV accounts (for instance Nextcloud, Fastmail or Radicale).
+    # Each account is a table like the following:
+    #
+    # { url = \"https://cloud.example.com/remote.php/dav\", username = \"me\", emails = [\"me@example.com\"], calendars = [\"Work\"] }
+    #
+    # `url` can be the root of the CalDAV server, or the URL of a single
+    # calendar.  `emails` are your own email addresses, used to ignore events
+    # you have declined.  `calendars` is an optional list of calendar names to
+    # check.  If it is left out, all your calendars are checked.
+    #
+    # Store the password (or app password) for each account with
+    # `break-time caldav login <username>`.  It is kept in the cache directory.
+    accounts = []
+
+    [plugin.microsoft_calendar]
+    # A list of Microsoft 365 / Outlook email addresses whose calendars should
+    # be checked for events.  Sign in to each of them with
+    # `break-time microsoft-calendar login <email>`.  The OAuth token is then
+    # stored in the cache directory.
+    accounts = []
+
+    # The application (client) ID of an app registered in Azure AD (Microsoft
+    # Entra ID) with the device code flow (\"Allow public client flows\")
+    # enabled and the Calendars.Read permission.  This must be set in order to
+    # use the accounts above.
+    client_id = \"\"
+
+    # The tenant to sign in to.  This can be \"common\", \"organizations\", or
+    # the ID of your organization's tenant.
+    # tenant = \"common\"
+
+    # The Microsoft Graph and login URLs.  These only need to be changed for
+    # national clouds, or for testing against a local mock server.
+    # graph_url = \"https://graph.microsoft.com/v1.0\"
+    # login_url = \"https://login.microsoftonline.com\"
+
     [plugin.x11_window_title_checker]
+
+    # Profiles are named sets of settings that override the settings above,
+    # like one for deep-work days and one for days full of meetings.  A profile
+    # can override any setting, including plugin settings.  Tables are merged
+    # setting by setting, while lists like `[[break_tier]]` are replaced.  The
+    # settings without any overrides are the \"default\" profile.
+    #
+    # Switch profiles from the systray icon menu, or with
+    # `break-time profile use <name>`.  break-time switches to the settings of
+    # the new profile without restarting, and the active profile is remembered
+    # across restarts.
+    #
+    # [profile.deep-work]
+    # seconds_between_breaks = 5400 # 90 minutes
+    # break_duration_seconds = 900 # 15 minutes
+    #
+    # [profile.meetings]
+    # seconds_between_breaks = 1800 # 30 minutes
+    # break_duration_seconds = 300 # 5 minutes
+    #
+    # [profile.meetings.plugin.google_calendar]
+    # accounts = [\"me@work.example.com\"]
+
+    # Profile rules switch profiles automatically.  `from` and `to` are times
+    # of day, and `monitors` is the exact list of X monitors that must be
+    # connected, as shown by `xrandr`.  All the given conditions must match,
+    # and the first matching rule wins.  The rules only switch profiles when
+    # the profile they pick changes, so a profile picked by hand stays active
+    # until then.
+    #
+    # [[profile_rule]]
+    # profile = \"meetings\"
+    # from = \"13:00\"
+    # to = \"17:00\"
+    # monitors = [\"eDP-1\", \"DP-1\"]
     "
 );
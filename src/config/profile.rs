@@ -0,0 +1,272 @@
+// Settings profiles let the user keep several sets of settings in one config
+// file, like one for deep-work days and one for days full of meetings.  Each
+// `[profile.<name>]` table overrides any of the settings, and
+// `[[profile_rule]]` entries switch between profiles automatically.
+//
+// The active profile is kept in the cache directory, so that it is still
+// active after break-time restarts.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The name of the profile that uses the settings from the config file
+/// without any overrides.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// A rule for switching to a profile automatically.  A rule matches when all
+/// of its conditions match.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ProfileRule {
+    pub profile: String,
+    /// The rule matches from this time of day.
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub from: Option<NaiveTime>,
+    /// The rule matches until this time of day.  If this is before `from`,
+    /// the rule matches overnight.
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub to: Option<NaiveTime>,
+    /// The rule matches when exactly these X monitors are connected, like
+    /// `["eDP-1", "DP-1"]`.
+    pub monitors: Option<Vec<String>>,
+}
+
+fn deserialize_time<'de, D>(
+    deserializer: D,
+) -> Result<Option<NaiveTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let opt_time: Option<String> = Option::deserialize(deserializer)?;
+    opt_time
+        .map(|time| {
+            NaiveTime::parse_from_str(&time, "%H:%M").map_err(|err| {
+                serde::de::Error::custom(format!(
+                    "could not parse {:?} as a time like \"09:30\": {}",
+                    time, err
+                ))
+            })
+        })
+        .transpose()
+}
+
+impl ProfileRule {
+    /// Whether this rule matches at time of day `now`, with the X monitors
+    /// called `monitors` connected.
+    pub fn matches(&self, now: NaiveTime, monitors: &[String]) -> bool {
+        let time_matches = match (self.from, self.to) {
+            (None, None) => true,
+            (Some(from), None) => now >= from,
+            (None, Some(to)) => now < to,
+            (Some(from), Some(to)) if from <= to => from <= now && now < to,
+            (Some(from), Some(to)) => now >= from || now < to,
+        };
+        let monitors_match = self.monitors.as_ref().map_or(true, |expected| {
+            let mut expected = expected.clone();
+            let mut monitors = monitors.to_vec();
+            expected.sort();
+            monitors.sort();
+            expected == monitors
+        });
+        time_matches && monitors_match
+    }
+
+    pub const fn needs_monitors(&self) -> bool {
+        self.monitors.is_some()
+    }
+}
+
+/// The profile of the first rule in `rules` that matches, if any.
+pub fn matching_profile<'a>(
+    rules: &'a [ProfileRule],
+    now: NaiveTime,
+    monitors: &[String],
+) -> Option<&'a str> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(now, monitors))
+        .map(|rule| rule.profile.as_str())
+}
+
+/// The profiles and profile rules from a config file.
+#[derive(Clone, Debug, Default)]
+pub struct Profiles {
+    pub tables: BTreeMap<String, toml::value::Table>,
+    pub rules: Vec<ProfileRule>,
+}
+
+impl Profiles {
+    /// Take the `profile` and `profile_rule` entries out of the top-level
+    /// table of a config file.
+    pub fn take_from(
+        config_table: &mut toml::value::Table,
+    ) -> Result<Self, String> {
+        let tables = match config_table.remove("profile") {
+            None => BTreeMap::new(),
+            Some(value) => value
+                .try_into()
+                .map_err(|err| format!("bad [profile] table: {}", err))?,
+        };
+        let rules = match config_table.remove("profile_rule") {
+            None => vec![],
+            Some(value) => value
+                .try_into()
+                .map_err(|err| format!("bad [[profile_rule]]: {}", err))?,
+        };
+        let profiles = Self { tables, rules };
+        for rule in &profiles.rules {
+            if !profiles.exists(&rule.profile) {
+                return Err(format!(
+                    "[[profile_rule]] refers to a profile that doesn't exist: {:?}",
+                    rule.profile
+                ));
+            }
+        }
+        Ok(profiles)
+    }
+
+    /// The names of all the profiles, starting with the default profile.
+    pub fn names(&self) -> Vec<String> {
+        std::iter::once(String::from(DEFAULT_PROFILE))
+            .chain(self.tables.keys().cloned())
+            .collect()
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        name == DEFAULT_PROFILE || self.tables.contains_key(name)
+    }
+
+    /// Override the settings in `config_table` with the ones from the
+    /// profile called `name`.
+    pub fn apply(&self, name: &str, config_table: &mut toml::value::Table) {
+        if let Some(profile_table) = self.tables.get(name) {
+            merge_tables(config_table, profile_table);
+        }
+    }
+}
+
+/// Override the values in `base` with the ones in `overrides`.  Tables are
+/// merged key by key, and everything else, including arrays, is replaced.
+fn merge_tables(base: &mut toml::value::Table, overrides: &toml::value::Table) {
+    for (key, override_value) in overrides {
+        match (base.get_mut(key), override_value) {
+            (
+                Some(toml::Value::Table(base_table)),
+                toml::Value::Table(override_table),
+            ) => merge_tables(base_table, override_table),
+            _ => {
+                base.insert(key.clone(), override_value.clone());
+            }
+        }
+    }
+}
+
+/// Which profile is active, as saved in the cache directory.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ProfileState {
+    /// The active profile.  `None` until a profile has been picked.
+    pub active: Option<String>,
+    /// The profile that the profile rules picked the last time they were
+    /// checked.  The rules only switch profiles when this changes, so that a
+    /// profile picked by hand isn't switched away from straight away.
+    pub last_rule_profile: Option<String>,
+}
+
+fn state_path(cache_dir: &Path) -> PathBuf {
+    cache_dir.join("profile.toml")
+}
+
+impl ProfileState {
+    pub fn load(cache_dir: &Path) -> Self {
+        std::fs::read_to_string(state_path(cache_dir))
+            .ok()
+            .and_then(|state_str| toml::from_str(&state_str).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> Result<(), String> {
+        let state_str = toml::to_string(self).map_err(|err| err.to_string())?;
+        std::fs::write(state_path(cache_dir), state_str)
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        let mut config_table: toml::value::Table =
+            toml::from_str(indoc::indoc!(
+                r#"
+            seconds_between_breaks = 3000
+
+            [plugin.google_calendar]
+            accounts = ["me@example.com"]
+            auth_flow = "redirect"
+
+            [profile.meetings]
+            seconds_between_breaks = 1800
+
+            [profile.meetings.plugin.google_calendar]
+            accounts = ["work@example.com"]
+
+            [[profile_rule]]
+            profile = "meetings"
+            from = "09:00"
+            to = "17:00"
+            "#
+            ))
+            .unwrap();
+
+        let profiles = Profiles::take_from(&mut config_table).unwrap();
+        assert_eq!(profiles.names(), vec!["default", "meetings"]);
+        assert!(!config_table.contains_key("profile"));
+
+        profiles.apply("meetings", &mut config_table);
+        assert_eq!(
+            config_table["seconds_between_breaks"],
+            toml::Value::Integer(1800)
+        );
+        let google_calendar = &config_table["plugin"]["google_calendar"];
+        assert_eq!(
+            google_calendar["accounts"],
+            toml::Value::Array(vec![toml::Value::from("work@example.com")])
+        );
+        assert_eq!(google_calendar["auth_flow"], toml::Value::from("redirect"));
+
+        let at = |hour| NaiveTime::from_hms(hour, 0, 0);
+        assert_eq!(
+            matching_profile(&profiles.rules, at(10), &[]),
+            Some("meetings")
+        );
+        assert_eq!(matching_profile(&profiles.rules, at(18), &[]), None);
+    }
+
+    #[test]
+    fn test_profile_rule_matches() {
+        let monitors = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| String::from(*name))
+                .collect::<Vec<_>>()
+        };
+        let rule = ProfileRule {
+            profile: String::from("night"),
+            from: Some(NaiveTime::from_hms(22, 0, 0)),
+            to: Some(NaiveTime::from_hms(6, 0, 0)),
+            monitors: Some(monitors(&["eDP-1", "DP-1"])),
+        };
+        let docked = monitors(&["DP-1", "eDP-1"]);
+
+        assert!(rule.matches(NaiveTime::from_hms(23, 0, 0), &docked));
+        assert!(rule.matches(NaiveTime::from_hms(5, 0, 0), &docked));
+        assert!(!rule.matches(NaiveTime::from_hms(12, 0, 0), &docked));
+        assert!(
+            !rule.matches(NaiveTime::from_hms(23, 0, 0), &monitors(&["eDP-1"]))
+        );
+    }
+}
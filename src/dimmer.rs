@@ -0,0 +1,399 @@
+// This module dims the screens over the last seconds before a break, by
+// fading the gamma ramps of the monitors through RandR.  It can also wash
+// the colors out while dimming.
+//
+// The original gamma ramps are restored when the break starts or is put off.
+// They are also saved to the cache directory while the screens are dimmed,
+// so that they can be restored on the next start if break-time dies before
+// it restores them itself.
+
+use glib::source::Continue;
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::x11::X11;
+
+/// How far the colors are pulled towards grey at the end of the fade, when
+/// desaturating.
+const MAX_DESATURATION: f64 = 0.5;
+
+/// How often the gamma ramps are updated while fading.
+const FADE_STEP_MILLISECONDS: u32 = 200;
+
+/// The gamma ramps of a CRTC, which drives a monitor.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Ramps {
+    crtc: xcb::randr::Crtc,
+    red: Vec<u16>,
+    green: Vec<u16>,
+    blue: Vec<u16>,
+}
+
+/// The original gamma ramps, as saved in the cache directory.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SavedRamps {
+    crtcs: Vec<Ramps>,
+}
+
+/// `ramp`, `amount` of the way through the fade.  At the end of the fade,
+/// the ramp is scaled down to `brightness`, and pulled
+/// `MAX_DESATURATION` of the way towards grey if `desaturate` is set.
+/// Pulling every channel towards the same grey shrinks the differences
+/// between them, which is what washes the colors out.
+fn fade_ramp(
+    ramp: &[u16],
+    amount: f64,
+    brightness: f64,
+    desaturate: bool,
+) -> Vec<u16> {
+    let grey = f64::from(u16::MAX) / 2.0;
+    let desaturation = if desaturate {
+        amount * MAX_DESATURATION
+    } else {
+        0.0
+    };
+    let scale = amount.mul_add(brightness - 1.0, 1.0);
+    ramp.iter()
+        .map(|&value| {
+            let value = f64::from(value);
+            let faded = (grey - value).mul_add(desaturation, value) * scale;
+            // The value is clamped to the range of a u16 first.
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let faded = faded.round().clamp(0.0, f64::from(u16::MAX)) as u16;
+            faded
+        })
+        .collect()
+}
+
+/// How far through the fade the screens should be, from 0 to 1, when the
+/// break is `remaining` away and the fade takes `lead_time`.
+fn fade_amount(remaining: Duration, lead_time: Duration) -> f64 {
+    (1.0 - remaining.as_secs_f64() / lead_time.as_secs_f64()).clamp(0.0, 1.0)
+}
+
+/// How long to wait before starting the fade, when the break is `remaining`
+/// away and the fade takes `lead_time`.
+fn fade_delay(remaining: Duration, lead_time: Duration) -> Duration {
+    remaining
+        .checked_sub(lead_time)
+        .unwrap_or_else(|| Duration::from_secs(0))
+}
+
+struct Gamma {
+    x11: X11,
+    saved_path: PathBuf,
+    /// The original gamma ramps, while the screens are dimmed.
+    original: RefCell<Option<Vec<Ramps>>>,
+}
+
+impl Gamma {
+    fn crtcs(&self) -> Vec<xcb::randr::Crtc> {
+        self.x11
+            .get_root_win()
+            .and_then(|root_win| {
+                xcb::randr::get_screen_resources_current(
+                    &self.x11.conn,
+                    root_win,
+                )
+                .get_reply()
+                .ok()
+            })
+            .map(|resources| resources.crtcs().to_vec())
+            .unwrap_or_default()
+    }
+
+    fn get_ramps(&self) -> Vec<Ramps> {
+        self.crtcs()
+            .into_iter()
+            .filter_map(|crtc| {
+                let reply = xcb::randr::get_crtc_gamma(&self.x11.conn, crtc)
+                    .get_reply()
+                    .ok()?;
+                Some(Ramps {
+                    crtc,
+                    red: reply.red().to_vec(),
+                    green: reply.green().to_vec(),
+                    blue: reply.blue().to_vec(),
+                })
+            })
+            .filter(|ramps| !ramps.red.is_empty())
+            .collect()
+    }
+
+    fn set_ramps(&self, all_ramps: &[Ramps]) {
+        for ramps in all_ramps {
+            let res = xcb::randr::set_crtc_gamma_checked(
+                &self.x11.conn,
+                ramps.crtc,
+                &ramps.red,
+                &ramps.green,
+                &ramps.blue,
+            )
+            .request_check();
+            if let Err(err) = res {
+                println!(
+                    "Could not set the gamma ramps of CRTC {}: {:?}",
+                    ramps.crtc, err
+                );
+            }
+        }
+    }
+
+    /// Fade the screens `amount` of the way, from 0 to 1.
+    fn fade(&self, amount: f64, brightness: f64, desaturate: bool) {
+        let mut original = self.original.borrow_mut();
+        if original.is_none() {
+            let ramps = self.get_ramps();
+            self.save(&ramps);
+            *original = Some(ramps);
+        }
+        if let Some(original) = original.as_ref() {
+            let faded: Vec<Ramps> = original
+                .iter()
+                .map(|ramps| Ramps {
+                    crtc: ramps.crtc,
+                    red: fade_ramp(&ramps.red, amount, brightness, desaturate),
+                    green: fade_ramp(
+                        &ramps.green,
+                        amount,
+                        brightness,
+                        desaturate,
+                    ),
+                    blue: fade_ramp(
+                        &ramps.blue,
+                        amount,
+                        brightness,
+                        desaturate,
+                    ),
+                })
+                .collect();
+            self.set_ramps(&faded);
+        }
+    }
+
+    /// Put the original gamma ramps back, if the screens are dimmed.
+    fn restore(&self) {
+        if let Some(original) = self.original.replace(None) {
+            self.set_ramps(&original);
+            self.remove_saved();
+        }
+    }
+
+    /// Put back the gamma ramps saved by a break-time that died while the
+    /// screens were dimmed.
+    fn restore_saved(&self) {
+        let saved: SavedRamps = match std::fs::read_to_string(&self.saved_path)
+        {
+            Err(_) => return,
+            Ok(saved_str) => match toml::from_str(&saved_str) {
+                Err(err) => {
+                    println!(
+                        "Could not read the saved gamma ramps from {:?}: {}",
+                        self.saved_path, err
+                    );
+                    self.remove_saved();
+                    return;
+                }
+                Ok(saved) => saved,
+            },
+        };
+        println!(
+            "The screens were left dimmed last time, restoring the gamma ramps from {:?}",
+            self.saved_path
+        );
+        self.set_ramps(&saved.crtcs);
+        self.remove_saved();
+    }
+
+    fn save(&self, ramps: &[Ramps]) {
+        let saved = SavedRamps {
+            crtcs: ramps.to_vec(),
+        };
+        let res = toml::to_string(&saved)
+            .map_err(|err| err.to_string())
+            .and_then(|saved_str| {
+                std::fs::write(&self.saved_path, saved_str)
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = res {
+            println!(
+                "Could not save the gamma ramps to {:?}: {}",
+                self.saved_path, err
+            );
+        }
+    }
+
+    fn remove_saved(&self) {
+        if let Err(err) = std::fs::remove_file(&self.saved_path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                println!("Could not remove {:?}: {}", self.saved_path, err);
+            }
+        }
+    }
+}
+
+pub struct Dimmer {
+    lead_time: Duration,
+    brightness: f64,
+    desaturate: bool,
+    gamma: Rc<Gamma>,
+    /// When the next break starts, while waiting to dim the screens or
+    /// dimming them.
+    break_time: Rc<Cell<Option<Instant>>>,
+    /// The timeout that starts the fade, or the one that steps it once it
+    /// has started.  There is only ever one of them.
+    source: Rc<RefCell<Option<glib::SourceId>>>,
+}
+
+impl Dimmer {
+    /// Create the dimmer, and restore the gamma ramps if break-time died
+    /// while the screens were dimmed.
+    pub fn new(config: &Config) -> Self {
+        let gamma = Gamma {
+            x11: X11::connect(),
+            saved_path: config.cache_dir.join("gamma.toml"),
+            original: RefCell::new(None),
+        };
+        gamma.restore_saved();
+        Self {
+            lead_time: Duration::from_secs(
+                config.settings.dim_before_break_seconds.into(),
+            ),
+            brightness: config.settings.dim_brightness.clamp(0.0, 1.0),
+            desaturate: config.settings.dim_desaturate,
+            gamma: Rc::new(gamma),
+            break_time: Rc::new(Cell::new(None)),
+            source: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Use the dimming settings from `config`, after switching profiles.
+    pub fn reconfigure(&mut self, config: &Config) {
+        self.lead_time = Duration::from_secs(
+            config.settings.dim_before_break_seconds.into(),
+        );
+        self.brightness = config.settings.dim_brightness.clamp(0.0, 1.0);
+        self.desaturate = config.settings.dim_desaturate;
+    }
+
+    /// Dim the screens over the dimming time before the next break, which
+    /// is `remaining` away.  The fade is timed from when the break is due,
+    /// so it starts on time even though this is only called once a minute.
+    pub fn update(&self, remaining: Duration) {
+        if self.lead_time == Duration::from_secs(0)
+            || remaining == Duration::from_secs(0)
+        {
+            self.restore();
+            return;
+        }
+
+        // If the break has been pushed back since the fade started, undo the
+        // fade until it is time to start it again.
+        if remaining > self.lead_time {
+            self.gamma.restore();
+        }
+        self.break_time.set(Some(Instant::now() + remaining));
+        self.start_timeout(remaining);
+    }
+
+    /// Replace the running timeout with one that starts the fade at the
+    /// dimming time before the break, or with the fade itself if that time
+    /// has already come.
+    fn start_timeout(&self, remaining: Duration) {
+        self.stop_timeout();
+
+        let lead_time = self.lead_time;
+        let brightness = self.brightness;
+        let desaturate = self.desaturate;
+        let gamma = Rc::clone(&self.gamma);
+        let break_time = Rc::clone(&self.break_time);
+        let fade_step = move || {
+            if let Some(break_time) = break_time.get() {
+                let remaining =
+                    break_time.saturating_duration_since(Instant::now());
+                gamma.fade(
+                    fade_amount(remaining, lead_time),
+                    brightness,
+                    desaturate,
+                );
+            }
+            Continue(true)
+        };
+
+        let delay = fade_delay(remaining, lead_time);
+        let source_id = if delay == Duration::from_secs(0) {
+            glib::timeout_add_local(FADE_STEP_MILLISECONDS, fade_step)
+        } else {
+            let source = Rc::clone(&self.source);
+            let delay_ms = u32::try_from(delay.as_millis()).unwrap_or(u32::MAX);
+            glib::timeout_add_local(delay_ms, move || {
+                // This timeout is done after returning, so its id is replaced
+                // without removing it.
+                *source.borrow_mut() = Some(glib::timeout_add_local(
+                    FADE_STEP_MILLISECONDS,
+                    fade_step.clone(),
+                ));
+                Continue(false)
+            })
+        };
+        *self.source.borrow_mut() = Some(source_id);
+    }
+
+    fn stop_timeout(&self) {
+        if let Some(source_id) = self.source.borrow_mut().take() {
+            glib::source_remove(source_id);
+        }
+    }
+
+    /// Stop dimming, and put the original gamma ramps back.
+    pub fn restore(&self) {
+        self.stop_timeout();
+        self.break_time.set(None);
+        self.gamma.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fade_ramp() {
+        let ramp = vec![0, 32768, 65535];
+        assert_eq!(fade_ramp(&ramp, 0.0, 0.5, true), ramp);
+        assert_eq!(fade_ramp(&ramp, 1.0, 0.5, false), vec![0, 16384, 32768]);
+        assert_eq!(fade_ramp(&ramp, 1.0, 1.0, true), vec![16384, 32768, 49151]);
+    }
+
+    #[test]
+    fn test_fade_timing() {
+        let lead_time = Duration::from_secs(90);
+
+        // A minute-long tick that lands before the dimming time waits for
+        // it, instead of starting partway through the fade.
+        assert_eq!(
+            fade_delay(Duration::from_secs(120), lead_time),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            fade_delay(Duration::from_secs(60), lead_time),
+            Duration::from_secs(0)
+        );
+
+        assert!(fade_amount(Duration::from_secs(120), lead_time).abs() < 1e-9);
+        assert!(fade_amount(Duration::from_secs(90), lead_time).abs() < 1e-9);
+        assert!(
+            (fade_amount(Duration::from_secs(45), lead_time) - 0.5).abs()
+                < 1e-9
+        );
+        assert!(
+            (fade_amount(Duration::from_secs(0), lead_time) - 1.0).abs() < 1e-9
+        );
+    }
+}
@@ -9,104 +9,359 @@
 )]
 
 mod config;
+mod dimmer;
+mod notification;
 mod opts;
 mod prelude;
+mod profile_switcher;
 mod scheduler;
 mod tray;
 pub mod ui;
 mod x11;
 
 use std::sync::mpsc::Sender;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use config::Config;
+use dimmer::Dimmer;
+use notification::Notifier;
+use profile_switcher::ProfileSwitcher;
 use scheduler::Scheduler;
 use tray::Tray;
+use ui::challenge::EarlyExits;
+use ui::warning::Warning;
+use x11::X11;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum Msg {
     EndBreak,
+    /// The break was ended early, by doing the challenge.  This is sent
+    /// before `EndBreak`.
+    BreakEndedEarly,
     Pause,
     Quit,
     ResetSysTrayIcon,
     Resume,
-    StartBreak,
-    TimeRemainingBeforeBreak(Duration),
+    /// Start a break.  Its duration is usually the configured
+    /// `break_duration_seconds`, but can be shorter when the break has to fit
+    /// between meetings, or longer to make up for breaks that were put off.
+    StartBreak(Box<scheduler::BreakInfo>),
+    /// The time remaining before the next break, and the name of the break
+    /// tier it is from.
+    TimeRemainingBeforeBreak(Duration, String),
     EnableIdleDetector,
     DisableIdleDetector,
+    /// Problems with the plugins, like a plugin that couldn't be initialized
+    /// or a calendar account that can't be reached.
+    PluginProblems(Vec<String>),
+    /// The work period the user is in, and the number of work periods before
+    /// a long break.  Only sent in Pomodoro mode.
+    PomodoroCycle(u32, u32),
+    /// Switch to the settings profile with this name.
+    SwitchProfile(String),
+    /// Check whether the settings profile should be switched, because of
+    /// `break-time profile use` or the profile rules.
+    CheckProfile,
+    /// Put the next break off for this long, from the warning banner.
+    PostponeBreak(Duration),
+    /// Start the next break now, from the warning banner.
+    StartBreakNow,
+    /// The user pressed a key or moved the mouse after a break, while
+    /// waiting for them to come back.
+    UserReturned,
 }
 
-fn handle_msg_recv(
-    config: &Config,
+/// Everything on the GTK thread that the messages in `Msg` act on.
+struct App {
     sender: glib::Sender<Msg>,
-    scheduler_outer_sender: &Sender<scheduler::Msg>,
-    scheduler_inner_sender: &Sender<scheduler::InnerMsg>,
-    tray: &mut Tray,
-    msg: Msg,
-) {
+    scheduler_outer_sender: Sender<scheduler::Msg>,
+    scheduler_inner_sender: Sender<scheduler::InnerMsg>,
+    tray: Tray,
+    profile_switcher: ProfileSwitcher,
+    early_exits: EarlyExits,
+    warning: Warning,
+    notifier: Notifier,
+    dimmer: Dimmer,
+    config: Config,
+    user_wait: UserWait,
+}
+
+/// Waiting for the user to come back after a break, before starting the next
+/// work period.  The scheduler is stopped while waiting, the same as after
+/// `Msg::Pause`, so it isn't sent anything until the wait is over.
+#[derive(Default)]
+struct UserWait {
+    is_waiting: bool,
+    /// The poll for keyboard or mouse input, while waiting.
+    poll: Option<glib::SourceId>,
+}
+
+impl UserWait {
+    fn start(&mut self, poll: glib::SourceId) {
+        self.stop();
+        self.is_waiting = true;
+        self.poll = Some(poll);
+    }
+
+    /// Stop waiting and polling.  Returns whether it was waiting.
+    fn stop(&mut self) -> bool {
+        if let Some(poll) = self.poll.take() {
+            glib::source_remove(poll);
+        }
+        std::mem::replace(&mut self.is_waiting, false)
+    }
+
+    /// Pause the scheduler.  When waiting for the user, the scheduler is
+    /// already stopped, and a `InnerMsg::Pause` would only be read once it
+    /// starts again after `Msg::Resume`, pausing it right away.  Returns
+    /// whether it was waiting.
+    fn pause(
+        &mut self,
+        scheduler_inner_sender: &Sender<scheduler::InnerMsg>,
+    ) -> bool {
+        let was_waiting = self.stop();
+        if !was_waiting {
+            scheduler_inner_sender.send(scheduler::InnerMsg::Pause).expect(
+                "TODO: figure out what to do about channels potentially failing",
+            );
+        }
+        was_waiting
+    }
+
+    /// The user came back.  Start the next work period, if still waiting for
+    /// it.  Returns whether it was waiting.
+    fn user_returned(
+        &mut self,
+        scheduler_outer_sender: &Sender<scheduler::Msg>,
+    ) -> bool {
+        let was_waiting = self.stop();
+        if was_waiting {
+            scheduler_outer_sender.send(scheduler::Msg::Start).expect(
+                "TODO: figure out what to do about channels potentially failing",
+            );
+        }
+        was_waiting
+    }
+}
+
+fn handle_msg_recv(app: &mut App, msg: Msg) {
     match msg {
         Msg::EndBreak => {
-            println!("break ended");
-            tray.break_end();
-            scheduler_outer_sender.send(scheduler::Msg::Start).expect("TODO: figure out what to do about channels potentially failing");
+            end_break(app);
+        }
+        Msg::BreakEndedEarly => {
+            app.early_exits.record();
         }
         Msg::Pause => {
-            tray.pause();
-            scheduler_inner_sender.send(scheduler::InnerMsg::Pause).expect("TODO: figure out what to do about channels potentially failing");
+            app.warning.hide();
+            app.dimmer.restore();
+            if app.user_wait.pause(&app.scheduler_inner_sender) {
+                app.tray.stop_waiting_for_user();
+            }
+            app.tray.pause();
         }
         Msg::Quit => {
+            app.dimmer.restore();
             gtk::main_quit();
         }
-        Msg::StartBreak => {
+        Msg::StartBreak(break_info) => {
             println!("starting break");
-            tray.render_break_starting();
-            ui::start_break(config, sender);
+            app.warning.hide();
+            app.dimmer.restore();
+            app.tray.render_break_starting();
+            app.profile_switcher.break_started();
+            let difficulty = if break_info.challenge.increase_difficulty {
+                app.early_exits.today()
+            } else {
+                0
+            };
+            ui::start_break(
+                app.sender.clone(),
+                &break_info,
+                difficulty,
+                &app.config,
+                &app.notifier,
+            );
         }
         Msg::ResetSysTrayIcon => {
-            tray.render_normal_icon();
+            app.tray.render_normal_icon();
         }
         Msg::Resume => {
-            tray.resume();
-            scheduler_outer_sender.send(scheduler::Msg::Start).expect("TODO: figure out what to do about channels potentially failing");
+            app.tray.resume();
+            app.scheduler_outer_sender.send(scheduler::Msg::Start).expect("TODO: figure out what to do about channels potentially failing");
         }
-        Msg::TimeRemainingBeforeBreak(remaining_time) => {
-            tray.update_time_remaining(remaining_time);
+        Msg::TimeRemainingBeforeBreak(remaining_time, tier_name) => {
+            app.tray.update_time_remaining(remaining_time, &tier_name);
+            app.warning.update(remaining_time, &tier_name);
+            app.dimmer.update(remaining_time);
         }
         Msg::EnableIdleDetector => {
-            tray.set_is_idle_detector_enabled(tray::IsIdleDetectorEnabled::Yes);
-            scheduler_inner_sender.send(scheduler::InnerMsg::EnableIdleDetector).expect("TODO: figure out what to do about channels potentially failing");
+            app.tray
+                .set_is_idle_detector_enabled(tray::IsIdleDetectorEnabled::Yes);
+            app.scheduler_inner_sender.send(scheduler::InnerMsg::EnableIdleDetector).expect("TODO: figure out what to do about channels potentially failing");
         }
         Msg::DisableIdleDetector => {
-            tray.set_is_idle_detector_enabled(tray::IsIdleDetectorEnabled::No);
-            scheduler_inner_sender.send(scheduler::InnerMsg::DisableIdleDetector).expect("TODO: figure out what to do about channels potentially failing");
+            app.tray
+                .set_is_idle_detector_enabled(tray::IsIdleDetectorEnabled::No);
+            app.scheduler_inner_sender.send(scheduler::InnerMsg::DisableIdleDetector).expect("TODO: figure out what to do about channels potentially failing");
+        }
+        Msg::PluginProblems(problems) => {
+            app.tray.set_problems(problems);
         }
+        Msg::PomodoroCycle(cycle, cycles_before_long_break) => {
+            app.tray.set_pomodoro_cycle(cycle, cycles_before_long_break);
+        }
+        Msg::SwitchProfile(name) => {
+            switch_profile(app, &name);
+        }
+        Msg::CheckProfile => {
+            if let Some(name) = app.profile_switcher.check() {
+                switch_profile(app, &name);
+            }
+        }
+        Msg::PostponeBreak(postpone) => {
+            app.warning.hide();
+            app.dimmer.restore();
+            app.scheduler_inner_sender.send(scheduler::InnerMsg::Postpone(postpone)).expect("TODO: figure out what to do about channels potentially failing");
+        }
+        Msg::StartBreakNow => {
+            app.warning.hide();
+            app.dimmer.restore();
+            app.scheduler_inner_sender.send(scheduler::InnerMsg::StartNow).expect("TODO: figure out what to do about channels potentially failing");
+        }
+        Msg::UserReturned => {
+            if app.user_wait.user_returned(&app.scheduler_outer_sender) {
+                println!("the user came back, starting the next work period");
+                app.tray.stop_waiting_for_user();
+            }
+        }
+    }
+}
+
+/// Start the next work period after a break, or wait for the user to come
+/// back first.
+fn end_break(app: &mut App) {
+    println!("break ended");
+    app.tray.break_end();
+    if let Some(name) = app.profile_switcher.break_ended() {
+        switch_profile(app, &name);
+    }
+    if app.config.settings.wait_for_input_after_break {
+        println!("waiting for the user to come back");
+        app.tray.wait_for_user();
+        app.user_wait.start(wait_for_user(app.sender.clone()));
+    } else {
+        app.scheduler_outer_sender
+            .send(scheduler::Msg::Start)
+            .expect(
+            "TODO: figure out what to do about channels potentially failing",
+        );
     }
 }
 
-pub fn run(config: Config) {
+/// Switch to the profile called `name`, and start using its settings unless
+/// that has to wait until the break is over.
+fn switch_profile(app: &mut App, name: &str) {
+    let config = match app
+        .profile_switcher
+        .switch_to(name)
+        .and_then(|name| app.config.with_profile(&name))
+    {
+        None => return,
+        Some(config) => config,
+    };
+    println!("Switching to the profile {:?}", name);
+    app.warning.hide();
+    app.warning.reconfigure(&config);
+    app.dimmer.restore();
+    app.dimmer.reconfigure(&config);
+    app.tray.set_active_profile(name);
+    app.scheduler_inner_sender
+        .send(scheduler::InnerMsg::Reconfigure(Box::new(config.clone())))
+        .expect(
+            "TODO: figure out what to do about channels potentially failing",
+        );
+    let idle_detector_msg = if config.settings.idle_detection_enabled {
+        Msg::EnableIdleDetector
+    } else {
+        Msg::DisableIdleDetector
+    };
+    app.config = config;
+    handle_msg_recv(app, idle_detector_msg);
+}
+
+/// Poll the X server every second until there is keyboard or mouse input,
+/// and then send `Msg::UserReturned`.  It keeps polling until the returned
+/// source is removed.
+fn wait_for_user(sender: glib::Sender<Msg>) -> glib::SourceId {
+    let x11 = X11::connect();
+    let break_end = Instant::now();
+    glib::timeout_add_seconds_local(1, move || {
+        let has_returned =
+            x11.ms_since_user_input()
+                .map_or(false, |ms_since_user_input| {
+                    Duration::from_millis(ms_since_user_input.into())
+                        < break_end.elapsed()
+                });
+        if has_returned {
+            sender.send(Msg::UserReturned).expect(
+                "TODO: figure out what to do about channels potentially failing",
+            );
+        }
+        glib::source::Continue(true)
+    })
+}
+
+/// How often to check whether the settings profile should be switched.
+const PROFILE_CHECK_SECONDS: u32 = 60;
+
+pub fn run(config: &Config) {
+    let mut profile_switcher = ProfileSwitcher::new(config);
+    let early_exits = EarlyExits::load(&config.cache_dir);
+    let switched_config = profile_switcher
+        .check()
+        .and_then(|name| profile_switcher.switch_to(&name))
+        .and_then(|name| config.with_profile(&name));
+    let config = switched_config.as_ref().unwrap_or(config);
+
     gtk::init().expect("Could not initialize GTK");
 
     let (sender, receiver) =
         glib::MainContext::channel(glib::source::PRIORITY_DEFAULT);
 
-    let mut tray = tray::Tray::run(&config, sender.clone());
+    let tray = tray::Tray::run(config, sender.clone());
 
     println!("Starting the scheduler...");
     let (scheduler_outer_sender, scheduler_inner_sender) =
-        Scheduler::run(&config, sender.clone());
+        Scheduler::run(config, sender.clone());
 
-    receiver.attach(None, move |msg| {
-        handle_msg_recv(
-            &config,
-            sender.clone(),
-            &scheduler_outer_sender,
-            &scheduler_inner_sender,
-            &mut tray,
-            msg,
+    let sender_clone = sender.clone();
+    glib::timeout_add_seconds_local(PROFILE_CHECK_SECONDS, move || {
+        sender_clone.send(Msg::CheckProfile).expect(
+            "TODO: figure out what to do about channels potentially failing",
         );
         glib::source::Continue(true)
     });
 
+    let notifier = Notifier::new();
+    let mut app = App {
+        warning: Warning::new(config, sender.clone(), notifier.clone()),
+        dimmer: Dimmer::new(config),
+        sender,
+        scheduler_outer_sender,
+        scheduler_inner_sender,
+        tray,
+        profile_switcher,
+        early_exits,
+        config: config.clone(),
+        user_wait: UserWait::default(),
+        notifier,
+    };
+    receiver.attach(None, move |msg| {
+        handle_msg_recv(&mut app, msg);
+        glib::source::Continue(true)
+    });
+
     gtk::main();
 }
 
@@ -115,14 +370,106 @@ pub fn run_google_calendar_command(
     google_calendar_command: opts::GoogleCalendar,
 ) {
     match google_calendar_command {
-        opts::GoogleCalendar::ListEvents => {
-            scheduler::plugins::google_calendar::list_events(&config);
+        opts::GoogleCalendar::ListEvents(opts::ListEvents {
+            from,
+            to,
+            json,
+        }) => {
+            scheduler::plugins::google_calendar::list_events(
+                &config, from, to, json,
+            );
         }
         opts::GoogleCalendar::IgnoreEvent(opts::IgnoreEvent { event_id }) => {
             scheduler::plugins::google_calendar::ignore_event(
                 &config, &event_id,
             );
         }
+        opts::GoogleCalendar::UnignoreEvent(opts::IgnoreEvent { event_id }) => {
+            scheduler::plugins::google_calendar::unignore_event(
+                &config, &event_id,
+            );
+        }
+        opts::GoogleCalendar::Login(opts::Login { email, flow }) => {
+            scheduler::plugins::google_calendar::login(&config, &email, flow);
+        }
+        opts::GoogleCalendar::Logout(opts::Logout { email }) => {
+            scheduler::plugins::google_calendar::logout(&config, &email);
+        }
+        opts::GoogleCalendar::Status => {
+            scheduler::plugins::google_calendar::status(&config);
+        }
+    }
+}
+
+pub fn run_caldav_command(config: &Config, caldav_command: opts::CalDav) {
+    match caldav_command {
+        opts::CalDav::Login(opts::CalDavAccount { username, url }) => {
+            scheduler::plugins::caldav::login(
+                &config,
+                &username,
+                url.as_deref(),
+            );
+        }
+        opts::CalDav::Logout(opts::CalDavAccount { username, url }) => {
+            scheduler::plugins::caldav::logout(
+                &config,
+                &username,
+                url.as_deref(),
+            );
+        }
+    }
+}
+
+pub fn run_microsoft_calendar_command(
+    config: &Config,
+    microsoft_calendar_command: opts::MicrosoftCalendar,
+) {
+    match microsoft_calendar_command {
+        opts::MicrosoftCalendar::Login(opts::MicrosoftAccount { email }) => {
+            scheduler::plugins::microsoft_calendar::login(&config, &email);
+        }
+        opts::MicrosoftCalendar::Logout(opts::MicrosoftAccount { email }) => {
+            scheduler::plugins::microsoft_calendar::logout(&config, &email);
+        }
+    }
+}
+
+pub fn run_profile_command(config: &Config, profile_command: opts::Profile) {
+    match profile_command {
+        opts::Profile::List => {
+            for name in &config.profile_names {
+                let marker = if *name == config.active_profile {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{} {}", marker, name);
+            }
+        }
+        opts::Profile::Use(opts::UseProfile { name }) => {
+            if !config.profile_names.contains(&name) {
+                println!(
+                    "There is no profile called {:?}.  The profiles are: {}",
+                    name,
+                    config.profile_names.join(", ")
+                );
+                return;
+            }
+            let mut state =
+                config::profile::ProfileState::load(&config.cache_dir);
+            state.active = Some(name.clone());
+            match state.save(&config.cache_dir) {
+                Err(err) => {
+                    println!("Could not save the active profile: {}", err);
+                }
+                Ok(()) => {
+                    println!(
+                        "Now using the profile {:?}.  A running break-time switches to it within a minute.",
+                        name
+                    );
+                }
+            }
+        }
     }
 }
 
@@ -132,9 +479,68 @@ pub fn default_main() {
     let config = Config::load(&opts).expect("Could not load config file.");
 
     match opts.cmd {
-        None => run(config),
+        None => run(&config),
         Some(opts::Command::GoogleCalendar(google_calendar_command)) => {
             run_google_calendar_command(&config, google_calendar_command);
         }
+        Some(opts::Command::CalDav(caldav_command)) => {
+            run_caldav_command(&config, caldav_command);
+        }
+        Some(opts::Command::MicrosoftCalendar(microsoft_calendar_command)) => {
+            run_microsoft_calendar_command(&config, microsoft_calendar_command);
+        }
+        Some(opts::Command::Profile(profile_command)) => {
+            run_profile_command(&config, profile_command);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn pause_then_resume_while_waiting_for_user() {
+        let (outer_sender, outer_receiver) = channel();
+        let (inner_sender, inner_receiver) = channel();
+        let mut user_wait = UserWait {
+            is_waiting: true,
+            poll: None,
+        };
+
+        assert!(user_wait.pause(&inner_sender));
+        // `Msg::Resume` starts the scheduler.
+        outer_sender.send(scheduler::Msg::Start).unwrap();
+        assert!(!user_wait.user_returned(&outer_sender));
+
+        let outer_msgs: Vec<_> = outer_receiver.try_iter().collect();
+        assert!(matches!(outer_msgs[..], [scheduler::Msg::Start]));
+        assert!(inner_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn pause_while_not_waiting_for_user() {
+        let (inner_sender, inner_receiver) = channel();
+        let mut user_wait = UserWait::default();
+
+        assert!(!user_wait.pause(&inner_sender));
+        assert!(matches!(
+            inner_receiver.try_recv(),
+            Ok(scheduler::InnerMsg::Pause)
+        ));
+    }
+
+    #[test]
+    fn user_returned_while_waiting() {
+        let (outer_sender, outer_receiver) = channel();
+        let mut user_wait = UserWait {
+            is_waiting: true,
+            poll: None,
+        };
+
+        assert!(user_wait.user_returned(&outer_sender));
+        assert!(!user_wait.user_returned(&outer_sender));
+        assert_eq!(outer_receiver.try_iter().count(), 1);
     }
 }
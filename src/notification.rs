@@ -0,0 +1,270 @@
+// This module sends desktop notifications to the freedesktop notification
+// server, org.freedesktop.Notifications, with `gdbus call`.  The commands are
+// run on a thread of their own, so that a slow or missing notification server
+// doesn't hold up the GTK thread.
+
+#[cfg(test)]
+mod fake_server;
+
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
+
+const APP_NAME: &str = "break-time";
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+
+/// How long `gdbus` waits for the notification server to answer, in seconds.
+const CALL_TIMEOUT_SECONDS: &str = "5";
+
+/// Quote `s` as a string in the `GVariant` text format that `gdbus` parses.
+fn variant_string(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// The parameters of a `Notify` call.  They have their types written out, so
+/// that `gdbus` doesn't need to introspect the notification server.
+fn notify_args(summary: &str, body: &str) -> Vec<String> {
+    vec![
+        variant_string(APP_NAME),
+        // The id of a notification to replace, and the icon.
+        String::from("uint32 0"),
+        variant_string(""),
+        variant_string(summary),
+        variant_string(body),
+        // The actions and hints.
+        String::from("@as []"),
+        String::from("@a{sv} {}"),
+        // Let the notification server decide when it expires.
+        String::from("int32 -1"),
+    ]
+}
+
+/// The id of a notification from what `gdbus` prints for a `Notify` call,
+/// like `(uint32 7,)`.
+fn parse_id(output: &str) -> Option<u32> {
+    output
+        .trim()
+        .strip_prefix("(uint32 ")?
+        .strip_suffix(",)")?
+        .parse()
+        .ok()
+}
+
+/// The message bus that `gdbus` is run against.
+#[derive(Clone, Debug)]
+enum Bus {
+    Session,
+    #[cfg(test)]
+    Address(String),
+}
+
+impl Bus {
+    /// Call `method` of the notification server with `args`, and return what
+    /// `gdbus` printed.
+    fn call(&self, method: &str, args: &[String]) -> Result<String, String> {
+        let mut command = Command::new("gdbus");
+        command.arg("call");
+        match self {
+            Self::Session => command.arg("--session"),
+            #[cfg(test)]
+            Self::Address(address) => command.args(["--address", address]),
+        };
+        let output = command
+            .args(["--timeout", CALL_TIMEOUT_SECONDS])
+            .args(["--dest", NOTIFICATIONS_NAME])
+            .args(["--object-path", NOTIFICATIONS_PATH])
+            .args(["--method", &format!("{}.{}", NOTIFICATIONS_NAME, method)])
+            .args(args)
+            .output()
+            .map_err(|err| format!("could not run gdbus: {}", err))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(format!(
+                "gdbus failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    /// Send a notification, and return its id.
+    fn notify(&self, summary: &str, body: &str) -> Result<u32, String> {
+        let output = self.call("Notify", &notify_args(summary, body))?;
+        parse_id(&output).ok_or_else(|| {
+            format!("unexpected reply {} to Notify", output.trim())
+        })
+    }
+
+    fn close_notification(&self, id: u32) -> Result<(), String> {
+        self.call("CloseNotification", &[format!("uint32 {}", id)])
+            .map(|_| ())
+    }
+}
+
+/// A notification sent by a `Notifier`, which can be closed again.
+#[derive(Clone, Debug, Default)]
+pub struct Notification {
+    /// The id the notification server gave the notification, or 0 until it
+    /// has answered.
+    id: Arc<AtomicU32>,
+}
+
+enum Request {
+    Notify {
+        summary: String,
+        body: String,
+        notification: Notification,
+    },
+    Close(Notification),
+}
+
+/// Sends desktop notifications from a thread of its own.  If they can't be
+/// sent, this only prints why.
+#[derive(Clone)]
+pub struct Notifier {
+    sender: mpsc::Sender<Request>,
+}
+
+impl Notifier {
+    /// Send notifications to the notification server on the session bus.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::spawn(Bus::Session)
+    }
+
+    fn spawn(bus: Bus) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for request in receiver {
+                handle_request(&bus, request);
+            }
+        });
+        Self { sender }
+    }
+
+    /// Send a desktop notification.
+    pub fn notify(&self, summary: &str, body: &str) -> Notification {
+        let notification = Notification::default();
+        self.sender
+            .send(Request::Notify {
+                summary: String::from(summary),
+                body: String::from(body),
+                notification: notification.clone(),
+            })
+            .expect(
+                "TODO: figure out what to do about channels potentially failing",
+            );
+        notification
+    }
+
+    /// Close `notification`, if it is still shown.
+    pub fn close(&self, notification: &Notification) {
+        self.sender
+            .send(Request::Close(notification.clone()))
+            .expect(
+            "TODO: figure out what to do about channels potentially failing",
+        );
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn handle_request(bus: &Bus, request: Request) {
+    match request {
+        Request::Notify {
+            summary,
+            body,
+            notification,
+        } => match bus.notify(&summary, &body) {
+            Err(err) => println!("Could not send a notification: {}", err),
+            Ok(id) => notification.id.store(id, Ordering::SeqCst),
+        },
+        Request::Close(notification) => {
+            // Requests are handled in order, so the id of a notification that
+            // was sent is already known.
+            let id = notification.id.swap(0, Ordering::SeqCst);
+            if id != 0 {
+                if let Err(err) = bus.close_notification(id) {
+                    println!("Could not close a notification: {}", err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake_server::{FakeServer, FAKE_ID};
+    use super::*;
+    use std::io::BufRead;
+    use std::time::Duration;
+
+    #[test]
+    fn test_variant_string() {
+        assert_eq!(variant_string("it's a\\b"), "'it\\'s a\\\\b'");
+    }
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(parse_id("(uint32 42,)\n"), Some(42));
+        assert_eq!(parse_id("()\n"), None);
+    }
+
+    #[test]
+    fn test_notify_and_close() {
+        let has_gdbus = Command::new("gdbus").arg("help").output().is_ok();
+        let daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(std::process::Stdio::piped())
+            .spawn();
+        let mut daemon = match daemon {
+            Ok(daemon) if has_gdbus => daemon,
+            _ => {
+                println!("Skipping, dbus-daemon and gdbus are needed");
+                return;
+            }
+        };
+        let mut address = String::new();
+        std::io::BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let address = address.trim();
+
+        let (_server, calls) = FakeServer::start(address);
+        let notifier = Notifier::spawn(Bus::Address(String::from(address)));
+        let notification = notifier.notify("Break-Time!", "It's a break");
+        notifier.close(&notification);
+        // Closing it again doesn't call CloseNotification again.
+        notifier.close(&notification);
+        let another = notifier.notify("Break-Time!", "Another break");
+        notifier.close(&another);
+        let timeout = Duration::from_secs(10);
+        let result = (
+            calls.recv_timeout(timeout),
+            calls.recv_timeout(timeout),
+            calls.recv_timeout(timeout),
+            calls.recv_timeout(timeout),
+        );
+        daemon.kill().unwrap();
+        daemon.wait().unwrap();
+
+        assert_eq!(
+            result,
+            (
+                Ok(String::from(
+                    "Notify('break-time', 0, '', 'Break-Time!', \"It's a break\", [], {}, -1)"
+                )),
+                Ok(format!("CloseNotification({},)", FAKE_ID)),
+                Ok(String::from(
+                    "Notify('break-time', 0, '', 'Break-Time!', 'Another break', [], {}, -1)"
+                )),
+                Ok(format!("CloseNotification({},)", FAKE_ID)),
+            )
+        );
+    }
+}
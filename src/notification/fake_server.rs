@@ -0,0 +1,151 @@
+// A fake notification server for testing, on a private message bus.  It owns
+// org.freedesktop.Notifications and answers `Notify` and `CloseNotification`
+// from a message filter, which runs on gio's D-Bus thread, so the test
+// doesn't need a main loop.  gio 0.8 doesn't have bindings for
+// GDBusConnection, so this goes through gio-sys.
+
+#![allow(unsafe_code)]
+
+use glib::translate::{from_glib_full, from_glib_none, ToGlibPtr};
+use glib::ToVariant;
+use std::ffi::CString;
+use std::ptr;
+use std::sync::{mpsc, Mutex};
+
+use super::NOTIFICATIONS_NAME;
+
+/// The id the fake notification server gives notifications.
+pub const FAKE_ID: u32 = 7;
+
+/// A tuple variant of `children`.
+fn tuple(children: &[glib::Variant]) -> glib::Variant {
+    let ptrs: Vec<*mut glib_sys::GVariant> = children
+        .iter()
+        .map(|child| child.to_glib_none().0)
+        .collect();
+    // The tuple is floating, and from_glib_none sinks it.
+    unsafe {
+        from_glib_none(glib_sys::g_variant_new_tuple(ptrs.as_ptr(), ptrs.len()))
+    }
+}
+
+/// Turn a `GError` into a message, freeing it.
+unsafe fn error_message(error: *mut glib_sys::GError) -> String {
+    let error: glib::Error = from_glib_full(error);
+    error.to_string()
+}
+
+type CallSender = Mutex<mpsc::Sender<String>>;
+
+/// Answers `Notify` and `CloseNotification` calls, and sends the method and
+/// parameters of each one.  Other messages are passed on.
+unsafe extern "C" fn filter(
+    connection: *mut gio_sys::GDBusConnection,
+    message: *mut gio_sys::GDBusMessage,
+    incoming: glib_sys::gboolean,
+    user_data: glib_sys::gpointer,
+) -> *mut gio_sys::GDBusMessage {
+    let is_call = incoming != 0
+        && gio_sys::g_dbus_message_get_message_type(message)
+            == gio_sys::G_DBUS_MESSAGE_TYPE_METHOD_CALL;
+    if !is_call {
+        return message;
+    }
+    let member: Option<String> =
+        from_glib_none(gio_sys::g_dbus_message_get_member(message));
+    let reply_body = match member.as_deref() {
+        Some("Notify") => tuple(&[FAKE_ID.to_variant()]),
+        Some("CloseNotification") => tuple(&[]),
+        _ => return message,
+    };
+    let parameters: glib::Variant =
+        from_glib_none(gio_sys::g_dbus_message_get_body(message));
+    let sender = &*(user_data as *const CallSender);
+    let _ = sender
+        .lock()
+        .expect("fake server mutex was poisoned")
+        .send(format!("{}{}", member.unwrap_or_default(), parameters));
+
+    let reply = gio_sys::g_dbus_message_new_method_reply(message);
+    gio_sys::g_dbus_message_set_body(reply, reply_body.to_glib_none().0);
+    gio_sys::g_dbus_connection_send_message(
+        connection,
+        reply,
+        gio_sys::G_DBUS_SEND_MESSAGE_FLAGS_NONE,
+        ptr::null_mut(),
+        ptr::null_mut(),
+    );
+    // The filter owns the message, and returning null drops it.
+    gobject_sys::g_object_unref(reply.cast());
+    gobject_sys::g_object_unref(message.cast());
+    ptr::null_mut()
+}
+
+unsafe extern "C" fn free_sender(user_data: glib_sys::gpointer) {
+    drop(Box::from_raw(user_data.cast::<CallSender>()));
+}
+
+pub struct FakeServer(*mut gio_sys::GDBusConnection);
+
+impl FakeServer {
+    /// Start the server on the message bus at `address`, and return it and
+    /// a receiver for the calls it gets, like
+    /// `CloseNotification(uint32 7,)`.
+    pub fn start(address: &str) -> (Self, mpsc::Receiver<String>) {
+        let address = CString::new(address).unwrap();
+        let mut error = ptr::null_mut();
+        let connection = unsafe {
+            gio_sys::g_dbus_connection_new_for_address_sync(
+                address.as_ptr(),
+                gio_sys::G_DBUS_CONNECTION_FLAGS_AUTHENTICATION_CLIENT
+                    | gio_sys::G_DBUS_CONNECTION_FLAGS_MESSAGE_BUS_CONNECTION,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &raw mut error,
+            )
+        };
+        assert!(!connection.is_null(), "{}", unsafe { error_message(error) });
+        let server = Self(connection);
+
+        let (sender, receiver) = mpsc::channel();
+        let user_data: Box<CallSender> = Box::new(Mutex::new(sender));
+        unsafe {
+            gio_sys::g_dbus_connection_add_filter(
+                connection,
+                Some(filter),
+                Box::into_raw(user_data).cast(),
+                Some(free_sender),
+            );
+        }
+
+        let parameters =
+            tuple(&[NOTIFICATIONS_NAME.to_variant(), 0_u32.to_variant()]);
+        let bus = CString::new("org.freedesktop.DBus").unwrap();
+        let bus_path = CString::new("/org/freedesktop/DBus").unwrap();
+        let method = CString::new("RequestName").unwrap();
+        let reply = unsafe {
+            gio_sys::g_dbus_connection_call_sync(
+                connection,
+                bus.as_ptr(),
+                bus_path.as_ptr(),
+                bus.as_ptr(),
+                method.as_ptr(),
+                parameters.to_glib_none().0,
+                ptr::null(),
+                gio_sys::G_DBUS_CALL_FLAGS_NONE,
+                -1,
+                ptr::null_mut(),
+                &raw mut error,
+            )
+        };
+        assert!(!reply.is_null(), "{}", unsafe { error_message(error) });
+        unsafe { glib_sys::g_variant_unref(reply) };
+        (server, receiver)
+    }
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        unsafe { gobject_sys::g_object_unref(self.0.cast()) }
+    }
+}
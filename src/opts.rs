@@ -1,3 +1,5 @@
+use crate::scheduler::plugins::google_calendar::{parse_time_arg, AuthFlow};
+
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -27,12 +29,48 @@ impl Opts {
 #[derive(Debug, StructOpt)]
 pub enum Command {
     GoogleCalendar(GoogleCalendar),
+    /// Store or delete the passwords for the `plugin.caldav` accounts.
+    #[structopt(name = "caldav")]
+    CalDav(CalDav),
+    /// Sign in to or out of the `plugin.microsoft_calendar` accounts.
+    MicrosoftCalendar(MicrosoftCalendar),
+    /// Show or switch the settings profile.
+    Profile(Profile),
 }
 
 #[derive(Debug, StructOpt)]
 pub enum GoogleCalendar {
-    ListEvents,
+    /// List the events on your calendars, and whether or not each of them
+    /// would stop a break from starting.
+    ListEvents(ListEvents),
+    /// Make break-time ignore an event.
     IgnoreEvent(IgnoreEvent),
+    /// Stop ignoring an event that was ignored with ignore-event.
+    UnignoreEvent(IgnoreEvent),
+    /// Sign in to a Google account, so that break-time can check its calendars.
+    Login(Login),
+    /// Sign out of a Google account, revoking and deleting its token.
+    Logout(Logout),
+    /// Show whether each account is signed in, and whether its token works.
+    Status,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ListEvents {
+    /// Start of the time to list events for, like 2020-03-02, "2020-03-02
+    /// 14:30", or an RFC 3339 date and time.  Defaults to the start of the time
+    /// that is checked for events before a break.
+    #[structopt(long, parse(try_from_str = parse_time_arg))]
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// End of the time to list events for.  Defaults to the same length of
+    /// time after --from as is checked for events before a break.
+    #[structopt(long, parse(try_from_str = parse_time_arg))]
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Print the events as JSON.
+    #[structopt(long)]
+    pub json: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -40,3 +78,72 @@ pub struct IgnoreEvent {
     /// Event ID.  You can get this with `break-time google-calendar list-events`.
     pub event_id: String,
 }
+
+#[derive(Debug, StructOpt)]
+pub struct Login {
+    /// The email address of the Google account.
+    pub email: String,
+
+    /// How to get the authorization code from Google: redirect, copy-paste,
+    /// or device.  Defaults to the `auth_flow` setting in the config file.
+    #[structopt(long)]
+    pub flow: Option<AuthFlow>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Logout {
+    /// The email address of the Google account.
+    pub email: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CalDav {
+    /// Ask for the password of an account in `plugin.caldav`, check it with
+    /// the server, and store it for break-time to use.
+    Login(CalDavAccount),
+    /// Delete the stored password of an account in `plugin.caldav`.
+    Logout(CalDavAccount),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CalDavAccount {
+    /// The username of the account, as in `plugin.caldav.accounts`.
+    pub username: String,
+
+    /// The url of the account, only needed when there is more than one
+    /// account with the same username.
+    #[structopt(long)]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum MicrosoftCalendar {
+    /// Sign in to a Microsoft account with a code shown on the terminal, so
+    /// that break-time can check its calendar.
+    Login(MicrosoftAccount),
+    /// Sign out of a Microsoft account, deleting its token.
+    Logout(MicrosoftAccount),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MicrosoftAccount {
+    /// The email address of the Microsoft account.
+    pub email: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Profile {
+    /// List the profiles in the config file.  The active profile is marked
+    /// with a `*`.
+    List,
+    /// Switch to a profile.  A running break-time switches to the settings
+    /// of the profile within a minute.
+    Use(UseProfile),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct UseProfile {
+    /// The name of the profile, or "default" for the settings without any
+    /// profile.
+    pub name: String,
+}
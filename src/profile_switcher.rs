@@ -0,0 +1,133 @@
+// This module switches between settings profiles while break-time is running.
+// Profiles are switched from the systray icon, with `break-time profile use`,
+// or by the profile rules in the config file.
+//
+// Switching profiles hands the settings of the new profile to the scheduler
+// and the rest of the app, without restarting, so the countdowns to the next
+// breaks keep going.  A switch that happens during a break waits until the
+// break is over.
+
+use std::path::PathBuf;
+
+use crate::config::profile::{self, ProfileRule, ProfileState};
+use crate::config::Config;
+use crate::x11::X11;
+
+pub struct ProfileSwitcher {
+    cache_dir: PathBuf,
+    profile_names: Vec<String>,
+    active_profile: String,
+    rules: Vec<ProfileRule>,
+    /// A connection to the X server, only if any of the rules check which
+    /// monitors are connected.
+    x11: Option<X11>,
+    is_break_running: bool,
+    /// The profile to switch to once the running break ends.
+    pending_switch: Option<String>,
+}
+
+impl ProfileSwitcher {
+    pub fn new(config: &Config) -> Self {
+        let x11 =
+            if config.profile_rules.iter().any(ProfileRule::needs_monitors) {
+                Some(X11::connect())
+            } else {
+                None
+            };
+        Self {
+            cache_dir: config.cache_dir.clone(),
+            profile_names: config.profile_names.clone(),
+            active_profile: config.active_profile.clone(),
+            rules: config.profile_rules.clone(),
+            x11,
+            is_break_running: false,
+            pending_switch: None,
+        }
+    }
+
+    /// The profile that should be active now, if it isn't the active one.
+    ///
+    /// This is the profile that was picked with `break-time profile use`, or
+    /// the profile from the profile rules if the rules pick a different
+    /// profile than they did last time.
+    pub fn check(&self) -> Option<String> {
+        let mut state = ProfileState::load(&self.cache_dir);
+        let mut wanted_profile = match state.active.clone() {
+            Some(active) if self.profile_names.contains(&active) => active,
+            _ => self.active_profile.clone(),
+        };
+
+        if !self.rules.is_empty() {
+            let monitors = self
+                .x11
+                .as_ref()
+                .map(X11::connected_outputs)
+                .unwrap_or_default();
+            let rule_profile = profile::matching_profile(
+                &self.rules,
+                chrono::Local::now().time(),
+                &monitors,
+            )
+            .map(String::from);
+            if rule_profile != state.last_rule_profile {
+                state.last_rule_profile = rule_profile.clone();
+                if let Err(err) = state.save(&self.cache_dir) {
+                    println!("Could not save the profile state: {}", err);
+                }
+                if let Some(rule_profile) = rule_profile {
+                    println!(
+                        "Profile rules picked the profile {:?}",
+                        rule_profile
+                    );
+                    wanted_profile = rule_profile;
+                }
+            }
+        }
+
+        if wanted_profile == self.active_profile {
+            None
+        } else {
+            Some(wanted_profile)
+        }
+    }
+
+    /// Switch to the profile called `name`.  If a break is running, this
+    /// waits until it ends.  Returns the profile whose settings should be
+    /// used from now on, if they should change now.
+    pub fn switch_to(&mut self, name: &str) -> Option<String> {
+        if name == self.active_profile {
+            return None;
+        }
+        if self.is_break_running {
+            println!(
+                "Switching to the profile {:?} after the break ends",
+                name
+            );
+            self.pending_switch = Some(String::from(name));
+            return None;
+        }
+
+        let mut state = ProfileState::load(&self.cache_dir);
+        state.active = Some(String::from(name));
+        if let Err(err) = state.save(&self.cache_dir) {
+            println!(
+                "Could not save the profile state, so not switching to the profile {:?}: {}",
+                name, err
+            );
+            return None;
+        }
+        self.active_profile = String::from(name);
+        Some(self.active_profile.clone())
+    }
+
+    pub fn break_started(&mut self) {
+        self.is_break_running = true;
+    }
+
+    /// Returns the profile that was picked during the break, to switch to
+    /// now that it is over.
+    pub const fn break_ended(&mut self) -> Option<String> {
+        self.is_break_running = false;
+        self.pending_switch.take()
+    }
+}
@@ -1,46 +1,237 @@
 // This code is pretty horrible.  I am sorry.
 
+mod activities;
+mod break_planner;
+mod clock;
 mod idle_detector;
 pub mod plugins;
+mod pomodoro;
+mod tier;
 
-use super::config::Config;
+use super::config::{
+    Activity, ChallengeSettings, Compensation, Config, Enforcement,
+    PluginPolicy, PluginSettings, Schedule,
+};
+use activities::ActivityPicker;
+use break_planner::{PlanSettings, PlannedBreak};
 use idle_detector::IdleDetector;
-use plugins::{CanBreak, Plugin};
+use plugins::backoff::Backoff;
+use plugins::{BusyBlock, CanBreak, Plugin};
+use pomodoro::Pomodoro;
+use tier::{to_chrono_duration, Tier, TierKind};
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 use std::time::Duration;
 
+use chrono::{DateTime, Utc};
+
 #[derive(Copy, Clone, Debug)]
 pub enum Msg {
     Start,
 }
 
-pub struct Plugins(Vec<Box<dyn Plugin>>);
+/// A break that the scheduler has decided to start.
+#[derive(Clone, Debug)]
+pub struct BreakInfo {
+    /// The name of the break tier this break is from.
+    pub tier_name: String,
+    pub duration: Duration,
+    pub clicks_to_end_break_early: u32,
+    pub enforcement: Enforcement,
+    /// Why the break isn't a normal break, like "extended: 70 min without a
+    /// break".  This is shown on the break screen.
+    pub reason: Option<String>,
+    /// In Pomodoro mode, the work period that was just finished, and the
+    /// number of work periods before a long break.
+    pub pomodoro_cycle: Option<(u32, u32)>,
+    /// What the user has to do to end the break early.
+    pub challenge: ChallengeSettings,
+    /// The activity to show on the break screen.
+    pub activity: Option<Activity>,
+}
+
+/// Creates a plugin from the config.
+type PluginInit = fn(&Config) -> Result<Box<dyn Plugin + Send>, ()>;
+
+const PLUGIN_INITS: [(&str, PluginInit); 5] = [
+    ("window_titles", |config| {
+        Ok(Box::new(plugins::WindowTitles::new(config)?)
+            as Box<dyn Plugin + Send>)
+    }),
+    ("google_calendar", |config| {
+        Ok(Box::new(plugins::GoogleCalendar::new(config)?)
+            as Box<dyn Plugin + Send>)
+    }),
+    ("ics_calendar", |config| {
+        Ok(Box::new(plugins::IcsCalendar::new(config)?)
+            as Box<dyn Plugin + Send>)
+    }),
+    ("caldav", |config| {
+        Ok(Box::new(plugins::CalDav::new(config)?) as Box<dyn Plugin + Send>)
+    }),
+    ("microsoft_calendar", |config| {
+        Ok(Box::new(plugins::MicrosoftCalendar::new(config)?)
+            as Box<dyn Plugin + Send>)
+    }),
+];
+
+const MIN_INIT_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_INIT_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+enum PluginSlot {
+    /// The plugin is being initialized for the first time.
+    Starting,
+    Ready(Box<dyn Plugin + Send>),
+    /// The plugin couldn't be initialized.  It is retried in the background.
+    Failed,
+}
+
+/// What happened when initializing the plugin at an index in
+/// `PLUGIN_INITS`.
+enum InitResult {
+    Ready(usize, Box<dyn Plugin + Send>),
+    Failed(usize),
+}
+
+/// Initialize the plugin at `index` in `PLUGIN_INITS` on its own thread,
+/// retrying with a backoff until it works.  Each attempt is reported over
+/// `sender`.
+fn spawn_plugin_init(config: Config, index: usize, sender: Sender<InitResult>) {
+    std::thread::spawn(move || {
+        let (name, init) = PLUGIN_INITS[index];
+        let mut backoff = Backoff::new(MIN_INIT_BACKOFF, MAX_INIT_BACKOFF);
+        loop {
+            let init_result = match init(&config) {
+                Ok(plugin) => {
+                    println!("Scheduler initialized plugin {}", name);
+                    InitResult::Ready(index, plugin)
+                }
+                Err(()) => InitResult::Failed(index),
+            };
+            let is_ready = matches!(init_result, InitResult::Ready(..));
+            if sender.send(init_result).is_err() || is_ready {
+                return;
+            }
+            let wait = backoff.next();
+            println!(
+                "Scheduler could not initialize plugin {}, retrying in {} seconds",
+                name,
+                wait.as_secs()
+            );
+            std::thread::sleep(wait);
+        }
+    });
+}
+
+/// All the plugins.  Each plugin is initialized on its own thread, so that a
+/// slow or failing plugin doesn't hold up the scheduler or the other plugins.
+/// It is just left out until it can be initialized.
+pub struct Plugins {
+    slots: Vec<PluginSlot>,
+    init_receiver: Receiver<InitResult>,
+}
 
 impl Plugins {
-    fn new(config: &Config) -> Result<Self, ()> {
-        let window_title_plugin = plugins::WindowTitles::new(config)?;
-        let google_calendar_plugin = plugins::GoogleCalendar::new(config)?;
-        let all_plugins: Vec<Box<dyn Plugin>> = vec![
-            Box::new(window_title_plugin),
-            Box::new(google_calendar_plugin),
-        ];
-        Ok(Self(all_plugins))
+    fn new(config: &Config) -> Self {
+        let (init_sender, init_receiver) = channel();
+        for index in 0..PLUGIN_INITS.len() {
+            spawn_plugin_init(config.clone(), index, init_sender.clone());
+        }
+
+        Self {
+            slots: PLUGIN_INITS.iter().map(|_| PluginSlot::Starting).collect(),
+            init_receiver,
+        }
+    }
+
+    /// Pick up the plugins that have been initialized, or that failed to,
+    /// since the last call.
+    fn receive_inits(&mut self) {
+        while let Ok(init_result) = self.init_receiver.try_recv() {
+            match init_result {
+                InitResult::Ready(index, plugin) => {
+                    self.slots[index] = PluginSlot::Ready(plugin);
+                }
+                InitResult::Failed(index) => {
+                    self.slots[index] = PluginSlot::Failed;
+                }
+            }
+        }
+    }
+
+    /// Problems with the plugins, like plugins that couldn't be initialized.
+    fn problems(&self) -> Vec<String> {
+        self.slots
+            .iter()
+            .zip(PLUGIN_INITS.iter())
+            .flat_map(|(slot, (name, _))| match slot {
+                PluginSlot::Starting => vec![],
+                PluginSlot::Ready(plugin) => plugin.problems(),
+                PluginSlot::Failed => {
+                    vec![format!("{} could not be initialized", name)]
+                }
+            })
+            .collect()
+    }
+
+    fn ready(&self) -> impl Iterator<Item = &dyn Plugin> {
+        self.slots.iter().filter_map(|slot| match slot {
+            PluginSlot::Ready(plugin) => Some(plugin.as_ref() as &dyn Plugin),
+            PluginSlot::Starting | PluginSlot::Failed => None,
+        })
+    }
+
+    /// The busy blocks from all the plugins that know about the user's
+    /// schedule, or `None` if none of them do.
+    fn busy_blocks(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Option<Vec<BusyBlock>> {
+        self.ready()
+            .filter_map(|plugin| plugin.busy_blocks(start, end))
+            .fold(None, |opt_accum: Option<Vec<BusyBlock>>, blocks| {
+                let mut accum = opt_accum.unwrap_or_default();
+                accum.extend(blocks);
+                Some(accum)
+            })
     }
 
     fn can_break_now(
         &self,
+    ) -> (Option<CanBreak>, Vec<Box<dyn std::error::Error>>) {
+        Self::combine_can_break(self.ready().map(Plugin::can_break_now))
+    }
+
+    /// Like `can_break_now()`, but plugins that know about the user's schedule
+    /// check the whole time from `start` to `end` instead.
+    fn can_break_during(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> (Option<CanBreak>, Vec<Box<dyn std::error::Error>>) {
+        Self::combine_can_break(self.ready().map(|plugin| {
+            match plugin.busy_blocks(start, end) {
+                Some(busy_blocks) => {
+                    Ok(CanBreak::from_bool(busy_blocks.is_empty()))
+                }
+                None => plugin.can_break_now(),
+            }
+        }))
+    }
+
+    fn combine_can_break(
+        results: impl Iterator<Item = Result<CanBreak, Box<dyn std::error::Error>>>,
     ) -> (Option<CanBreak>, Vec<Box<dyn std::error::Error>>) {
         fn f(
             (opt_old_can_break, mut err_accum): (
                 Option<CanBreak>,
                 Vec<Box<dyn std::error::Error>>,
             ),
-            plugin: &dyn Plugin,
+            res_can_break: Result<CanBreak, Box<dyn std::error::Error>>,
         ) -> (Option<CanBreak>, Vec<Box<dyn std::error::Error>>) {
-            let res_can_break = plugin.can_break_now();
             match res_can_break {
                 Err(err) => {
                     err_accum.push(err);
@@ -59,16 +250,7 @@ impl Plugins {
         // TODO: I probably want to parallelize calling can_break_now()
         // for each of the plugins, because they may take a non-trivial
         // amount of time deciding whether or not to break.
-        self.iter()
-            .fold((None, vec![]), |accum, plugin| f(accum, plugin))
-    }
-}
-
-impl std::ops::Deref for Plugins {
-    type Target = [Box<dyn Plugin>];
-
-    fn deref(&self) -> &[Box<dyn Plugin>] {
-        &self.0
+        results.fold((None, vec![]), f)
     }
 }
 
@@ -80,9 +262,30 @@ enum State {
 
 pub struct Scheduler {
     idle_detection_enabled: Arc<AtomicBool>,
+    /// How long the user has to be idle for the countdowns to start over.
+    /// This is shared with the idle detector.
+    idle_detection_seconds: Arc<AtomicU32>,
     sender: glib::Sender<super::Msg>,
+    /// The plugin settings that `plugins` were created with.
+    plugin_settings: PluginSettings,
     plugins: Plugins,
-    time_until_break: Duration,
+    /// The last plugin problems that were sent to the tray.
+    plugin_problems: Vec<String>,
+    /// The break tiers, sorted from the shortest break to the longest.
+    tiers: Vec<Tier>,
+    /// The tier of the break that is currently running, if any.
+    running_tier: Option<usize>,
+    /// The work period count in Pomodoro mode.  `None` in interval mode.
+    pomodoro: Option<Pomodoro>,
+    /// How to plan breaks around meetings.  This is `None` if
+    /// `calendar_aware_breaks` is turned off.  `break_duration` and `horizon`
+    /// are filled in for each tier by `plan_break()`.
+    plan_settings: Option<PlanSettings>,
+    compensation: Compensation,
+    challenge: ChallengeSettings,
+    activity_picker: ActivityPicker,
+    compensation_ratio: f64,
+    max_compensation: Duration,
     break_ending_receiver: Receiver<Msg>,
     restart_wait_time_receiver: Receiver<InnerMsg>,
     state: State,
@@ -97,21 +300,37 @@ impl Scheduler {
     pub fn new(
         config: &Config,
         idle_detection_enabled: Arc<AtomicBool>,
+        idle_detection_seconds: Arc<AtomicU32>,
         sender: glib::Sender<super::Msg>,
         break_ending_receiver: Receiver<Msg>,
         restart_wait_time_receiver: Receiver<InnerMsg>,
-    ) -> Result<Self, ()> {
-        Ok(Self {
+    ) -> Self {
+        Self {
             idle_detection_enabled,
+            idle_detection_seconds,
             sender,
-            plugins: Plugins::new(&config)?,
-            time_until_break: Duration::from_secs(
-                config.settings.seconds_between_breaks.into(),
+            plugin_settings: config.settings.all_plugin_settings.clone(),
+            plugins: Plugins::new(&config),
+            plugin_problems: vec![],
+            tiers: create_tiers(config),
+            running_tier: None,
+            pomodoro: if config.settings.schedule == Schedule::Pomodoro {
+                Some(Pomodoro::new(&config.settings.pomodoro))
+            } else {
+                None
+            },
+            plan_settings: create_plan_settings(config),
+            compensation: config.settings.compensation,
+            challenge: config.settings.challenge.clone(),
+            activity_picker: ActivityPicker::new(config),
+            compensation_ratio: config.settings.compensation_ratio,
+            max_compensation: Duration::from_secs(
+                config.settings.max_compensation_seconds.into(),
             ),
             break_ending_receiver,
             restart_wait_time_receiver,
             state: State::CountDownToBreak,
-        })
+        }
     }
 
     pub fn run(
@@ -125,24 +344,28 @@ impl Scheduler {
         let idle_detection_enabled =
             Arc::new(AtomicBool::new(config.settings.idle_detection_enabled));
         let idle_detection_enabled_clone = idle_detection_enabled.clone();
+        let idle_detection_seconds =
+            Arc::new(AtomicU32::new(config.settings.idle_detection_seconds));
+        let idle_detection_seconds_clone = idle_detection_seconds.clone();
         std::thread::spawn(move || {
-            // TODO: Need to actually handle this error.
             let mut sched = Self::new(
                 &config_clone,
                 idle_detection_enabled_clone,
+                idle_detection_seconds_clone,
                 sender,
                 sched_break_ending_receiver,
                 restart_wait_time_receiver,
-            )
-            .expect("Could not initialize plugins.");
-            println!("Scheduler initialized plugins");
+            );
+            println!(
+                "Scheduler started initializing plugins in the background"
+            );
+            sched.send_pomodoro_cycle();
             sched.run_loop();
         });
-        let config_clone = config.clone();
         let restart_wait_time_sender_clone = restart_wait_time_sender.clone();
         std::thread::spawn(move || {
             IdleDetector::run(
-                &config_clone,
+                &idle_detection_seconds,
                 &idle_detection_enabled,
                 restart_wait_time_sender_clone,
             );
@@ -174,6 +397,7 @@ impl Scheduler {
 
                     match msg {
                         Msg::Start => {
+                            self.restart_countdowns();
                             self.state = State::CountDownToBreak;
                         }
                     }
@@ -182,22 +406,288 @@ impl Scheduler {
         }
     }
 
+    /// Switch to the settings in `config`, after switching profiles.  The
+    /// countdowns of the break tiers that are in both the old and the new
+    /// settings keep going, and the plugins are only created again if their
+    /// settings changed.
+    fn reconfigure(&mut self, config: &Config) {
+        if config.settings.all_plugin_settings != self.plugin_settings {
+            println!("Scheduler creating the plugins again with new settings");
+            self.plugin_settings = config.settings.all_plugin_settings.clone();
+            self.plugins = Plugins::new(config);
+        }
+
+        let old_tiers =
+            std::mem::replace(&mut self.tiers, create_tiers(config));
+        for tier in &mut self.tiers {
+            if let Some(old_tier) = old_tiers
+                .iter()
+                .find(|old_tier| old_tier.settings.name == tier.settings.name)
+            {
+                tier.continue_countdown(old_tier);
+            }
+        }
+
+        let mut pomodoro = if config.settings.schedule == Schedule::Pomodoro {
+            Some(Pomodoro::new(&config.settings.pomodoro))
+        } else {
+            None
+        };
+        if let (Some(pomodoro), Some(old_pomodoro)) =
+            (&mut pomodoro, &self.pomodoro)
+        {
+            pomodoro.continue_from(old_pomodoro);
+        }
+        self.pomodoro = pomodoro;
+        self.send_pomodoro_cycle();
+
+        self.idle_detection_seconds
+            .store(config.settings.idle_detection_seconds, Ordering::Relaxed);
+        self.plan_settings = create_plan_settings(config);
+        self.compensation = config.settings.compensation;
+        self.challenge = config.settings.challenge.clone();
+        self.activity_picker = ActivityPicker::new(config);
+        self.compensation_ratio = config.settings.compensation_ratio;
+        self.max_compensation = Duration::from_secs(
+            config.settings.max_compensation_seconds.into(),
+        );
+    }
+
+    /// Pick up the plugins initialized in the background, and tell the tray
+    /// if the problems with the plugins have changed.
+    fn update_plugin_problems(&mut self) {
+        self.plugins.receive_inits();
+        let problems = self.plugins.problems();
+        if problems != self.plugin_problems {
+            for problem in &problems {
+                println!("Plugin problem: {}", problem);
+            }
+            self.sender
+                .send(super::Msg::PluginProblems(problems.clone()))
+                .expect("TODO: figure out what to do about channels potentially failing");
+            self.plugin_problems = problems;
+        }
+    }
+
+    /// Tell the tray which work period the user is in, in Pomodoro mode.
+    fn send_pomodoro_cycle(&self) {
+        if let Some(pomodoro) = &self.pomodoro {
+            let (cycle, cycles_before_long_break) = pomodoro.current_cycle();
+            self.sender
+                .send(super::Msg::PomodoroCycle(
+                    cycle,
+                    cycles_before_long_break,
+                ))
+                .expect("TODO: figure out what to do about channels potentially failing");
+        }
+    }
+
+    /// Start the countdowns over after a break or a pause.  After a break,
+    /// the countdowns of the tier that ran and all the tiers with shorter
+    /// breaks start over.  After a pause, or any break in Pomodoro mode, they
+    /// all start over.
+    fn restart_countdowns(&mut self) {
+        let max_break_duration = match self.running_tier.take() {
+            Some(running_tier) if self.pomodoro.is_none() => {
+                self.tiers[running_tier].break_duration
+            }
+            _ => Duration::from_secs(u64::MAX),
+        };
+        for tier in &mut self.tiers {
+            if tier.break_duration <= max_break_duration {
+                tier.restart_countdown();
+            }
+        }
+    }
+
+    /// Plan the break for the tier at `tier_index` around the busy blocks
+    /// reported by the plugins.  Returns `None` if breaks shouldn't be
+    /// planned, either because `calendar_aware_breaks` is turned off or
+    /// because none of the plugins know about the user's schedule.
+    fn plan_break(&self, tier_index: usize) -> Option<PlannedBreak> {
+        let tier = &self.tiers[tier_index];
+        let due = tier.due;
+        let plan_settings = PlanSettings {
+            break_duration: to_chrono_duration(tier.break_duration),
+            horizon: to_chrono_duration(tier.interval),
+            ..self.plan_settings?
+        };
+        let now = Utc::now();
+        let busy_blocks = self.plugins.busy_blocks(
+            now,
+            due + plan_settings.horizon + plan_settings.break_duration,
+        )?;
+        let opt_plan =
+            break_planner::plan_break(now, due, &busy_blocks, &plan_settings);
+        if let Some(plan) = opt_plan {
+            if plan.start != due
+                || plan.duration != plan_settings.break_duration
+            {
+                println!(
+                    "Scheduler moved the break due at {} to {}, lasting {} seconds, to fit around meetings",
+                    due.with_timezone(&chrono::Local),
+                    plan.start.with_timezone(&chrono::Local),
+                    plan.duration.num_seconds()
+                );
+            }
+        }
+        opt_plan
+    }
+
+    /// Compensate for breaks having been put off, by either lengthening the
+    /// break that is about to start, or shortening the next work period.
+    fn compensate(&mut self, tier_index: usize, break_info: &mut BreakInfo) {
+        let worked = self.tiers[tier_index].countdown_started.elapsed();
+        let compensation = compensation_for(
+            worked,
+            self.tiers[tier_index].interval,
+            self.compensation_ratio,
+            self.max_compensation,
+        );
+        if compensation == Duration::from_secs(0) {
+            return;
+        }
+
+        let lengthen_break = match self.compensation {
+            Compensation::LengthenBreak => {
+                // Don't lengthen the break into a meeting.
+                let now = Utc::now();
+                let end = now
+                    + to_chrono_duration(break_info.duration + compensation);
+                self.plugins
+                    .busy_blocks(now, end)
+                    .map_or(true, |busy_blocks| busy_blocks.is_empty())
+            }
+            Compensation::ShortenWorkPeriod => false,
+        };
+
+        let worked_mins = worked.as_secs() / 60;
+        if lengthen_break {
+            break_info.duration += compensation;
+            break_info.reason =
+                Some(format!("extended: {} min without a break", worked_mins));
+        } else {
+            self.tiers[tier_index].work_period_reduction = compensation;
+            break_info.reason = Some(format!(
+                "next work period shortened: {} min without a break",
+                worked_mins
+            ));
+        }
+        println!(
+            "Scheduler compensating {} seconds for working {} minutes without a break",
+            compensation.as_secs(),
+            worked_mins
+        );
+    }
+
+    /// Pick the tier whose break comes next.
+    fn next_tier(&self) -> usize {
+        match &self.pomodoro {
+            None => tier::next_tier(&self.tiers, |_| true),
+            Some(pomodoro) => {
+                // Fixed breaks still happen in Pomodoro mode, alongside
+                // whichever of the short or long break is next.
+                let mut pomodoro_tiers = (0..self.tiers.len())
+                    .filter(|&index| !self.tiers[index].is_fixed());
+                let pomodoro_tier = if pomodoro.next_break_is_long() {
+                    pomodoro_tiers.next_back()
+                } else {
+                    pomodoro_tiers.next()
+                }
+                .expect("There should always be Pomodoro break tiers");
+                tier::next_tier(&self.tiers, |index| {
+                    index == pomodoro_tier || self.tiers[index].is_fixed()
+                })
+            }
+        }
+    }
+
+    /// Tell the app to start a break from the tier at `tier_index`, planned
+    /// with `opt_plan`.
+    fn start_break(
+        &mut self,
+        tier_index: usize,
+        tier_name: String,
+        opt_plan: Option<PlannedBreak>,
+    ) {
+        let activity = self.activity_picker.pick(&tier_name);
+        let tier = &self.tiers[tier_index];
+        let mut break_info = BreakInfo {
+            tier_name,
+            duration: opt_plan
+                .and_then(|plan| plan.duration.to_std().ok())
+                .unwrap_or(tier.break_duration),
+            clicks_to_end_break_early: tier.settings.clicks_to_end_break_early,
+            enforcement: tier.settings.enforcement,
+            reason: None,
+            pomodoro_cycle: self.pomodoro.as_ref().map(Pomodoro::current_cycle),
+            challenge: self.challenge.clone(),
+            activity,
+        };
+        self.compensate(tier_index, &mut break_info);
+        self.running_tier = Some(tier_index);
+        if let Some(pomodoro) = &mut self.pomodoro {
+            if !self.tiers[tier_index].is_fixed() {
+                pomodoro.complete_cycle();
+            }
+        }
+        self.send_pomodoro_cycle();
+        println!(
+            "Scheduler realized it was able to break, so sending a message."
+        );
+        self.sender
+            .send(super::Msg::StartBreak(Box::new(break_info)))
+            .expect(
+                "TODO: figure out what to do about channels potentially failing",
+            );
+    }
+
     fn wait_until_break(&mut self) -> WaitUntilBreakResult {
         loop {
-            let waiting_result = self.send_msgs_while_waiting();
+            self.update_plugin_problems();
+            let tier_index = self.next_tier();
+            let due = self.tiers[tier_index].due;
+            let tier_name = self.tiers[tier_index].settings.name.clone();
+            let opt_plan = self.plan_break(tier_index);
+            let wait = (opt_plan.map_or(due, |plan| plan.start) - Utc::now())
+                .to_std()
+                .unwrap_or_else(|_| Duration::from_secs(0));
+            let waiting_result = self.send_msgs_while_waiting(wait, &tier_name);
             match waiting_result {
-                WaitingResult::Finished => {
+                WaitingResult::Finished | WaitingResult::StartNow => {
                     println!(
-                        "Scheduler successfully finished sleeping, checking if it can break now..."
+                        "Scheduler successfully finished sleeping, checking if it can start a {} now...",
+                        tier_name
                     );
-                    let (opt_can_break, errs) = self.plugins.can_break_now();
+                    self.update_plugin_problems();
+                    // A break the user started is started no matter what the
+                    // plugins say, and isn't planned around anything.
+                    let start_now =
+                        matches!(waiting_result, WaitingResult::StartNow);
+                    let opt_plan = opt_plan.filter(|_| !start_now);
+                    let (opt_can_break, errs) = match (
+                        self.tiers[tier_index].settings.plugins,
+                        opt_plan,
+                    ) {
+                        _ if start_now => (Some(CanBreak::Yes), vec![]),
+                        (PluginPolicy::Ignore, _) => {
+                            (Some(CanBreak::Yes), vec![])
+                        }
+                        (PluginPolicy::Check, None) => {
+                            self.plugins.can_break_now()
+                        }
+                        (PluginPolicy::Check, Some(plan)) => {
+                            let now = Utc::now();
+                            self.plugins
+                                .can_break_during(now, now + plan.duration)
+                        }
+                    };
                     if errs.is_empty() {
                         match opt_can_break {
                             None => panic!("If there are no errors, then we should always get a response to can_break"),
                             Some(can_break) => {
                                 if can_break.into_bool() {
-                                    println!("Scheduler realized it was able to break, so sending a message.");
-                                    self.sender.send(super::Msg::StartBreak).expect("TODO: figure out what to do about channels potentially failing");
+                                    self.start_break(tier_index, tier_name, opt_plan);
                                     return WaitUntilBreakResult::FinishedWaiting;
                                 }
                                 println!("Could not break right now, so sleeping again...");
@@ -212,12 +702,37 @@ impl Scheduler {
                         }
                         println!("Sleeping again just to be safe...");
                     }
+                    self.tiers[tier_index].put_off();
                 }
-                WaitingResult::NeedToRestart => {
+                WaitingResult::NeedToRestart(idle_time) => {
                     // Just let this loop restart.
                     println!(
                         "Scheduler got a message to restart sleeping again, probably because X has been idle..."
                     );
+                    for tier in &mut self.tiers {
+                        tier.restart_countdown();
+                    }
+                    if let Some(pomodoro) = &mut self.pomodoro {
+                        if pomodoro.reset_if_idle_for(idle_time) {
+                            println!("Scheduler starting the Pomodoro work periods over after being idle for {} seconds", idle_time.as_secs());
+                            self.send_pomodoro_cycle();
+                        }
+                    }
+                }
+                WaitingResult::Postponed(postpone) => {
+                    println!(
+                        "Scheduler postponing the {} by {} seconds",
+                        tier_name,
+                        postpone.as_secs()
+                    );
+                    self.tiers[tier_index].postpone(postpone);
+                }
+                WaitingResult::Reconfigured(config) => {
+                    println!(
+                        "Scheduler switching to the settings of the profile {:?}",
+                        config.active_profile
+                    );
+                    self.reconfigure(&config);
                 }
                 WaitingResult::Paused => {
                     return WaitUntilBreakResult::Paused;
@@ -226,14 +741,22 @@ impl Scheduler {
         }
     }
 
-    fn send_msgs_while_waiting(&mut self) -> WaitingResult {
+    fn send_msgs_while_waiting(
+        &mut self,
+        wait: Duration,
+        tier_name: &str,
+    ) -> WaitingResult {
         self.sender.send(super::Msg::ResetSysTrayIcon).expect(
             "TODO: figure out what to do about channels potentially failing",
         );
-        let mut remaining_time = self.time_until_break;
-        for period in
-            create_periods_to_send_time_left_message(self.time_until_break)
-        {
+        // Work out the time remaining from the wall clock each time around,
+        // instead of counting down the time slept, so that time spent
+        // suspended counts too.
+        let deadline = Utc::now() + to_chrono_duration(wait);
+        for period in create_periods_to_send_time_left_message(wait) {
+            let remaining_time = (deadline - Utc::now())
+                .to_std()
+                .unwrap_or_else(|_| Duration::from_secs(0));
             let opt_time_to_sleep = remaining_time.checked_sub(period);
             println!("In send_msgs_while_waiting loop for period {:?}, remaining_time: {:?}, time_to_sleep: {:?}", period, remaining_time, opt_time_to_sleep);
             match opt_time_to_sleep {
@@ -246,7 +769,7 @@ impl Scheduler {
                         .restart_wait_time_receiver
                         .recv_timeout(time_to_sleep);
                     match res {
-                        Ok(InnerMsg::HasBeenIdle) => {
+                        Ok(InnerMsg::HasBeenIdle(idle_time)) => {
                             println!(
                                 "\tIn send_msgs_while_waiting loop for period {:?}, remaining_time: {:?}, time_to_sleep: {:?}, got HasBeenIdle message",
                                 period, remaining_time, opt_time_to_sleep);
@@ -255,7 +778,7 @@ impl Scheduler {
                                 .idle_detection_enabled
                                 .load(Ordering::Relaxed)
                             {
-                                return WaitingResult::NeedToRestart;
+                                return WaitingResult::NeedToRestart(idle_time);
                             }
                         }
                         Ok(InnerMsg::Pause) => {
@@ -263,6 +786,15 @@ impl Scheduler {
                                 period, remaining_time, opt_time_to_sleep);
                             return WaitingResult::Paused;
                         }
+                        Ok(InnerMsg::Postpone(postpone)) => {
+                            return WaitingResult::Postponed(postpone);
+                        }
+                        Ok(InnerMsg::StartNow) => {
+                            return WaitingResult::StartNow;
+                        }
+                        Ok(InnerMsg::Reconfigure(config)) => {
+                            return WaitingResult::Reconfigured(config);
+                        }
                         Ok(InnerMsg::EnableIdleDetector) => {
                             println!("\tIn send_msgs_while_waiting loop for period {:?}, remaining_time: {:?}, time_to_sleep: {:?}, got EnableIdleDetector message",
                                 period, remaining_time, opt_time_to_sleep);
@@ -272,9 +804,11 @@ impl Scheduler {
 
                             // TODO: This doesn't logically belong here.
                             self.sender.send(
-                                super::Msg::TimeRemainingBeforeBreak(period),
+                                super::Msg::TimeRemainingBeforeBreak(
+                                    period,
+                                    tier_name.to_string(),
+                                ),
                             ).expect("TODO: figure out what to do about channels potentially failing");
-                            remaining_time -= time_to_sleep;
                         }
                         Ok(InnerMsg::DisableIdleDetector) => {
                             println!("\tIn send_msgs_while_waiting loop for period {:?}, remaining_time: {:?}, time_to_sleep: {:?}, got DisableIdleDetector message",
@@ -285,17 +819,22 @@ impl Scheduler {
 
                             // TODO: This doesn't logically belong here.
                             self.sender.send(
-                                super::Msg::TimeRemainingBeforeBreak(period),
+                                super::Msg::TimeRemainingBeforeBreak(
+                                    period,
+                                    tier_name.to_string(),
+                                ),
                             ).expect("TODO: figure out what to do about channels potentially failing");
-                            remaining_time -= time_to_sleep;
                         }
                         Err(_) => {
                             println!("\tIn send_msgs_while_waiting loop for period {:?}, remaining_time: {:?}, time_to_sleep: {:?}, timeout no message",
                                 period, remaining_time, opt_time_to_sleep);
+                            self.update_plugin_problems();
                             self.sender.send(
-                                super::Msg::TimeRemainingBeforeBreak(period),
+                                super::Msg::TimeRemainingBeforeBreak(
+                                    period,
+                                    tier_name.to_string(),
+                                ),
                             ).expect("TODO: figure out what to do about channels potentially failing");
-                            remaining_time -= time_to_sleep;
                         }
                     }
                 }
@@ -308,15 +847,107 @@ impl Scheduler {
 
 enum WaitingResult {
     Finished,
-    NeedToRestart,
+    /// The user wants to start the break now.
+    StartNow,
+    /// The user wants to put the break off for this long.
+    Postponed(Duration),
+    /// The user has been idle for this long.
+    NeedToRestart(Duration),
+    /// The settings changed, because the user switched profiles.
+    Reconfigured(Box<Config>),
     Paused,
 }
 
 pub enum InnerMsg {
     Pause,
-    HasBeenIdle,
+    /// Put the next break off for this long.
+    Postpone(Duration),
+    /// Start the next break now.
+    StartNow,
+    /// The user has been idle for this long.
+    HasBeenIdle(Duration),
     EnableIdleDetector,
     DisableIdleDetector,
+    /// Use the settings from this config, after switching profiles.
+    Reconfigure(Box<Config>),
+}
+
+/// How to plan breaks around meetings, or `None` if `calendar_aware_breaks`
+/// is turned off.
+fn create_plan_settings(config: &Config) -> Option<PlanSettings> {
+    if !config.settings.calendar_aware_breaks {
+        return None;
+    }
+    Some(PlanSettings {
+        break_duration: chrono::Duration::seconds(
+            config.settings.break_duration_seconds.into(),
+        ),
+        min_break_duration: chrono::Duration::seconds(
+            config.settings.min_break_duration_seconds.into(),
+        ),
+        max_pull_forward: chrono::Duration::seconds(
+            config.settings.max_seconds_to_pull_break_forward.into(),
+        ),
+        horizon: chrono::Duration::seconds(
+            config.settings.seconds_between_breaks.into(),
+        ),
+    })
+}
+
+/// Create the countdowns for the configured break tiers and fixed breaks,
+/// sorted from the shortest break to the longest.  Fixed breaks with a time
+/// that can't be parsed are left out.
+fn create_tiers(config: &Config) -> Vec<Tier> {
+    let mut tiers: Vec<Tier> = config
+        .settings
+        .break_tiers()
+        .into_iter()
+        .map(|settings| {
+            let kind = if settings.align_to_clock {
+                TierKind::Aligned {
+                    offset: Duration::from_secs(
+                        settings.clock_offset_seconds.into(),
+                    ),
+                }
+            } else {
+                TierKind::Interval
+            };
+            Tier::new(settings, kind)
+        })
+        .collect();
+    for fixed_break in &config.settings.fixed_breaks {
+        match fixed_break.at.parse() {
+            Ok(fixed_time) => tiers.push(Tier::new(
+                fixed_break.break_tier(),
+                TierKind::Fixed(fixed_time),
+            )),
+            Err(err) => println!(
+                "Ignoring the fixed break {:?}: {}",
+                fixed_break.name, err
+            ),
+        }
+    }
+    tiers.sort_by_key(|tier| tier.break_duration);
+    tiers
+}
+
+/// How much compensation to give for having worked for `worked` when breaks
+/// are meant to be `work_period` apart.
+fn compensation_for(
+    worked: Duration,
+    work_period: Duration,
+    ratio: f64,
+    max_compensation: Duration,
+) -> Duration {
+    let overtime = worked
+        .checked_sub(work_period)
+        .unwrap_or_else(|| Duration::from_secs(0));
+    // A negative or NaN ratio gives no compensation, and one too large for a
+    // Duration gives the most compensation.
+    let compensation =
+        Duration::try_from_secs_f64(overtime.as_secs_f64() * ratio.max(0.0))
+            .unwrap_or(max_compensation);
+    std::cmp::min(compensation, max_compensation)
 }
 
 fn create_periods_to_send_time_left_message(
@@ -341,6 +972,40 @@ fn create_periods_to_send_time_left_message(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_compensation_for() {
+        let mins = |m: u64| Duration::from_secs(m * 60);
+
+        assert_eq!(
+            compensation_for(mins(45), mins(50), 0.5, mins(10)),
+            mins(0)
+        );
+        assert_eq!(
+            compensation_for(mins(70), mins(50), 0.5, mins(10)),
+            mins(10)
+        );
+        assert_eq!(
+            compensation_for(mins(60), mins(50), 0.2, mins(10)),
+            mins(2)
+        );
+        assert_eq!(
+            compensation_for(mins(170), mins(50), 0.5, mins(10)),
+            mins(10)
+        );
+        assert_eq!(
+            compensation_for(mins(170), mins(50), 0.0, mins(10)),
+            mins(0)
+        );
+        assert_eq!(
+            compensation_for(mins(170), mins(50), f64::NAN, mins(10)),
+            mins(0)
+        );
+        assert_eq!(
+            compensation_for(mins(170), mins(50), 1e300, mins(10)),
+            mins(10)
+        );
+    }
+
     #[test]
     fn test_periods_to_send_time_left_message() {
         let res = create_periods_to_send_time_left_message(
@@ -0,0 +1,183 @@
+// This module picks the activity to show on the break screen for each break.
+//
+// The activities are picked in rounds.  An activity isn't picked again until
+// all the other activities for the break have been picked in the current
+// round.  The activities picked in the current round are kept in the cache
+// directory, so that restarting break-time doesn't start a new round.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Activity, ActivityRotation, Config};
+
+/// The activities picked in the current round, as saved in the cache
+/// directory.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Round {
+    picked: Vec<String>,
+}
+
+pub struct ActivityPicker {
+    activities: Vec<Activity>,
+    rotation: ActivityRotation,
+    round_path: PathBuf,
+    round: Round,
+}
+
+impl ActivityPicker {
+    pub fn new(config: &Config) -> Self {
+        let round_path = config.cache_dir.join("activities.toml");
+        let round = std::fs::read_to_string(&round_path)
+            .ok()
+            .and_then(|round_str| toml::from_str(&round_str).ok())
+            .unwrap_or_default();
+        Self {
+            activities: config.settings.activities.clone(),
+            rotation: config.settings.activity_rotation,
+            round_path,
+            round,
+        }
+    }
+
+    /// Pick the activity for a break from the break tier called `tier_name`,
+    /// or `None` if there are no activities for it.
+    pub fn pick(&mut self, tier_name: &str) -> Option<Activity> {
+        let random = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.subsec_nanos() as usize);
+        let activity = pick_activity(
+            &self.activities,
+            self.rotation,
+            &mut self.round.picked,
+            tier_name,
+            random,
+        )?
+        .clone();
+        if let Err(err) = save_round(&self.round_path, &self.round) {
+            println!(
+                "Could not save the picked activities to {:?}: {}",
+                self.round_path, err
+            );
+        }
+        Some(activity)
+    }
+}
+
+fn save_round(round_path: &Path, round: &Round) -> Result<(), String> {
+    let round_str = toml::to_string(round).map_err(|err| err.to_string())?;
+    std::fs::write(round_path, round_str).map_err(|err| err.to_string())
+}
+
+/// Pick an activity for the break tier called `tier_name` that isn't in
+/// `picked`, and add it to `picked`.  If all the activities for the tier are
+/// in `picked`, a new round starts, without the activity that was picked
+/// last.  `random` is used to pick an activity when shuffling.
+fn pick_activity<'a>(
+    activities: &'a [Activity],
+    rotation: ActivityRotation,
+    picked: &mut Vec<String>,
+    tier_name: &str,
+    random: usize,
+) -> Option<&'a Activity> {
+    let for_tier: Vec<&Activity> = activities
+        .iter()
+        .filter(|activity| {
+            activity.tiers.is_empty()
+                || activity.tiers.iter().any(|tier| tier == tier_name)
+        })
+        .collect();
+    let not_picked = |picked: &[String]| -> Vec<&'a Activity> {
+        for_tier
+            .iter()
+            .copied()
+            .filter(|activity| !picked.contains(&activity.name))
+            .collect()
+    };
+
+    let mut candidates = not_picked(picked);
+    if candidates.is_empty() {
+        let last_picked: Vec<String> = for_tier
+            .iter()
+            .rev()
+            .filter_map(|activity| {
+                picked.iter().rposition(|name| *name == activity.name)
+            })
+            .max()
+            .map(|index| picked[index].clone())
+            .into_iter()
+            .collect();
+        picked.retain(|name| !for_tier.iter().any(|a| a.name == *name));
+        candidates = not_picked(&last_picked);
+        if candidates.is_empty() {
+            candidates = for_tier.clone();
+        }
+    }
+
+    let activity = match rotation {
+        ActivityRotation::InOrder => candidates.first().copied(),
+        ActivityRotation::Shuffle => candidates
+            .get(random % std::cmp::max(candidates.len(), 1))
+            .copied(),
+    }?;
+    picked.push(activity.name.clone());
+    Some(activity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity(name: &str, tiers: &[&str]) -> Activity {
+        Activity {
+            name: String::from(name),
+            text: String::new(),
+            image: None,
+            steps: vec![],
+            tiers: tiers.iter().map(|tier| String::from(*tier)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_pick_activity() {
+        let activities = vec![
+            activity("a", &[]),
+            activity("b", &[]),
+            activity("eyes", &["eye break"]),
+        ];
+        let mut picked = vec![];
+        let mut pick = |tier_name, random| {
+            pick_activity(
+                &activities,
+                ActivityRotation::InOrder,
+                &mut picked,
+                tier_name,
+                random,
+            )
+            .map(|activity| activity.name.clone())
+        };
+
+        assert_eq!(pick("break", 0).as_deref(), Some("a"));
+        assert_eq!(pick("break", 0).as_deref(), Some("b"));
+        // A new round starts, but "b" isn't picked twice in a row.
+        assert_eq!(pick("break", 0).as_deref(), Some("a"));
+        assert_eq!(pick("eye break", 0).as_deref(), Some("b"));
+        assert_eq!(pick("eye break", 0).as_deref(), Some("eyes"));
+
+        let mut picked = vec![];
+        let mut names = vec![];
+        for random in &[1, 1, 1] {
+            let activity = pick_activity(
+                &activities[..2],
+                ActivityRotation::Shuffle,
+                &mut picked,
+                "break",
+                *random,
+            )
+            .unwrap();
+            names.push(activity.name.clone());
+        }
+        assert_eq!(names, vec!["b", "a", "b"]);
+    }
+}
@@ -0,0 +1,196 @@
+// This module decides when a break should start, based on the busy blocks
+// (like meetings) reported by the calendar plugins.  Instead of putting a break
+// off until after a meeting, it can be pulled forward into the free time before
+// the meeting, or moved (and shortened) to fit between back-to-back meetings.
+
+use super::plugins::BusyBlock;
+
+use chrono::{DateTime, Duration, Utc};
+
+#[derive(Clone, Copy, Debug)]
+pub struct PlanSettings {
+    pub break_duration: Duration,
+    /// The shortest break worth taking between back-to-back meetings.
+    pub min_break_duration: Duration,
+    /// How much earlier than it is due a break may be pulled forward.
+    pub max_pull_forward: Duration,
+    /// How far after the break is due to look for free time to move it to.
+    pub horizon: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlannedBreak {
+    pub start: DateTime<Utc>,
+    pub duration: Duration,
+}
+
+/// Sort the busy blocks, and merge the ones that overlap or touch.
+fn merge_busy_blocks(busy_blocks: &[BusyBlock]) -> Vec<BusyBlock> {
+    let mut sorted = busy_blocks.to_vec();
+    sorted.sort_by_key(|block| block.start);
+
+    let mut merged: Vec<BusyBlock> = vec![];
+    for block in sorted {
+        match merged.last_mut() {
+            Some(last) if block.start <= last.end => {
+                last.end = std::cmp::max(last.end, block.end);
+            }
+            _ => merged.push(block),
+        }
+    }
+    merged
+}
+
+/// Plan a break that is due at `due`.  Returns `None` if there isn't enough
+/// free time for a break before `due + settings.horizon`.
+pub fn plan_break(
+    now: DateTime<Utc>,
+    due: DateTime<Utc>,
+    busy_blocks: &[BusyBlock],
+    settings: &PlanSettings,
+) -> Option<PlannedBreak> {
+    let due = std::cmp::max(now, due);
+    let len = settings.break_duration;
+    let blocks: Vec<BusyBlock> = merge_busy_blocks(busy_blocks)
+        .into_iter()
+        .filter(|block| block.end > now)
+        .collect();
+
+    // The first busy block that gets in the way of a break starting on time.
+    let opt_blocker_index = blocks
+        .iter()
+        .position(|block| block.end > due && block.start < due + len);
+
+    let blocker_index = match opt_blocker_index {
+        None => {
+            return Some(PlannedBreak {
+                start: due,
+                duration: len,
+            })
+        }
+        Some(blocker_index) => blocker_index,
+    };
+    let blocker = blocks[blocker_index];
+
+    // Pull the break forward so that it ends when the busy block starts, if
+    // there is enough free time before it.
+    let earliest_start = std::cmp::max(now, due - settings.max_pull_forward);
+    let pulled_start = blocker.start - len;
+    let prev_end = blocker_index
+        .checked_sub(1)
+        .map(|prev_index| blocks[prev_index].end);
+    if pulled_start >= earliest_start
+        && prev_end.map_or(true, |prev_end| prev_end <= pulled_start)
+    {
+        return Some(PlannedBreak {
+            start: pulled_start,
+            duration: len,
+        });
+    }
+
+    // Otherwise, move the break to the first free time after the busy block,
+    // shortening it if it doesn't fit before the next busy block.
+    let latest_start = due + settings.horizon;
+    let mut gap_start = blocker.end;
+    for next in &blocks[blocker_index + 1..] {
+        if gap_start > latest_start {
+            return None;
+        }
+        let gap = next.start - gap_start;
+        if gap >= settings.min_break_duration {
+            return Some(PlannedBreak {
+                start: gap_start,
+                duration: std::cmp::min(gap, len),
+            });
+        }
+        gap_start = next.end;
+    }
+
+    if gap_start > latest_start {
+        None
+    } else {
+        Some(PlannedBreak {
+            start: gap_start,
+            duration: len,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn at(hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.ymd(2020, 3, 2).and_hms(hour, min, 0)
+    }
+
+    fn block(start: DateTime<Utc>, end: DateTime<Utc>) -> BusyBlock {
+        BusyBlock { start, end }
+    }
+
+    #[test]
+    fn test_plan_break() {
+        let settings = PlanSettings {
+            break_duration: Duration::minutes(10),
+            min_break_duration: Duration::minutes(5),
+            max_pull_forward: Duration::minutes(15),
+            horizon: Duration::minutes(120),
+        };
+        let plan = |due, blocks: &[BusyBlock]| {
+            plan_break(at(10, 0), due, blocks, &settings)
+        };
+
+        // Nothing in the way.
+        assert_eq!(
+            plan(at(10, 15), &[block(at(11, 0), at(12, 0))]),
+            Some(PlannedBreak {
+                start: at(10, 15),
+                duration: Duration::minutes(10)
+            })
+        );
+
+        // A meeting starts in 12 minutes and the break is due in 15, so the
+        // break is pulled forward to end when the meeting starts.
+        assert_eq!(
+            plan(at(10, 15), &[block(at(10, 12), at(11, 30))]),
+            Some(PlannedBreak {
+                start: at(10, 2),
+                duration: Duration::minutes(10)
+            })
+        );
+
+        // There isn't enough time before the meeting, so the break is moved
+        // between the back-to-back meetings and shortened to fit.
+        assert_eq!(
+            plan(
+                at(10, 15),
+                &[
+                    block(at(9, 30), at(10, 20)),
+                    block(at(10, 20), at(11, 0)),
+                    block(at(11, 7), at(12, 0)),
+                ]
+            ),
+            Some(PlannedBreak {
+                start: at(11, 0),
+                duration: Duration::minutes(7)
+            })
+        );
+
+        // Gaps that are too short are skipped.
+        assert_eq!(
+            plan(
+                at(10, 15),
+                &[block(at(10, 5), at(11, 0)), block(at(11, 3), at(11, 30)),]
+            ),
+            Some(PlannedBreak {
+                start: at(11, 30),
+                duration: Duration::minutes(10)
+            })
+        );
+
+        // No free time before the horizon.
+        assert_eq!(plan(at(10, 15), &[block(at(10, 5), at(13, 0))]), None);
+    }
+}
@@ -0,0 +1,168 @@
+// This module works out wall-clock times for breaks that are aligned to the
+// clock, like ":50 past every hour", or at fixed times, like lunch at 12:30.
+//
+// The times are worked out in local time, so that they stay at the same time
+// on the clock across daylight saving time changes.
+
+use std::str::FromStr;
+
+use chrono::{
+    DateTime, Duration, LocalResult, NaiveDateTime, NaiveTime, TimeZone,
+    Timelike,
+};
+
+/// The time of a fixed break.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FixedTime {
+    /// Every day at this time, written like `12:30`.
+    Daily(NaiveTime),
+    /// Every hour at this minute, written like `*:50`.
+    Hourly(u32),
+}
+
+impl FromStr for FixedTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            format!(
+                "could not parse {:?} as a time, expected something like \"12:30\" or \"*:50\"",
+                s
+            )
+        };
+        match s.strip_prefix("*:") {
+            Some(minute_str) => {
+                let minute: u32 = minute_str.parse().map_err(|_| err())?;
+                if minute < 60 {
+                    Ok(Self::Hourly(minute))
+                } else {
+                    Err(err())
+                }
+            }
+            None => NaiveTime::parse_from_str(s, "%H:%M")
+                .map(Self::Daily)
+                .map_err(|_| err()),
+        }
+    }
+}
+
+/// Turn a local time into a `DateTime`.  A time that is skipped when the
+/// clocks go forward is moved an hour later.  A time that happens twice when
+/// the clocks go back uses the first one.
+fn from_local<Tz: TimeZone>(tz: &Tz, local: &NaiveDateTime) -> DateTime<Tz> {
+    match tz.from_local_datetime(local) {
+        LocalResult::Single(date_time) => date_time,
+        LocalResult::Ambiguous(earliest, _) => earliest,
+        LocalResult::None => tz
+            .from_local_datetime(&(*local + Duration::hours(1)))
+            .earliest()
+            .expect("Time should exist an hour after a skipped time"),
+    }
+}
+
+/// The first time after `now` when a fixed break at `fixed_time` happens.
+pub fn next_fixed_time<Tz: TimeZone>(
+    now: &DateTime<Tz>,
+    fixed_time: FixedTime,
+) -> DateTime<Tz> {
+    let tz = now.timezone();
+    let local_now = now.naive_local();
+    let mut candidate = match fixed_time {
+        FixedTime::Daily(time) => local_now.date().and_time(time),
+        FixedTime::Hourly(minute) => {
+            local_now.date().and_hms(local_now.hour(), minute, 0)
+        }
+    };
+    let step = match fixed_time {
+        FixedTime::Daily(_) => Duration::days(1),
+        FixedTime::Hourly(_) => Duration::hours(1),
+    };
+    loop {
+        let date_time = from_local(&tz, &candidate);
+        if date_time > *now {
+            return date_time;
+        }
+        candidate += step;
+    }
+}
+
+/// The first time after `now` that is a multiple of `interval` after local
+/// midnight plus `offset`.  With an `interval` of an hour and an `offset` of 50
+/// minutes, this is the next ":50 past the hour".
+pub fn next_aligned_time<Tz: TimeZone>(
+    now: &DateTime<Tz>,
+    interval: Duration,
+    offset: Duration,
+) -> DateTime<Tz> {
+    let tz = now.timezone();
+    let local_now = now.naive_local();
+    let interval_secs = std::cmp::max(interval.num_seconds(), 1);
+    let interval = Duration::seconds(interval_secs);
+    let offset = Duration::seconds(offset.num_seconds() % interval_secs);
+    let mut candidate = local_now.date().and_hms(0, 0, 0) + offset;
+    loop {
+        let date_time = from_local(&tz, &candidate);
+        if date_time > *now {
+            return date_time;
+        }
+        candidate += interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{FixedOffset, Utc};
+
+    #[test]
+    fn test_parse_fixed_time() {
+        assert_eq!(
+            "12:30".parse(),
+            Ok(FixedTime::Daily(NaiveTime::from_hms(12, 30, 0)))
+        );
+        assert_eq!("*:50".parse(), Ok(FixedTime::Hourly(50)));
+        assert!("*:60".parse::<FixedTime>().is_err());
+        assert!("lunch".parse::<FixedTime>().is_err());
+    }
+
+    #[test]
+    fn test_next_times() {
+        let tz = FixedOffset::east(9 * 3600);
+        let now = tz.ymd(2020, 3, 2).and_hms(12, 40, 0);
+
+        assert_eq!(
+            next_fixed_time(&now, "12:30".parse().unwrap()),
+            tz.ymd(2020, 3, 3).and_hms(12, 30, 0)
+        );
+        assert_eq!(
+            next_fixed_time(&now, "15:00".parse().unwrap()),
+            tz.ymd(2020, 3, 2).and_hms(15, 0, 0)
+        );
+        assert_eq!(
+            next_fixed_time(&now, "*:50".parse().unwrap()),
+            tz.ymd(2020, 3, 2).and_hms(12, 50, 0)
+        );
+        assert_eq!(
+            next_fixed_time(&now, "*:30".parse().unwrap()),
+            tz.ymd(2020, 3, 2).and_hms(13, 30, 0)
+        );
+
+        assert_eq!(
+            next_aligned_time(&now, Duration::hours(1), Duration::minutes(50)),
+            tz.ymd(2020, 3, 2).and_hms(12, 50, 0)
+        );
+        assert_eq!(
+            next_aligned_time(&now, Duration::minutes(25), Duration::zero()),
+            tz.ymd(2020, 3, 2).and_hms(12, 55, 0)
+        );
+        assert_eq!(
+            next_aligned_time(
+                &Utc.ymd(2020, 3, 2).and_hms(23, 55, 0),
+                Duration::hours(1),
+                Duration::zero()
+            ),
+            Utc.ymd(2020, 3, 3).and_hms(0, 0, 0)
+        );
+    }
+}
@@ -14,50 +14,41 @@
 // - https://www.x.org/releases/X11R7.7/doc/xextproto/sync.html
 // - https://github.com/freedesktop/xorg-xserver/blob/7f962c70b6d9c346477f23f6c15211e749110078/Xext/sync.c
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 use super::InnerMsg;
-use crate::config::Config;
 use crate::prelude::*;
+use crate::x11::X11;
 
 const SLEEP_SECONDS: u64 = 20;
 const SLEEP_MILLISECONDS: u128 = (SLEEP_SECONDS as u128) * 1000;
 
 pub struct IdleDetector {
-    conn: xcb::Connection,
-    root_window: xcb::Window,
+    x11: X11,
     restart_wait_time_sender: Sender<InnerMsg>,
 }
 
 impl IdleDetector {
     pub fn new(restart_wait_time_sender: Sender<InnerMsg>) -> Self {
-        let (conn, screen_num) = xcb::Connection::connect(None).unwrap();
-        let setup: xcb::Setup = conn.get_setup();
-        let mut roots: xcb::ScreenIterator = setup.roots();
-        let preferred_screen_pos = usize::try_from(screen_num)
-            .expect("x11 preferred_screen is not positive");
-        let screen: xcb::Screen = roots.nth(preferred_screen_pos).unwrap();
-        let root_window: xcb::Window = screen.root();
-
         Self {
-            conn,
-            root_window,
+            x11: X11::connect(),
             restart_wait_time_sender,
         }
     }
 
     pub fn run(
-        config: &Config,
+        idle_detection_seconds: &Arc<AtomicU32>,
         idle_detection_enabled: &Arc<AtomicBool>,
         restart_wait_time_sender: Sender<InnerMsg>,
     ) -> ! {
         let idle_detector = Self::new(restart_wait_time_sender);
-        let idle_detection_milliseconds =
-            config.settings.idle_detection_seconds * 1000;
         loop {
+            let idle_detection_milliseconds =
+                u128::from(idle_detection_seconds.load(Ordering::Relaxed))
+                    * 1000;
             let time_before_sleep = SystemTime::now();
 
             let sleep_time_duration = Duration::from_secs(SLEEP_SECONDS);
@@ -77,14 +68,10 @@ impl IdleDetector {
             let suspend_milliseconds: u128 =
                 time_difference_milliseconds.saturating_sub(SLEEP_MILLISECONDS);
 
-            let idle_query_res = xcb::screensaver::query_info(
-                &idle_detector.conn,
-                idle_detector.root_window,
-            )
-            .get_reply()
-            .unwrap();
-
-            let ms_since_user_input = idle_query_res.ms_since_user_input();
+            let ms_since_user_input = idle_detector
+                .x11
+                .ms_since_user_input()
+                .expect("Could not query the screensaver info from X");
 
             println!(
                 "idle detector: ms_since_user_input: {}, suspend_milliseconds: {}, idle_detection_milliseconds: {}",
@@ -94,7 +81,7 @@ impl IdleDetector {
             );
 
             if has_been_idle(
-                idle_detection_milliseconds.into(),
+                idle_detection_milliseconds,
                 ms_since_user_input.into(),
                 suspend_milliseconds,
             ) {
@@ -102,9 +89,14 @@ impl IdleDetector {
                     println!(
                         "idle detector detected that we have been idle, so sending HasBeenIdle message",
                     );
+                    let idle_milliseconds =
+                        u128::from(ms_since_user_input) + suspend_milliseconds;
+                    let idle_time = Duration::from_millis(
+                        u64::try_from(idle_milliseconds).unwrap_or(u64::MAX),
+                    );
                     idle_detector
                         .restart_wait_time_sender
-                        .send(InnerMsg::HasBeenIdle).expect("TODO: figure out what to do about channels potentially failing");
+                        .send(InnerMsg::HasBeenIdle(idle_time)).expect("TODO: figure out what to do about channels potentially failing");
                 } else {
                     println!(
                         "idle detector detected that we have been idle, but idle_detection is not enable, so not sending HasBeenIdle message",
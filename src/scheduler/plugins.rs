@@ -1,8 +1,16 @@
+pub mod backoff;
+pub mod caldav;
+mod calendar;
 pub mod google_calendar;
+mod ics_calendar;
+pub mod microsoft_calendar;
 mod window_titles;
 
+pub use caldav::CalDav;
 pub use google_calendar::GoogleCalendar;
-pub use window_titles::WindowTitles;
+pub use ics_calendar::IcsCalendar;
+pub use microsoft_calendar::MicrosoftCalendar;
+pub use window_titles::{TopLevelWin, WindowTitles};
 
 #[derive(Copy, Clone, Debug)]
 pub enum CanBreak {
@@ -35,10 +43,37 @@ impl CanBreak {
     }
 }
 
+/// A time when the user is busy, like a meeting on their calendar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusyBlock {
+    pub start: chrono::DateTime<chrono::Utc>,
+    pub end: chrono::DateTime<chrono::Utc>,
+}
+
 pub trait Plugin {
     fn can_break_now(&self) -> Result<CanBreak, Box<dyn std::error::Error>>;
 
     fn name(&self) -> String;
+
+    /// Problems that the plugin is currently having, like an account that
+    /// can't be reached.  These are shown in the tray icon.
+    fn problems(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// The times between `start` and `end` when the user is busy.  Calendar
+    /// plugins return these so that breaks can be planned around meetings.
+    /// Plugins that don't know about the user's schedule return `None`.
+    ///
+    /// For a plugin that returns `Some`, a break can start whenever it
+    /// doesn't overlap any of the busy blocks.
+    fn busy_blocks(
+        &self,
+        _start: chrono::DateTime<chrono::Utc>,
+        _end: chrono::DateTime<chrono::Utc>,
+    ) -> Option<Vec<BusyBlock>> {
+        None
+    }
 }
 
 impl Plugin for Box<dyn Plugin> {
@@ -49,4 +84,16 @@ impl Plugin for Box<dyn Plugin> {
     fn name(&self) -> String {
         (**self).name()
     }
+
+    fn problems(&self) -> Vec<String> {
+        (**self).problems()
+    }
+
+    fn busy_blocks(
+        &self,
+        start: chrono::DateTime<chrono::Utc>,
+        end: chrono::DateTime<chrono::Utc>,
+    ) -> Option<Vec<BusyBlock>> {
+        (**self).busy_blocks(start, end)
+    }
 }
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+/// Exponential backoff for retrying things that can fail, like network
+/// requests.  Each call to `next` doubles the wait, up to `max`.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub const fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    /// Return how long to wait before the next retry.
+    pub fn next(&mut self) -> Duration {
+        let wait = self.current;
+        self.current = std::cmp::min(self.current * 2, self.max);
+        wait
+    }
+
+    /// Go back to the minimum wait, after something succeeded.
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff =
+            Backoff::new(Duration::from_secs(30), Duration::from_secs(100));
+
+        assert_eq!(backoff.next(), Duration::from_secs(30));
+        assert_eq!(backoff.next(), Duration::from_secs(60));
+        assert_eq!(backoff.next(), Duration::from_secs(100));
+        assert_eq!(backoff.next(), Duration::from_secs(100));
+
+        backoff.reset();
+        assert_eq!(backoff.next(), Duration::from_secs(30));
+    }
+}
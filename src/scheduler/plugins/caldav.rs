@@ -0,0 +1,820 @@
+mod client;
+
+use super::calendar::{self, ical, BusyEvent};
+use super::{BusyBlock, CanBreak, Plugin};
+
+use crate::config::{Config, PluginSettings};
+
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Utc};
+use hyper::Url;
+use serde::Deserialize;
+
+use client::{CalendarInfo, DavClient};
+
+/// How long the list of calendars found on the server is used before looking
+/// for calendars again, in case calendars were added or removed.
+const CALENDAR_LIST_REFRESH: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Debug, Deserialize)]
+struct CalDavAccountSettings {
+    /// The URL of the server.  This can be the root of the server, a
+    /// principal URL, a calendar home, or a single calendar.
+    url: String,
+    username: String,
+    /// The user's own email addresses.  These are used to find the user in
+    /// the attendee list of an event.
+    #[serde(default)]
+    emails: Vec<String>,
+    /// The display names (or URLs) of the calendars to check.  If this is
+    /// empty, all calendars are checked.
+    #[serde(default)]
+    calendars: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct CalDavSettings {
+    #[serde(default)]
+    accounts: Vec<CalDavAccountSettings>,
+}
+
+impl CalDavSettings {
+    fn from_plugin_settings(
+        plugin_settings: &PluginSettings,
+    ) -> Result<Self, ()> {
+        match plugin_settings.0.get("caldav") {
+            // If the "caldav" key doesn't exist, then just skip.
+            None => Ok(Self::default()),
+            Some(val) => val.clone().try_into().map_err(|_err| ()),
+        }
+    }
+}
+
+/// The file that holds the password for an account.
+fn password_path(
+    break_time_cache_dir: &Path,
+    url: &Url,
+    username: &str,
+) -> PathBuf {
+    let host = url.host_str().unwrap_or("localhost");
+    let file_name = match url.port() {
+        None => format!("{}@{}", username, host),
+        Some(port) => format!("{}@{}:{}", username, host, port),
+    };
+    break_time_cache_dir
+        .join("caldav")
+        .join(file_name.replace('/', "_"))
+}
+
+/// Read the password for an account, stored by `break-time caldav login`,
+/// and when the file was last modified.
+fn load_password(password_path: &Path) -> Option<(SystemTime, String)> {
+    let modified = std::fs::metadata(password_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    std::fs::read_to_string(password_path)
+        .ok()
+        .map(|password| (modified, password.trim_end_matches('\n').to_string()))
+}
+
+/// Read a line from the terminal without showing what is typed.  Echo is
+/// turned off with `stty` while reading.  If stdin isn't a terminal, the
+/// line is read as is.
+fn read_password() -> std::io::Result<String> {
+    let stty = |arg: &str| {
+        Command::new("stty")
+            .arg(arg)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::null())
+            .status()
+            .map_or(false, |status| status.success())
+    };
+    let echo_off = stty("-echo");
+    let mut password = String::new();
+    let res = std::io::stdin().read_line(&mut password);
+    if echo_off {
+        stty("echo");
+        // The newline that was typed wasn't shown either.
+        println!();
+    }
+    res.map(|_| password.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Store the password for an account, readable only by the user.
+fn store_password(password_path: &Path, password: &str) -> std::io::Result<()> {
+    if let Some(parent) = password_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut password_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(password_path)?;
+    password_file.write_all(password.as_bytes())
+}
+
+struct CalDavAccount {
+    url: Url,
+    username: String,
+    password_path: PathBuf,
+    emails: Vec<String>,
+    calendar_names: Vec<String>,
+    // The client is created once the password has been stored with
+    // `break-time caldav login`, and created again when the password file
+    // changes, so that logging in doesn't need a restart.  It is kept with
+    // the modification time of the password file.
+    client: Mutex<Option<(SystemTime, Arc<DavClient>)>>,
+    // The calendars are discovered the first time they are needed, so that
+    // an unreachable server doesn't stop break-time from starting, and again
+    // every `CALENDAR_LIST_REFRESH`.  They are kept with when they were found.
+    calendars: Mutex<Option<(Instant, Vec<CalendarInfo>)>>,
+    /// Why this account isn't being checked, if it isn't.
+    problem: Mutex<Option<String>>,
+}
+
+impl CalDavAccount {
+    fn new(
+        break_time_cache_dir: &Path,
+        settings: CalDavAccountSettings,
+    ) -> Result<Self, ()> {
+        let url = Url::parse(&settings.url).map_err(|url_err| {
+            println!(
+                "CalDAV Plugin: Could not parse url {}: {}",
+                settings.url, url_err
+            );
+        })?;
+
+        Ok(Self {
+            password_path: password_path(
+                break_time_cache_dir,
+                &url,
+                &settings.username,
+            ),
+            url,
+            username: settings.username,
+            emails: settings
+                .emails
+                .iter()
+                .map(|email| email.to_lowercase())
+                .collect(),
+            calendar_names: settings.calendars,
+            client: Mutex::new(None),
+            calendars: Mutex::new(None),
+            problem: Mutex::new(None),
+        })
+    }
+
+    fn set_problem(&self, problem: Option<String>) {
+        *self
+            .problem
+            .lock()
+            .expect("CalDAV Plugin: problem mutex was poisoned") = problem;
+    }
+
+    fn problem(&self) -> Option<String> {
+        self.problem
+            .lock()
+            .expect("CalDAV Plugin: problem mutex was poisoned")
+            .clone()
+    }
+
+    fn is_selected(&self, calendar: &CalendarInfo) -> bool {
+        self.calendar_names.is_empty()
+            || self.calendar_names.iter().any(|name| {
+                calendar.display_name.as_ref() == Some(name)
+                    || calendar.url.as_str() == name
+                    || calendar.url.path().trim_end_matches('/')
+                        == name.trim_end_matches('/')
+            })
+    }
+
+    fn calendars(
+        &self,
+        client: &DavClient,
+    ) -> Result<Vec<CalendarInfo>, CalDavErr> {
+        let mut opt_calendars = self
+            .calendars
+            .lock()
+            .expect("CalDAV Plugin: calendars mutex was poisoned");
+
+        let now = Instant::now();
+        let stale_calendars = match &*opt_calendars {
+            Some((found_at, calendars))
+                if now.saturating_duration_since(*found_at)
+                    < CALENDAR_LIST_REFRESH =>
+            {
+                return Ok(calendars.clone());
+            }
+            Some((_, calendars)) => Some(calendars.clone()),
+            None => None,
+        };
+
+        let calendars: Vec<CalendarInfo> =
+            match (client.discover_calendars(&self.url), stale_calendars) {
+                (Err(err), Some(stale_calendars)) => {
+                    println!("{}, checking the calendars found before", err);
+                    *opt_calendars = Some((now, stale_calendars.clone()));
+                    return Ok(stale_calendars);
+                }
+                (Err(err), None) => return Err(err),
+                (Ok(calendars), _) => calendars
+                    .into_iter()
+                    .filter(|calendar| self.is_selected(calendar))
+                    .collect(),
+            };
+
+        println!(
+            "CalDAV Plugin: Found calendars for {}: {:?}",
+            self.url,
+            calendars
+                .iter()
+                .map(|calendar| calendar.url.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        // Look again next time if nothing was found, in case the server or
+        // the settings get fixed.
+        *opt_calendars = if calendars.is_empty() {
+            None
+        } else {
+            Some((now, calendars.clone()))
+        };
+        Ok(calendars)
+    }
+
+    /// The client for this account, if its password has been stored.  The
+    /// password is read again when the file it is stored in changes.
+    fn client(&self) -> Option<Arc<DavClient>> {
+        let mut opt_client = self
+            .client
+            .lock()
+            .expect("CalDAV Plugin: client mutex was poisoned");
+        let opt_modified = std::fs::metadata(&self.password_path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let is_fresh = match (&*opt_client, opt_modified) {
+            (Some((client_modified, _)), Some(modified)) => {
+                *client_modified == modified
+            }
+            _ => false,
+        };
+        if !is_fresh {
+            *opt_client = load_password(&self.password_path).map(
+                |(modified, password)| {
+                    (
+                        modified,
+                        Arc::new(DavClient::new(
+                            self.username.clone(),
+                            password,
+                        )),
+                    )
+                },
+            );
+            // The calendars that can be seen may have changed with the
+            // password.
+            *self
+                .calendars
+                .lock()
+                .expect("CalDAV Plugin: calendars mutex was poisoned") = None;
+        }
+        opt_client.as_ref().map(|(_, client)| Arc::clone(client))
+    }
+
+    /// The events from `start_time` to `end_time` on the selected calendars
+    /// that should stop a break from happening.  Returns `None` if no
+    /// password has been stored for the account.
+    fn busy_events(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Option<Vec<BusyEvent>>, CalDavErr> {
+        let client = match self.client() {
+            None => {
+                self.set_problem(Some(format!(
+                    "CalDAV Plugin: No password is stored for {} on {}.  Run `break-time caldav login {}` to store one.",
+                    self.username, self.url, self.username
+                )));
+                return Ok(None);
+            }
+            Some(client) => client,
+        };
+
+        let calendars = self.calendars(&client)?;
+        self.set_problem(if calendars.is_empty() {
+            Some(format!(
+                "CalDAV Plugin: No calendars to check were found for {} on {}",
+                self.username, self.url
+            ))
+        } else {
+            None
+        });
+
+        let mut busy_events = vec![];
+
+        for cal in calendars {
+            let calendar_datas =
+                client.calendar_query(&cal.url, start_time, end_time)?;
+
+            for calendar_data in calendar_datas {
+                let events = ical::parse_events(&calendar_data).map_err(
+                    |parse_err| CalDavErr::Parsing {
+                        url: cal.url.to_string(),
+                        parse_err,
+                    },
+                )?;
+
+                for occurrence in
+                    ical::occurrences_between(&events, start_time, end_time)
+                {
+                    if calendar::filter_ical_event(
+                        occurrence.event,
+                        &self.emails,
+                    ) {
+                        busy_events.push(BusyEvent::new(
+                            &cal.url,
+                            occurrence.event.summary.clone(),
+                            occurrence.start,
+                            occurrence.end,
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(Some(busy_events))
+    }
+
+    fn can_break(&self) -> Result<CanBreak, CalDavErr> {
+        let (start_time, end_time) =
+            calendar::break_check_window(chrono::Utc::now());
+        Ok(self
+            .busy_events(start_time, end_time)?
+            .map_or(CanBreak::Yes, |busy_events| {
+                calendar::can_break_with(&busy_events)
+            }))
+    }
+}
+
+pub struct CalDav {
+    accounts: Vec<CalDavAccount>,
+}
+
+impl CalDav {
+    pub fn new(config: &Config) -> Result<Self, ()> {
+        let settings = CalDavSettings::from_plugin_settings(
+            &config.settings.all_plugin_settings,
+        )?;
+
+        let accounts = settings
+            .accounts
+            .into_iter()
+            .map(|account_settings| {
+                CalDavAccount::new(&config.cache_dir, account_settings)
+            })
+            .collect::<Result<Vec<_>, ()>>()?;
+
+        Ok(Self { accounts })
+    }
+
+    fn can_break(&self) -> Result<CanBreak, CalDavErr> {
+        self.accounts.iter().map(CalDavAccount::can_break).fold(
+            Ok(CanBreak::Yes),
+            |accum, can_break_res| match (accum, can_break_res) {
+                (Err(err), _) => Err(err),
+                (_, Err(err)) => Err(err),
+                (Ok(CanBreak::No), _) => Ok(CanBreak::No),
+                (_, can_break) => can_break,
+            },
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum CalDavErr {
+    Http {
+        url: String,
+        http_err: hyper::Error,
+    },
+    Status {
+        url: String,
+        status: hyper::status::StatusCode,
+    },
+    Io {
+        url: String,
+        io_err: std::io::Error,
+    },
+    Xml {
+        url: String,
+        xml_err: roxmltree::Error,
+    },
+    Url {
+        url: String,
+        url_err: hyper::error::ParseError,
+    },
+    Parsing {
+        url: String,
+        parse_err: ical::ParseErr,
+    },
+}
+
+impl std::error::Error for CalDavErr {}
+
+impl std::fmt::Display for CalDavErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Http { url, http_err } => write!(
+                f,
+                "CalDAV Plugin: Error sending request to {}: {}",
+                url, http_err
+            ),
+            Self::Status { url, status } => write!(
+                f,
+                "CalDAV Plugin: Unexpected response status from {}: {}",
+                url, status
+            ),
+            Self::Io { url, io_err } => write!(
+                f,
+                "CalDAV Plugin: Error reading response from {}: {}",
+                url, io_err
+            ),
+            Self::Xml { url, xml_err } => write!(
+                f,
+                "CalDAV Plugin: Error parsing XML response from {}: {}",
+                url, xml_err
+            ),
+            Self::Url { url, url_err } => write!(
+                f,
+                "CalDAV Plugin: Error parsing url {}: {}",
+                url, url_err
+            ),
+            Self::Parsing { url, parse_err } => write!(
+                f,
+                "CalDAV Plugin: Error parsing calendar data from {}: {}",
+                url, parse_err
+            ),
+        }
+    }
+}
+
+impl Plugin for CalDav {
+    fn can_break_now(&self) -> Result<CanBreak, Box<dyn std::error::Error>> {
+        self.can_break().map_err(|caldav_err| {
+            Box::new(caldav_err) as Box<dyn std::error::Error>
+        })
+    }
+
+    fn name(&self) -> String {
+        String::from("caldav")
+    }
+
+    fn problems(&self) -> Vec<String> {
+        self.accounts
+            .iter()
+            .filter_map(CalDavAccount::problem)
+            .collect()
+    }
+
+    fn busy_blocks(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Option<Vec<BusyBlock>> {
+        calendar::combine_busy_blocks(
+            self.accounts
+                .iter()
+                .map(|account| account.busy_events(start, end)),
+        )
+    }
+}
+
+fn read_settings(config: &Config) -> CalDavSettings {
+    CalDavSettings::from_plugin_settings(&config.settings.all_plugin_settings)
+        .expect("Could not parse the plugin.caldav settings")
+}
+
+/// Find the configured account for `username`, on the server at `opt_url` if
+/// there is more than one.
+fn find_account_settings(
+    settings: CalDavSettings,
+    username: &str,
+    opt_url: Option<&str>,
+) -> Result<CalDavAccountSettings, String> {
+    let mut accounts: Vec<CalDavAccountSettings> = settings
+        .accounts
+        .into_iter()
+        .filter(|account| {
+            account.username == username
+                && opt_url.map_or(true, |url| account.url == url)
+        })
+        .collect();
+    match accounts.len() {
+        0 => Err(format!(
+            "There is no account for {} in plugin.caldav.accounts in your config file.",
+            username
+        )),
+        1 => Ok(accounts.remove(0)),
+        _ => Err(format!(
+            "There is more than one account for {}.  Pick one with --url.",
+            username
+        )),
+    }
+}
+
+/// Ask for the password of the account for `username` on the terminal, check
+/// that it works, and store it for the plugin to use.
+pub fn login(config: &Config, username: &str, opt_url: Option<&str>) {
+    let account_settings =
+        match find_account_settings(read_settings(config), username, opt_url) {
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+            Ok(account_settings) => account_settings,
+        };
+    let account = match CalDavAccount::new(&config.cache_dir, account_settings)
+    {
+        Err(()) => return,
+        Ok(account) => account,
+    };
+
+    println!(
+        "Please enter the password (or app password) for {} on {}:",
+        account.username, account.url
+    );
+    let password = match read_password() {
+        Err(err) => {
+            println!("Could not read the password: {}", err);
+            return;
+        }
+        Ok(password) => password,
+    };
+    if password.is_empty() {
+        println!("No password entered, so nothing was stored.");
+        return;
+    }
+
+    let client = DavClient::new(account.username.clone(), password.clone());
+    match account.calendars(&client) {
+        Err(err) => {
+            println!("{}", err);
+            println!("The password was not stored.");
+            return;
+        }
+        Ok(calendars) if calendars.is_empty() => {
+            println!(
+                "Warning: no calendars to check were found at {}.  Check the url and calendars settings.",
+                account.url
+            );
+        }
+        Ok(_) => (),
+    }
+
+    match store_password(&account.password_path, &password) {
+        Err(err) => println!(
+            "Could not store the password in {:?}: {}",
+            account.password_path, err
+        ),
+        Ok(()) => println!(
+            "Stored the password in {:?}.  A running break-time starts using it at the next check.",
+            account.password_path
+        ),
+    }
+}
+
+/// Delete the stored password of the account for `username`.
+pub fn logout(config: &Config, username: &str, opt_url: Option<&str>) {
+    let account_settings =
+        match find_account_settings(read_settings(config), username, opt_url) {
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+            Ok(account_settings) => account_settings,
+        };
+    let account = match CalDavAccount::new(&config.cache_dir, account_settings)
+    {
+        Err(()) => return,
+        Ok(account) => account,
+    };
+    match std::fs::remove_file(&account.password_path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            println!("No password is stored for {}.", username);
+        }
+        Err(err) => {
+            println!("Could not delete {:?}: {}", account.password_path, err);
+        }
+        Ok(()) => {
+            println!("Deleted the stored password for {}.", username);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// Answer one request with each of `bodies` in turn, as a `207
+    /// Multi-Status` response, and send back each request, with its body.
+    fn serve(listener: TcpListener, bodies: Vec<String>) -> Receiver<String> {
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            for body in bodies {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                    if let Some(length) =
+                        line.to_lowercase().strip_prefix("content-length:")
+                    {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                }
+                let mut request_body = vec![0; content_length];
+                reader.read_exact(&mut request_body).unwrap();
+                request.push_str(&String::from_utf8(request_body).unwrap());
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 207 Multi-Status\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+                sender.send(request).unwrap();
+            }
+        });
+        receiver
+    }
+
+    /// Create an account for the calendar at `url`, with the password stored
+    /// in a cache directory named after `test_name`.
+    fn test_account(test_name: &str, url: &str) -> (PathBuf, CalDavAccount) {
+        let cache_dir = std::env::temp_dir().join(format!(
+            "break-time-caldav-{}-{}",
+            test_name,
+            std::process::id()
+        ));
+        let settings = CalDavAccountSettings {
+            url: String::from(url),
+            username: String::from("me"),
+            emails: vec![String::from("Me@example.com")],
+            calendars: vec![],
+        };
+        let account = CalDavAccount::new(&cache_dir, settings).unwrap();
+        store_password(&account.password_path, "secret").unwrap();
+        (cache_dir, account)
+    }
+
+    fn event(uid: &str, start: &str, end: &str, extra: &str) -> String {
+        format!(
+            "BEGIN:VEVENT\r\n\
+             UID:{}\r\n\
+             SUMMARY:{}\r\n\
+             DTSTART:{}\r\n\
+             DTEND:{}\r\n\
+             {}\
+             END:VEVENT\r\n",
+            uid, uid, start, end, extra
+        )
+    }
+
+    #[test]
+    fn test_busy_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/me/work/", listener.local_addr().unwrap());
+        let calendar = r#"<?xml version="1.0"?>
+<D:multistatus xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:response>
+    <D:href>/me/work/</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:resourcetype><D:collection/><C:calendar/></D:resourcetype>
+        <D:displayname>Work</D:displayname>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+</D:multistatus>"#;
+        let calendar_data = format!(
+            "BEGIN:VCALENDAR\r\n{}{}{}{}END:VCALENDAR\r\n",
+            event("Meeting", "20200302T101500Z", "20200302T104500Z", ""),
+            event(
+                "Cancelled",
+                "20200302T100000Z",
+                "20200302T110000Z",
+                "STATUS:CANCELLED\r\n"
+            ),
+            event(
+                "Declined",
+                "20200302T100000Z",
+                "20200302T110000Z",
+                "ATTENDEE;PARTSTAT=DECLINED:mailto:me@example.com\r\n"
+            ),
+            event(
+                "Focus",
+                "20200302T100000Z",
+                "20200302T110000Z",
+                "DESCRIPTION:ignore break-time\r\n"
+            ),
+        );
+        let report = format!(
+            r#"<?xml version="1.0"?>
+<D:multistatus xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:response>
+    <D:href>/me/work/events.ics</D:href>
+    <D:propstat>
+      <D:prop><C:calendar-data>{}</C:calendar-data></D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+</D:multistatus>"#,
+            calendar_data
+        );
+        let requests = serve(listener, vec![String::from(calendar), report]);
+        let (cache_dir, account) = test_account("busy-events", &url);
+
+        let res = account.busy_events(
+            Utc.ymd(2020, 3, 2).and_hms(10, 0, 0),
+            Utc.ymd(2020, 3, 2).and_hms(11, 0, 0),
+        );
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+
+        let busy_events = res.unwrap().unwrap();
+        assert_eq!(busy_events.len(), 1);
+        assert_eq!(busy_events[0].summary.as_deref(), Some("Meeting"));
+        assert_eq!(
+            busy_events[0].block,
+            BusyBlock {
+                start: Utc.ymd(2020, 3, 2).and_hms(10, 15, 0),
+                end: Utc.ymd(2020, 3, 2).and_hms(10, 45, 0),
+            }
+        );
+
+        let propfind = requests.recv().unwrap();
+        assert!(propfind.starts_with("PROPFIND /me/work/ "), "{}", propfind);
+        assert!(propfind.contains("Depth: 0"), "{}", propfind);
+        // "me:secret", base64 encoded.
+        assert!(
+            propfind.contains("Authorization: Basic bWU6c2VjcmV0"),
+            "{}",
+            propfind
+        );
+        let report = requests.recv().unwrap();
+        assert!(report.starts_with("REPORT /me/work/ "), "{}", report);
+        assert!(report.contains("Depth: 1"), "{}", report);
+        assert!(
+            report.contains(
+                r#"<C:time-range start="20200302T100000Z" end="20200302T110000Z"/>"#
+            ),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn test_client_reloads_changed_password() {
+        let (cache_dir, account) =
+            test_account("reload-password", "http://localhost/me/");
+        let set_modified = |secs| {
+            std::fs::File::options()
+                .write(true)
+                .open(&account.password_path)
+                .unwrap()
+                .set_modified(
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+                )
+                .unwrap();
+        };
+        set_modified(1_583_000_000);
+
+        let client = account.client().unwrap();
+        let unchanged = account.client().unwrap();
+        store_password(&account.password_path, "new secret").unwrap();
+        set_modified(1_583_000_060);
+        let changed = account.client().unwrap();
+        std::fs::remove_file(&account.password_path).unwrap();
+        let removed = account.client();
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+
+        assert!(Arc::ptr_eq(&client, &unchanged));
+        assert!(!Arc::ptr_eq(&client, &changed));
+        assert!(removed.is_none());
+    }
+}
@@ -0,0 +1,329 @@
+// This module is a minimal CalDAV (RFC 4791) client.  It knows how to find
+// the calendars for a user with PROPFIND, and how to fetch the events in a
+// time window with a calendar-query REPORT.
+
+use super::CalDavErr;
+
+use std::io::Read;
+
+use chrono::{DateTime, Utc};
+use hyper::header::{Authorization, Basic, Headers};
+use hyper::Url;
+
+const DAV_NS: &str = "DAV:";
+const CALDAV_NS: &str = "urn:ietf:params:xml:ns:caldav";
+
+const CURRENT_USER_PRINCIPAL_BODY: &str = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propfind xmlns:D="DAV:">
+  <D:prop><D:current-user-principal/></D:prop>
+</D:propfind>"#;
+
+const CALENDAR_HOME_SET_BODY: &str = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propfind xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop><C:calendar-home-set/></D:prop>
+</D:propfind>"#;
+
+const CALENDARS_BODY: &str = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propfind xmlns:D="DAV:">
+  <D:prop><D:resourcetype/><D:displayname/></D:prop>
+</D:propfind>"#;
+
+#[derive(Clone, Debug)]
+pub struct CalendarInfo {
+    pub url: Url,
+    pub display_name: Option<String>,
+}
+
+pub struct DavClient {
+    http_client: hyper::Client,
+    username: String,
+    password: String,
+}
+
+impl DavClient {
+    pub fn new(username: String, password: String) -> Self {
+        let http_client: hyper::Client = hyper::Client::with_connector(
+            hyper::net::HttpsConnector::new(hyper_rustls::TlsClient::new()),
+        );
+
+        Self {
+            http_client,
+            username,
+            password,
+        }
+    }
+
+    /// Send a `PROPFIND` or `REPORT` request, and return the body of the
+    /// response.  Only a `207 Multi-Status` response is treated as a success.
+    fn request(
+        &self,
+        method: &str,
+        url: &Url,
+        depth: &str,
+        body: &str,
+    ) -> Result<String, CalDavErr> {
+        let mut headers = Headers::new();
+        headers.set(Authorization(Basic {
+            username: self.username.clone(),
+            password: Some(self.password.clone()),
+        }));
+        headers.set_raw("Depth", vec![depth.as_bytes().to_vec()]);
+        headers.set_raw(
+            "Content-Type",
+            vec![b"application/xml; charset=utf-8".to_vec()],
+        );
+
+        let mut response = self
+            .http_client
+            .request(
+                hyper::method::Method::Extension(String::from(method)),
+                url.clone(),
+            )
+            .headers(headers)
+            .body(body)
+            .send()
+            .map_err(|http_err| CalDavErr::Http {
+                url: url.to_string(),
+                http_err,
+            })?;
+
+        if response.status != hyper::status::StatusCode::MultiStatus {
+            return Err(CalDavErr::Status {
+                url: url.to_string(),
+                status: response.status,
+            });
+        }
+
+        let mut response_body = String::new();
+        response
+            .read_to_string(&mut response_body)
+            .map_err(|io_err| CalDavErr::Io {
+                url: url.to_string(),
+                io_err,
+            })?;
+
+        Ok(response_body)
+    }
+
+    /// Find the URL of a property that contains a single `<D:href>`, like
+    /// `current-user-principal` or `calendar-home-set`.
+    fn find_href_prop(
+        &self,
+        url: &Url,
+        body: &str,
+        prop: (&str, &str),
+    ) -> Result<Option<Url>, CalDavErr> {
+        let response_body = self.request("PROPFIND", url, "0", body)?;
+        let doc = parse_xml(url, &response_body)?;
+
+        let opt_href = doc
+            .descendants()
+            .find(|node| node.has_tag_name(prop))
+            .and_then(|prop_node| {
+                prop_node
+                    .descendants()
+                    .find(|node| node.has_tag_name((DAV_NS, "href")))
+            })
+            .and_then(|href_node| href_node.text());
+
+        match opt_href {
+            None => Ok(None),
+            Some(href) => join_url(url, href).map(Some),
+        }
+    }
+
+    /// Find all the calendars that can be reached from `url`.  `url` can be
+    /// the root of the server, a principal URL, a calendar home, or a
+    /// single calendar.
+    pub fn discover_calendars(
+        &self,
+        url: &Url,
+    ) -> Result<Vec<CalendarInfo>, CalDavErr> {
+        // A URL that points at a calendar is the only calendar to check.
+        let response_body =
+            self.request("PROPFIND", url, "0", CALENDARS_BODY)?;
+        let calendars = calendars_from_multistatus(url, &response_body)?;
+        if !calendars.is_empty() {
+            return Ok(calendars);
+        }
+
+        let principal_url = self
+            .find_href_prop(
+                url,
+                CURRENT_USER_PRINCIPAL_BODY,
+                (DAV_NS, "current-user-principal"),
+            )?
+            .unwrap_or_else(|| url.clone());
+
+        let home_url = self
+            .find_href_prop(
+                &principal_url,
+                CALENDAR_HOME_SET_BODY,
+                (CALDAV_NS, "calendar-home-set"),
+            )?
+            .unwrap_or(principal_url);
+
+        let response_body =
+            self.request("PROPFIND", &home_url, "1", CALENDARS_BODY)?;
+        calendars_from_multistatus(&home_url, &response_body)
+    }
+
+    /// Return the iCalendar data for every calendar object in `calendar`
+    /// that has an event overlapping the `start_time` to `end_time` window.
+    pub fn calendar_query(
+        &self,
+        calendar: &Url,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<String>, CalDavErr> {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop><C:calendar-data/></D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        <C:time-range start="{}" end="{}"/>
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#,
+            start_time.format("%Y%m%dT%H%M%SZ"),
+            end_time.format("%Y%m%dT%H%M%SZ"),
+        );
+
+        let response_body = self.request("REPORT", calendar, "1", &body)?;
+        let doc = parse_xml(calendar, &response_body)?;
+
+        let calendar_datas = doc
+            .descendants()
+            .filter(|node| node.has_tag_name((CALDAV_NS, "calendar-data")))
+            .filter_map(|node| node.text())
+            .map(String::from)
+            .collect();
+
+        Ok(calendar_datas)
+    }
+}
+
+/// Find all the calendars listed in the response to a `PROPFIND` of a
+/// calendar home, or of a single calendar.
+fn calendars_from_multistatus(
+    home_url: &Url,
+    response_body: &str,
+) -> Result<Vec<CalendarInfo>, CalDavErr> {
+    let doc = parse_xml(home_url, response_body)?;
+
+    let mut calendars = vec![];
+
+    for response in doc
+        .descendants()
+        .filter(|node| node.has_tag_name((DAV_NS, "response")))
+    {
+        let is_calendar = response
+            .descendants()
+            .any(|node| node.has_tag_name((CALDAV_NS, "calendar")));
+
+        let opt_href = response
+            .children()
+            .find(|node| node.has_tag_name((DAV_NS, "href")))
+            .and_then(|node| node.text());
+
+        if let (true, Some(href)) = (is_calendar, opt_href) {
+            let display_name = response
+                .descendants()
+                .find(|node| node.has_tag_name((DAV_NS, "displayname")))
+                .and_then(|node| node.text())
+                .map(String::from);
+
+            calendars.push(CalendarInfo {
+                url: join_url(home_url, href)?,
+                display_name,
+            });
+        }
+    }
+
+    Ok(calendars)
+}
+
+fn parse_xml<'a>(
+    url: &Url,
+    response_body: &'a str,
+) -> Result<roxmltree::Document<'a>, CalDavErr> {
+    roxmltree::Document::parse(response_body).map_err(|xml_err| {
+        CalDavErr::Xml {
+            url: url.to_string(),
+            xml_err,
+        }
+    })
+}
+
+/// Resolve an `href` from a response relative to the URL of the request.
+fn join_url(base: &Url, href: &str) -> Result<Url, CalDavErr> {
+    base.join(href.trim()).map_err(|url_err| CalDavErr::Url {
+        url: href.to_string(),
+        url_err,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calendars_from_multistatus() {
+        let home_url = Url::parse("http://localhost:5232/me/").unwrap();
+        let response_body = r#"<?xml version="1.0"?>
+<multistatus xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <response>
+    <href>/me/</href>
+    <propstat>
+      <prop><resourcetype><collection/></resourcetype></prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+  <response>
+    <href>/me/work/</href>
+    <propstat>
+      <prop>
+        <resourcetype><collection/><C:calendar/></resourcetype>
+        <displayname>Work</displayname>
+      </prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+</multistatus>"#;
+
+        let calendars =
+            calendars_from_multistatus(&home_url, response_body).unwrap();
+
+        assert_eq!(calendars.len(), 1);
+        assert_eq!(calendars[0].url.as_str(), "http://localhost:5232/me/work/");
+        assert_eq!(calendars[0].display_name.as_deref(), Some("Work"));
+    }
+
+    #[test]
+    fn test_calendars_from_multistatus_single_calendar() {
+        let calendar_url =
+            Url::parse("http://localhost:5232/me/work/").unwrap();
+        let response_body = r#"<?xml version="1.0"?>
+<D:multistatus xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:response>
+    <D:href>/me/work/</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:resourcetype><D:collection/><C:calendar/></D:resourcetype>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+</D:multistatus>"#;
+
+        let calendars =
+            calendars_from_multistatus(&calendar_url, response_body).unwrap();
+
+        assert_eq!(calendars.len(), 1);
+        assert_eq!(calendars[0].url, calendar_url);
+        assert_eq!(calendars[0].display_name, None);
+    }
+}
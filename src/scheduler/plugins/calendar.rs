@@ -0,0 +1,235 @@
+// This module holds logic that is shared between all the calendar plugins
+// (Google Calendar, local ICS files, etc).
+
+pub mod ical;
+
+use super::BusyBlock;
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+/// How far in the past to look for events when deciding whether or not we can
+/// break.  An event that ended a few minutes ago may have run over.
+pub const LOOK_BEHIND_MINUTES: i64 = 10;
+
+/// How far in the future to look for events when deciding whether or not we
+/// can break.  There is no point starting a break if a meeting is about to
+/// start.
+pub const LOOK_AHEAD_MINUTES: i64 = 20;
+
+/// An event that should stop a break from happening, and where it came from.
+#[derive(Clone, Debug)]
+pub struct BusyEvent {
+    /// The file, calendar, or account that the event is from.
+    pub source: String,
+    pub summary: Option<String>,
+    pub block: BusyBlock,
+}
+
+impl BusyEvent {
+    pub fn new(
+        source: &impl std::fmt::Display,
+        summary: Option<String>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            source: source.to_string(),
+            summary,
+            block: BusyBlock { start, end },
+        }
+    }
+
+    pub fn log(&self) {
+        println!(
+            "There was an event from {}: {:?} ({} to {})",
+            self.source,
+            self.summary,
+            self.block.start.to_rfc3339(),
+            self.block.end.to_rfc3339(),
+        );
+    }
+}
+
+/// Whether a break can start now, given the events in the
+/// `break_check_window`.  Each of the events is logged.
+pub fn can_break_with(busy_events: &[BusyEvent]) -> super::CanBreak {
+    for busy_event in busy_events {
+        busy_event.log();
+    }
+    super::CanBreak::from_bool(busy_events.is_empty())
+}
+
+/// Combine the busy events of each account into one list of busy blocks.
+/// Accounts that return `None` aren't signed in, and are skipped.  If any
+/// account can't be checked, the error is logged and `None` is returned, so
+/// that `can_break_now` reports it instead.
+pub fn combine_busy_blocks<E: std::fmt::Display>(
+    account_results: impl Iterator<Item = Result<Option<Vec<BusyEvent>>, E>>,
+) -> Option<Vec<BusyBlock>> {
+    let mut busy_blocks = vec![];
+    for account_result in account_results {
+        match account_result {
+            Err(err) => {
+                println!("{}", err);
+                return None;
+            }
+            Ok(opt_busy_events) => busy_blocks.extend(
+                opt_busy_events
+                    .into_iter()
+                    .flatten()
+                    .map(|busy_event| busy_event.block),
+            ),
+        }
+    }
+    Some(busy_blocks)
+}
+
+/// Expand a leading `~` in a path to the user's home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Return the `(start, end)` time window that calendar plugins check for
+/// events in.  If there are any events in this window, a break shouldn't
+/// start.
+pub fn break_check_window(
+    now: DateTime<Utc>,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    check_window(now, LOOK_BEHIND_MINUTES, LOOK_AHEAD_MINUTES)
+}
+
+/// Like `break_check_window`, but with a custom look-behind and look-ahead.
+pub fn check_window(
+    now: DateTime<Utc>,
+    look_behind_minutes: i64,
+    look_ahead_minutes: i64,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = now - chrono::Duration::minutes(look_behind_minutes);
+    let end = now + chrono::Duration::minutes(look_ahead_minutes);
+    (start, end)
+}
+
+/// Return `true` if an event description says that break-time should ignore
+/// the event.
+pub fn is_ignored_by_description(desc: &str) -> bool {
+    let desc = desc.to_lowercase();
+
+    // Ignore events where the description contains the magic string
+    // "ignore break-time"
+    if desc.contains("ignore break-time") {
+        return true;
+    }
+
+    // Ignore events where the description talks about being an out-of-office event.
+    // Even if we are out-of-office, we still may be on our personal computer, and
+    // want break-time to occassionally break.
+    desc.contains("out-of-office event")
+}
+
+/// Return `true` if an iCalendar `event` should stop a break from happening.
+///
+/// `emails` are the user's own (lowercased) email addresses.  They are used to
+/// find the user in the event's attendee list.
+pub fn filter_ical_event(event: &ical::Event, emails: &[String]) -> bool {
+    if let Some(desc) = &event.description {
+        if is_ignored_by_description(desc) {
+            return false;
+        }
+    }
+
+    // Ignore cancelled events.
+    if event.status.as_deref() == Some("CANCELLED") {
+        return false;
+    }
+
+    // Ignore events that are marked as "free" instead of "busy".
+    if event.transparent {
+        return false;
+    }
+
+    // Ignore events where you are one of the attendees, and you have
+    // declined.
+    let declined = event.attendees.iter().any(|attendee| {
+        emails.contains(&attendee.address.to_lowercase())
+            && attendee.partstat.as_deref() == Some("DECLINED")
+    });
+
+    !declined
+}
+
+/// Return `true` if `text` matches `pattern`, ignoring case.  In `pattern`, `*`
+/// matches any number of characters, and `?` matches a single character.
+/// Everything else has to match exactly.
+pub fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let mut p = 0;
+    let mut t = 0;
+    // The position of the last `*` in the pattern, and the position in the
+    // text that it is currently matched up to.
+    let mut last_star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = last_star {
+            // Let the last `*` match one more character, and try again.
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("lunch", "Lunch"));
+        assert!(matches_pattern("*lunch*", "Team LUNCH at noon"));
+        assert!(matches_pattern("1:1 with ?ob", "1:1 with Bob"));
+        assert!(matches_pattern("*", ""));
+        assert!(!matches_pattern("lunch", "Lunch with Bob"));
+        assert!(!matches_pattern("*lunch", "Lunch with Bob"));
+        assert!(!matches_pattern("?", ""));
+    }
+
+    #[test]
+    fn test_combine_busy_blocks() {
+        let start = Utc::now();
+        let end = start + chrono::Duration::minutes(30);
+        let busy_event = BusyEvent::new(&"me@example.com", None, start, end);
+
+        let ok: Vec<Result<_, String>> = vec![
+            Ok(Some(vec![busy_event.clone()])),
+            Ok(None),
+            Ok(Some(vec![])),
+        ];
+        assert_eq!(
+            combine_busy_blocks(ok.into_iter()),
+            Some(vec![BusyBlock { start, end }])
+        );
+
+        let failed = vec![
+            Ok(Some(vec![busy_event])),
+            Err(String::from("could not reach the server")),
+        ];
+        assert_eq!(combine_busy_blocks(failed.into_iter()), None);
+    }
+}
@@ -0,0 +1,921 @@
+// This module is a small iCalendar (RFC 5545) reader.  It only understands
+// the parts of the format that the calendar plugins need: VEVENTs, their
+// start and end times, and the most common recurrence rules.
+//
+// Time zones are handled in a simplified way.  Times ending in `Z` are UTC.
+// All other times (including times with a `TZID` parameter) are treated as
+// being in the local time zone.  This is correct for the overwhelmingly common
+// case of a calendar that was exported on the same machine it is being read
+// on.
+
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone,
+    Utc, Weekday,
+};
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+/// The maximum number of recurrence periods that will be looked at when
+/// expanding a single RRULE.  This stops a malformed rule from looping
+/// forever.
+const MAX_RECURRENCE_PERIODS: u32 = 100_000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IcalTime {
+    /// A date-time ending in `Z`.
+    Utc(NaiveDateTime),
+    /// A date-time without a `Z`.  This is interpreted in the local time zone.
+    Local(NaiveDateTime),
+    /// A `VALUE=DATE` value.  This is used for all-day events.
+    Date(NaiveDate),
+}
+
+impl IcalTime {
+    fn naive(self) -> NaiveDateTime {
+        match self {
+            Self::Utc(naive) | Self::Local(naive) => naive,
+            Self::Date(date) => date.and_hms(0, 0, 0),
+        }
+    }
+
+    /// Create a new time of the same kind as `self`, but at `naive`.
+    fn with_naive(self, naive: NaiveDateTime) -> Self {
+        match self {
+            Self::Utc(_) => Self::Utc(naive),
+            Self::Local(_) => Self::Local(naive),
+            Self::Date(_) => Self::Date(naive.date()),
+        }
+    }
+
+    pub fn to_utc(self) -> DateTime<Utc> {
+        match self {
+            Self::Utc(naive) => DateTime::from_utc(naive, Utc),
+            Self::Local(naive) => local_to_utc(naive),
+            Self::Date(date) => local_to_utc(date.and_hms(0, 0, 0)),
+        }
+    }
+
+    /// Convert `self` to a naive time in the same frame (UTC or local) as
+    /// `frame`.  This is needed to compare an `UNTIL` or `EXDATE` given in
+    /// UTC with a `DTSTART` given in local time.
+    fn naive_in_frame_of(self, frame: Self) -> NaiveDateTime {
+        match (self, frame) {
+            (Self::Utc(_), Self::Local(_) | Self::Date(_)) => {
+                self.to_utc().with_timezone(&Local).naive_local()
+            }
+            (Self::Local(_), Self::Utc(_)) => self.to_utc().naive_utc(),
+            _ => self.naive(),
+        }
+    }
+}
+
+fn local_to_utc(naive: NaiveDateTime) -> DateTime<Utc> {
+    // A local time may not exist (it falls in a DST gap), in which case we
+    // just move it forward an hour.
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| {
+            Local
+                .from_local_datetime(&(naive + Duration::hours(1)))
+                .earliest()
+        })
+        .map_or_else(
+            || DateTime::from_utc(naive, Utc),
+            |local| local.with_timezone(&Utc),
+        )
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RRule {
+    pub freq: Freq,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<IcalTime>,
+    /// `BYDAY` values.  The first element is the optional ordinal, so `-1FR`
+    /// is `(Some(-1), Weekday::Fri)`.
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Attendee {
+    /// The attendee's calendar address, without the leading `mailto:`.
+    pub address: String,
+    pub partstat: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Event {
+    pub uid: Option<String>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub status: Option<String>,
+    pub transparent: bool,
+    pub start: Option<IcalTime>,
+    pub end: Option<IcalTime>,
+    pub duration: Option<Duration>,
+    pub rrule: Option<RRule>,
+    pub rdates: Vec<IcalTime>,
+    pub exdates: Vec<IcalTime>,
+    pub recurrence_id: Option<IcalTime>,
+    pub attendees: Vec<Attendee>,
+}
+
+impl Event {
+    /// The length of a single occurrence of this event.
+    fn occurrence_length(&self) -> Duration {
+        match (self.start, self.end, self.duration) {
+            (Some(start), Some(end), _) => end.naive() - start.naive(),
+            (_, None, Some(duration)) => duration,
+            (Some(IcalTime::Date(_)), None, None) => Duration::days(1),
+            _ => Duration::zero(),
+        }
+    }
+
+    /// Return the start times of all occurrences of this event that start
+    /// on or before `end_time`.
+    fn occurrence_starts(&self, end_time: DateTime<Utc>) -> Vec<IcalTime> {
+        let start = match self.start {
+            None => return vec![],
+            Some(start) => start,
+        };
+
+        let mut starts: Vec<IcalTime> = match &self.rrule {
+            None => vec![start],
+            Some(rrule) => {
+                // Give ourselves a day of slack so that converting between
+                // local time and UTC can't make us miss an occurrence.
+                let naive_end = IcalTime::Utc(end_time.naive_utc())
+                    .naive_in_frame_of(start)
+                    + Duration::days(1);
+                expand_rrule(rrule, start, naive_end)
+                    .into_iter()
+                    .map(|naive| start.with_naive(naive))
+                    .collect()
+            }
+        };
+
+        starts.extend(self.rdates.iter().copied());
+
+        starts.retain(|occurrence_start| {
+            !self.exdates.iter().any(|exdate| match exdate {
+                // An EXDATE with VALUE=DATE removes the whole day.
+                IcalTime::Date(date) => {
+                    *date == occurrence_start.naive().date()
+                }
+                _ => {
+                    exdate.naive_in_frame_of(start) == occurrence_start.naive()
+                }
+            })
+        });
+
+        starts
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Occurrence<'a> {
+    pub event: &'a Event,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Return all the occurrences of `events` that overlap the `start_time` to
+/// `end_time` window.  Recurring events are expanded, and occurrences that
+/// have been overridden by a separate event with a `RECURRENCE-ID` are
+/// replaced by that event.
+pub fn occurrences_between(
+    events: &[Event],
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+) -> Vec<Occurrence<'_>> {
+    let overridden: HashSet<(&str, DateTime<Utc>)> = events
+        .iter()
+        .filter_map(|event| {
+            let uid = event.uid.as_ref()?;
+            let recurrence_id = event.recurrence_id?;
+            Some((uid.as_str(), recurrence_id.to_utc()))
+        })
+        .collect();
+
+    let mut occurrences = vec![];
+
+    for event in events {
+        let length = event.occurrence_length();
+        let is_override = event.recurrence_id.is_some();
+
+        for occurrence_start in event.occurrence_starts(end_time) {
+            let start = occurrence_start.to_utc();
+
+            if !is_override {
+                if let Some(uid) = &event.uid {
+                    if overridden.contains(&(uid.as_str(), start)) {
+                        continue;
+                    }
+                }
+            }
+
+            let end = start + length;
+
+            // This uses the same overlap rule as the Google Calendar API's
+            // timeMin and timeMax.  Events with no length are counted if
+            // they start inside the window.
+            let overlaps = if length == Duration::zero() {
+                start >= start_time && start < end_time
+            } else {
+                start < end_time && end > start_time
+            };
+
+            if overlaps {
+                occurrences.push(Occurrence { event, start, end });
+            }
+        }
+    }
+
+    occurrences
+}
+
+fn expand_rrule(
+    rrule: &RRule,
+    dtstart: IcalTime,
+    end: NaiveDateTime,
+) -> Vec<NaiveDateTime> {
+    let start = dtstart.naive();
+    let until = rrule.until.map(|until| until.naive_in_frame_of(dtstart));
+    let interval = rrule.interval.max(1);
+
+    let mut starts = vec![];
+    let mut emitted: u32 = 0;
+
+    for period in 0..MAX_RECURRENCE_PERIODS {
+        let (period_start, dates) =
+            period_dates(rrule, start.date(), period * interval);
+
+        if period_start.and_time(start.time()) > end {
+            break;
+        }
+
+        for date in dates {
+            let occurrence = date.and_time(start.time());
+            if occurrence < start {
+                continue;
+            }
+            if until.map_or(false, |until| occurrence > until)
+                || rrule.count.map_or(false, |count| emitted >= count)
+                || occurrence > end
+            {
+                return starts;
+            }
+            emitted += 1;
+            starts.push(occurrence);
+        }
+    }
+
+    starts
+}
+
+/// Return the first day of the `offset`th period after `start`, along with
+/// all the dates in that period that match `rrule`.
+fn period_dates(
+    rrule: &RRule,
+    start: NaiveDate,
+    offset: u32,
+) -> (NaiveDate, Vec<NaiveDate>) {
+    let mut dates = match rrule.freq {
+        Freq::Daily => {
+            let date = start + Duration::days(offset.into());
+            let matches_by_day = rrule.by_day.is_empty()
+                || rrule.by_day.iter().any(|(_, wd)| *wd == date.weekday());
+            let matches_by_month_day = rrule.by_month_day.is_empty()
+                || month_days_matching(date.year(), date.month(), rrule)
+                    .contains(&date);
+            if matches_by_day && matches_by_month_day {
+                (date, vec![date])
+            } else {
+                (date, vec![])
+            }
+        }
+        Freq::Weekly => {
+            let week_start = start
+                - Duration::days(start.weekday().num_days_from_monday().into())
+                + Duration::weeks(offset.into());
+            let weekdays: Vec<Weekday> = if rrule.by_day.is_empty() {
+                vec![start.weekday()]
+            } else {
+                rrule.by_day.iter().map(|(_, wd)| *wd).collect()
+            };
+            let dates = weekdays
+                .into_iter()
+                .map(|wd| {
+                    week_start
+                        + Duration::days(wd.num_days_from_monday().into())
+                })
+                .collect();
+            (week_start, dates)
+        }
+        Freq::Monthly => {
+            let (year, month) = add_months(start.year(), start.month(), offset);
+            let first = NaiveDate::from_ymd(year, month, 1);
+            let dates =
+                if rrule.by_day.is_empty() && rrule.by_month_day.is_empty() {
+                    NaiveDate::from_ymd_opt(year, month, start.day())
+                        .into_iter()
+                        .collect()
+                } else {
+                    month_days_matching(year, month, rrule)
+                };
+            (first, dates)
+        }
+        Freq::Yearly => {
+            let year = start.year() + i32::try_from(offset).unwrap_or(i32::MAX);
+            let first = NaiveDate::from_ymd(year, 1, 1);
+            let months: Vec<u32> = if rrule.by_month.is_empty() {
+                vec![start.month()]
+            } else {
+                rrule.by_month.clone()
+            };
+            let dates = months
+                .into_iter()
+                .flat_map(|month| {
+                    if rrule.by_day.is_empty() && rrule.by_month_day.is_empty()
+                    {
+                        NaiveDate::from_ymd_opt(year, month, start.day())
+                            .into_iter()
+                            .collect()
+                    } else {
+                        month_days_matching(year, month, rrule)
+                    }
+                })
+                .collect();
+            (first, dates)
+        }
+    };
+
+    if !rrule.by_month.is_empty() {
+        dates
+            .1
+            .retain(|date| rrule.by_month.contains(&date.month()));
+    }
+    dates.1.sort();
+    dates.1.dedup();
+    dates
+}
+
+fn add_months(year: i32, month: u32, offset: u32) -> (i32, u32) {
+    let month0 = i64::from(month) - 1 + i64::from(offset);
+    let year = i64::from(year) + month0 / 12;
+    let month = month0 % 12 + 1;
+    (
+        i32::try_from(year).unwrap_or(i32::MAX),
+        u32::try_from(month).unwrap_or(1),
+    )
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = add_months(year, month, 1);
+    NaiveDate::from_ymd(next_year, next_month, 1).pred().day()
+}
+
+/// Return all the days in the given month that match the `BYMONTHDAY` and
+/// `BYDAY` parts of `rrule`.
+fn month_days_matching(year: i32, month: u32, rrule: &RRule) -> Vec<NaiveDate> {
+    let num_days = days_in_month(year, month);
+    let all_days: Vec<NaiveDate> = (1..=num_days)
+        .map(|day| NaiveDate::from_ymd(year, month, day))
+        .collect();
+
+    let by_month_day: Vec<NaiveDate> = rrule
+        .by_month_day
+        .iter()
+        .filter_map(|&day| {
+            let day = if day < 0 {
+                i64::from(num_days) + 1 + i64::from(day)
+            } else {
+                i64::from(day)
+            };
+            u32::try_from(day)
+                .ok()
+                .and_then(|day| NaiveDate::from_ymd_opt(year, month, day))
+        })
+        .collect();
+
+    let by_day: Vec<NaiveDate> = rrule
+        .by_day
+        .iter()
+        .flat_map(|&(ordinal, weekday)| {
+            let matching: Vec<NaiveDate> = all_days
+                .iter()
+                .copied()
+                .filter(|date| date.weekday() == weekday)
+                .collect();
+            match ordinal {
+                None => matching,
+                Some(n) if n > 0 => usize::try_from(n - 1)
+                    .ok()
+                    .and_then(|i| matching.get(i).copied())
+                    .into_iter()
+                    .collect(),
+                Some(n) => usize::try_from(-n)
+                    .ok()
+                    .and_then(|i| matching.len().checked_sub(i))
+                    .and_then(|i| matching.get(i).copied())
+                    .into_iter()
+                    .collect(),
+            }
+        })
+        .collect();
+
+    match (rrule.by_month_day.is_empty(), rrule.by_day.is_empty()) {
+        (false, false) => by_month_day
+            .into_iter()
+            .filter(|date| by_day.contains(date))
+            .collect(),
+        (false, true) => by_month_day,
+        (true, _) => by_day,
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ContentLine {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl ContentLine {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param_name, _)| param_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Join folded lines back together.  Lines that start with a space or a tab
+/// are continuations of the previous line.
+fn unfold(src: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for raw_line in src.lines() {
+        let line = raw_line.trim_end_matches('\r');
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&line[1..]);
+                continue;
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line.to_string());
+        }
+    }
+    lines
+}
+
+fn parse_content_line(line: &str) -> Option<ContentLine> {
+    let mut in_quotes = false;
+    let mut parts: Vec<String> = vec![String::new()];
+    let mut value = None;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => parts.push(String::new()),
+            ':' if !in_quotes => {
+                value = Some(line[i + 1..].to_string());
+                break;
+            }
+            _ => {
+                if let Some(last) = parts.last_mut() {
+                    last.push(c);
+                }
+            }
+        }
+    }
+
+    let value = value?;
+    let mut parts = parts.into_iter();
+    let name = parts.next()?.to_uppercase();
+    let params = parts
+        .filter_map(|param| {
+            let mut split = param.splitn(2, '=');
+            let param_name = split.next()?.to_uppercase();
+            let param_value = split.next().unwrap_or("").to_string();
+            Some((param_name, param_value))
+        })
+        .collect();
+
+    Some(ContentLine {
+        name,
+        params,
+        value,
+    })
+}
+
+fn unescape_text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n' | 'N') => out.push('\n'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn parse_time(value: &str, is_date: bool) -> Option<IcalTime> {
+    let value = value.trim();
+    if is_date || value.len() == 8 {
+        return NaiveDate::parse_from_str(value, "%Y%m%d")
+            .ok()
+            .map(IcalTime::Date);
+    }
+    if let Some(utc_value) = value.strip_suffix('Z') {
+        NaiveDateTime::parse_from_str(utc_value, "%Y%m%dT%H%M%S")
+            .ok()
+            .map(IcalTime::Utc)
+    } else {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .ok()
+            .map(IcalTime::Local)
+    }
+}
+
+fn parse_time_line(line: &ContentLine) -> Option<IcalTime> {
+    let is_date = line
+        .param("VALUE")
+        .map_or(false, |value| value.eq_ignore_ascii_case("DATE"));
+    parse_time(&line.value, is_date)
+}
+
+fn parse_time_list_line(line: &ContentLine) -> Vec<IcalTime> {
+    let is_date = line
+        .param("VALUE")
+        .map_or(false, |value| value.eq_ignore_ascii_case("DATE"));
+    line.value
+        .split(',')
+        .filter_map(|value| parse_time(value, is_date))
+        .collect()
+}
+
+/// Parse an iCalendar duration like `PT1H30M` or `P1D`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let value = value.strip_prefix('P')?;
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in value.chars() {
+        match c {
+            'T' => in_time = true,
+            '0'..='9' => number.push(c),
+            _ => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                total = total
+                    + match (c, in_time) {
+                        ('W', false) => Duration::weeks(n),
+                        ('D', false) => Duration::days(n),
+                        ('H', true) => Duration::hours(n),
+                        ('M', true) => Duration::minutes(n),
+                        ('S', true) => Duration::seconds(n),
+                        _ => return None,
+                    };
+            }
+        }
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(if negative { -total } else { total })
+}
+
+fn parse_weekday(value: &str) -> Option<Weekday> {
+    match value {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn parse_by_day(value: &str) -> Option<(Option<i32>, Weekday)> {
+    let value = value.trim();
+    if value.len() < 2 {
+        return None;
+    }
+    let (ordinal, weekday) = value.split_at(value.len() - 2);
+    let weekday = parse_weekday(weekday)?;
+    if ordinal.is_empty() {
+        Some((None, weekday))
+    } else {
+        let ordinal: i32 = ordinal.trim_start_matches('+').parse().ok()?;
+        Some((Some(ordinal), weekday))
+    }
+}
+
+/// Parse the value of an `RRULE` property.  Returns `None` for rules that
+/// aren't supported (for instance, `FREQ=HOURLY`).
+fn parse_rrule(value: &str) -> Option<RRule> {
+    let mut rrule = RRule {
+        freq: Freq::Daily,
+        interval: 1,
+        count: None,
+        until: None,
+        by_day: vec![],
+        by_month_day: vec![],
+        by_month: vec![],
+    };
+    let mut freq = None;
+
+    for part in value.split(';') {
+        let mut split = part.splitn(2, '=');
+        let key = split.next()?.to_uppercase();
+        let val = split.next().unwrap_or("");
+        match key.as_str() {
+            "FREQ" => {
+                freq = match val.to_uppercase().as_str() {
+                    "DAILY" => Some(Freq::Daily),
+                    "WEEKLY" => Some(Freq::Weekly),
+                    "MONTHLY" => Some(Freq::Monthly),
+                    "YEARLY" => Some(Freq::Yearly),
+                    _ => return None,
+                };
+            }
+            "INTERVAL" => rrule.interval = val.parse().ok()?,
+            "COUNT" => rrule.count = Some(val.parse().ok()?),
+            "UNTIL" => rrule.until = Some(parse_time(val, false)?),
+            "BYDAY" => {
+                rrule.by_day = val
+                    .to_uppercase()
+                    .split(',')
+                    .map(parse_by_day)
+                    .collect::<Option<_>>()?;
+            }
+            "BYMONTHDAY" => {
+                rrule.by_month_day = val
+                    .split(',')
+                    .map(|day| day.trim().parse().ok())
+                    .collect::<Option<_>>()?;
+            }
+            "BYMONTH" => {
+                rrule.by_month = val
+                    .split(',')
+                    .map(|month| month.trim().parse().ok())
+                    .collect::<Option<_>>()?;
+            }
+            // BYSETPOS, WKST, etc are not supported.  Ignoring them gives a
+            // superset of the real occurrences, which is the safe direction
+            // to err in.
+            _ => (),
+        }
+    }
+
+    rrule.freq = freq?;
+    Some(rrule)
+}
+
+/// The address in the value of an ATTENDEE line, without the `mailto:`.
+fn attendee_address(value: &str) -> &str {
+    let value = value.trim();
+    if value
+        .get(..7)
+        .map_or(false, |prefix| prefix.eq_ignore_ascii_case("mailto:"))
+    {
+        &value[7..]
+    } else {
+        value
+    }
+}
+
+fn apply_line(event: &mut Event, line: &ContentLine) {
+    match line.name.as_str() {
+        "UID" => event.uid = Some(line.value.clone()),
+        "SUMMARY" => event.summary = Some(unescape_text(&line.value)),
+        "DESCRIPTION" => event.description = Some(unescape_text(&line.value)),
+        "STATUS" => event.status = Some(line.value.to_uppercase()),
+        "TRANSP" => {
+            event.transparent = line.value.eq_ignore_ascii_case("TRANSPARENT");
+        }
+        "DTSTART" => event.start = parse_time_line(line),
+        "DTEND" => event.end = parse_time_line(line),
+        "DURATION" => event.duration = parse_duration(&line.value),
+        "RECURRENCE-ID" => event.recurrence_id = parse_time_line(line),
+        "RRULE" => {
+            event.rrule = parse_rrule(&line.value);
+            if event.rrule.is_none() {
+                println!(
+                    "Calendar: Unsupported RRULE {:?}, treating event {:?} as a single event",
+                    line.value, event.uid
+                );
+            }
+        }
+        "RDATE" => event.rdates.extend(parse_time_list_line(line)),
+        "EXDATE" => event.exdates.extend(parse_time_list_line(line)),
+        "ATTENDEE" => {
+            event.attendees.push(Attendee {
+                address: attendee_address(&line.value).to_string(),
+                partstat: line.param("PARTSTAT").map(str::to_uppercase),
+            });
+        }
+        _ => (),
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseErr(pub String);
+
+impl std::fmt::Display for ParseErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parse all the VEVENTs out of the iCalendar data in `src`.  Events without
+/// a `DTSTART` are dropped.
+pub fn parse_events(src: &str) -> Result<Vec<Event>, ParseErr> {
+    let mut events = vec![];
+    // The stack of components we are currently inside of.  Properties are
+    // only applied to an event when the VEVENT is the innermost component, so
+    // that the properties of a nested VALARM are skipped.
+    let mut components: Vec<String> = vec![];
+    let mut current_event: Option<Event> = None;
+
+    for line in unfold(src) {
+        let content_line = match parse_content_line(&line) {
+            None => continue,
+            Some(content_line) => content_line,
+        };
+
+        match content_line.name.as_str() {
+            "BEGIN" => {
+                let component = content_line.value.to_uppercase();
+                if component == "VEVENT" {
+                    current_event = Some(Event::default());
+                }
+                components.push(component);
+            }
+            "END" => {
+                let component = content_line.value.to_uppercase();
+                match components.pop() {
+                    Some(open) if open == component => (),
+                    other => {
+                        return Err(ParseErr(format!(
+                            "Found END:{} while inside of {:?}",
+                            component, other
+                        )))
+                    }
+                }
+                if component == "VEVENT" {
+                    if let Some(event) = current_event.take() {
+                        if event.start.is_some() {
+                            events.push(event);
+                        }
+                    }
+                }
+            }
+            _ => {
+                if components.last().map(String::as_str) == Some("VEVENT") {
+                    if let Some(event) = current_event.as_mut() {
+                        apply_line(event, &content_line);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(open) = components.pop() {
+        return Err(ParseErr(format!("Missing END:{}", open)));
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.ymd(y, m, d).and_hms(h, min, 0)
+    }
+
+    #[test]
+    fn test_weekly_rrule_with_exdate_and_override() {
+        let src = "BEGIN:VCALENDAR\r\n\
+                   BEGIN:VEVENT\r\n\
+                   UID:standup\r\n\
+                   DTSTART:20200106T090000Z\r\n\
+                   DTEND:20200106T093000Z\r\n\
+                   RRULE:FREQ=WEEKLY;BYDAY=MO,WE;COUNT=6\r\n\
+                   EXDATE:20200108T090000Z\r\n\
+                   BEGIN:VALARM\r\n\
+                   DTSTART:20000101T000000Z\r\n\
+                   END:VALARM\r\n\
+                   END:VEVENT\r\n\
+                   BEGIN:VEVENT\r\n\
+                   UID:standup\r\n\
+                   RECURRENCE-ID:20200113T090000Z\r\n\
+                   DTSTART:20200113T140000Z\r\n\
+                   DTEND:20200113T143000Z\r\n\
+                   END:VEVENT\r\n\
+                   END:VCALENDAR\r\n";
+        let events = parse_events(src).unwrap();
+        assert_eq!(events.len(), 2);
+
+        let starts: Vec<DateTime<Utc>> = occurrences_between(
+            &events,
+            utc(2020, 1, 1, 0, 0),
+            utc(2020, 2, 1, 0, 0),
+        )
+        .into_iter()
+        .map(|occurrence| occurrence.start)
+        .collect();
+
+        assert_eq!(
+            starts,
+            vec![
+                utc(2020, 1, 6, 9, 0),
+                utc(2020, 1, 15, 9, 0),
+                utc(2020, 1, 20, 9, 0),
+                utc(2020, 1, 22, 9, 0),
+                utc(2020, 1, 13, 14, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_monthly_rrule_last_friday() {
+        let rrule =
+            parse_rrule("FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20200401T000000Z")
+                .unwrap();
+        let dtstart =
+            IcalTime::Utc(NaiveDate::from_ymd(2020, 1, 31).and_hms(16, 0, 0));
+        let starts = expand_rrule(
+            &rrule,
+            dtstart,
+            NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0),
+        );
+        let expected: Vec<NaiveDateTime> = vec![
+            NaiveDate::from_ymd(2020, 1, 31).and_hms(16, 0, 0),
+            NaiveDate::from_ymd(2020, 2, 28).and_hms(16, 0, 0),
+            NaiveDate::from_ymd(2020, 3, 27).and_hms(16, 0, 0),
+        ];
+        assert_eq!(starts, expected);
+    }
+
+    #[test]
+    fn test_parse_content_line_with_quoted_params() {
+        let line = parse_content_line(
+            "ATTENDEE;CN=\"Doe; Jane\";PARTSTAT=DECLINED:mailto:jane@example.com",
+        )
+        .unwrap();
+        assert_eq!(line.name, "ATTENDEE");
+        assert_eq!(line.param("cn"), Some("Doe; Jane"));
+        assert_eq!(line.param("PARTSTAT"), Some("DECLINED"));
+        assert_eq!(line.value, "mailto:jane@example.com");
+    }
+
+    #[test]
+    fn test_attendee_address() {
+        assert_eq!(
+            attendee_address("MAILTO:jane@example.com"),
+            "jane@example.com"
+        );
+        assert_eq!(attendee_address("Jürgen Müller"), "Jürgen Müller");
+        assert_eq!(attendee_address("ÄÄÄÄ"), "ÄÄÄÄ");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1W"), Some(Duration::weeks(1)));
+        assert_eq!(parse_duration("-P1D"), Some(Duration::days(-1)));
+        assert_eq!(parse_duration("P1H"), None);
+    }
+}
@@ -1,211 +1,555 @@
-use super::{CanBreak, Plugin};
+mod auth;
+mod cache;
+
+pub use auth::AuthFlow;
+
+use super::backoff::Backoff;
+use super::calendar;
+use super::{BusyBlock, CanBreak, Plugin};
 
 use crate::config::{Config, PluginSettings};
 
-use std::collections::HashMap;
-use std::net::TcpListener;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+
+use chrono::TimeZone;
 
 use google_calendar3::{CalendarHub, CalendarListEntry, Events, Scope};
-use yup_oauth2::{
-    ApplicationSecret, Authenticator, DefaultAuthenticatorDelegate,
-    DiskTokenStorage,
-};
-
-fn get_available_port() -> Option<u16> {
-    (8080..65535).find(|port| TcpListener::bind(("127.0.0.1", *port)).is_ok())
-}
-
-type CalHub = CalendarHub<
-    hyper::Client,
-    Authenticator<
-        DefaultAuthenticatorDelegate,
-        DiskTokenStorage,
-        hyper::Client,
-    >,
->;
-
-type Auth = Authenticator<
-    DefaultAuthenticatorDelegate,
-    DiskTokenStorage,
-    hyper::Client,
->;
+use serde::{Deserialize, Serialize};
+use yup_oauth2::GetToken;
+
+type CalHub = CalendarHub<hyper::Client, auth::Auth>;
+
+/// Options for which events to ignore when deciding whether or not a break can
+/// start.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Debug, Default, Deserialize)]
+struct EventFilter {
+    /// Ignore events that last all day.
+    #[serde(default)]
+    ignore_all_day_events: bool,
+    /// Ignore events that are marked as "free" instead of "busy".
+    #[serde(default)]
+    ignore_free_events: bool,
+    /// Ignore "working location" events.
+    #[serde(default)]
+    ignore_working_location_events: bool,
+    /// Ignore "focus time" events.
+    #[serde(default)]
+    ignore_focus_time_events: bool,
+    /// Ignore events where you are the only attendee.
+    #[serde(default)]
+    ignore_events_without_other_attendees: bool,
+    /// Ignore events where the summary matches one of these patterns.  See
+    /// `calendar::matches_pattern`.
+    #[serde(default)]
+    ignore_summary_patterns: Vec<String>,
+}
+
+/// The special entry in `calendars` that selects the account's primary
+/// calendar.
+const PRIMARY_CALENDAR: &str = "primary";
+
+/// The special entry in `calendars` that selects every calendar in the
+/// account's calendar list.
+const ALL_CALENDARS: &str = "all";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum AccountSettings {
+    /// Just an email address.  Only the primary calendar is checked.
+    Email(String),
+    Table {
+        email: String,
+        /// Calendar IDs, `"primary"`, or `"all"`.
+        #[serde(default = "default_calendars")]
+        calendars: Vec<String>,
+    },
+}
+
+fn default_calendars() -> Vec<String> {
+    vec![String::from(PRIMARY_CALENDAR)]
+}
+
+impl AccountSettings {
+    fn email(&self) -> &str {
+        match self {
+            Self::Email(email) | Self::Table { email, .. } => email,
+        }
+    }
+
+    fn calendars(&self) -> Vec<String> {
+        match self {
+            Self::Email(_) => default_calendars(),
+            Self::Table { calendars, .. } => calendars.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct GoogleCalSettings {
+    #[serde(default)]
+    accounts: Vec<AccountSettings>,
+    #[serde(default = "default_look_behind_minutes")]
+    look_behind_minutes: i64,
+    #[serde(default = "default_look_ahead_minutes")]
+    look_ahead_minutes: i64,
+    /// How often to sync events into the cache in the background.
+    #[serde(default = "default_sync_interval_minutes")]
+    sync_interval_minutes: u32,
+    /// The client secret JSON file of your own OAuth client, downloaded from
+    /// the Google Cloud Console.  Defaults to break-time's own client.
+    #[serde(default)]
+    client_secret_file: Option<PathBuf>,
+    /// How `login` gets the authorization code from Google.
+    #[serde(default)]
+    auth_flow: AuthFlow,
+    #[serde(flatten)]
+    filter: EventFilter,
+}
+
+const fn default_look_behind_minutes() -> i64 {
+    calendar::LOOK_BEHIND_MINUTES
+}
+
+const fn default_look_ahead_minutes() -> i64 {
+    calendar::LOOK_AHEAD_MINUTES
+}
+
+const fn default_sync_interval_minutes() -> u32 {
+    5
+}
+
+impl Default for GoogleCalSettings {
+    fn default() -> Self {
+        Self {
+            accounts: vec![],
+            look_behind_minutes: default_look_behind_minutes(),
+            look_ahead_minutes: default_look_ahead_minutes(),
+            sync_interval_minutes: default_sync_interval_minutes(),
+            client_secret_file: None,
+            auth_flow: AuthFlow::default(),
+            filter: EventFilter::default(),
+        }
+    }
+}
+
+impl GoogleCalSettings {
+    fn from_plugin_settings(
+        plugin_settings: &PluginSettings,
+    ) -> Result<Self, ()> {
+        match plugin_settings.0.get("google_calendar") {
+            // If the "google_calendar" key doesn't exist, then just skip.
+            None => Ok(Self::default()),
+            Some(val) => val.clone().try_into().map_err(|_err| ()),
+        }
+    }
+
+    fn client_secret_file(&self) -> Option<PathBuf> {
+        self.client_secret_file
+            .as_ref()
+            .map(|path| calendar::expand_home(path))
+    }
+
+    fn check_window(
+        &self,
+    ) -> (chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>) {
+        calendar::check_window(
+            chrono::Utc::now(),
+            self.look_behind_minutes,
+            self.look_ahead_minutes,
+        )
+    }
+}
 
 pub struct CalFetcher {
     email: String,
     hub: CalHub,
     calendar_ids: Vec<String>,
+    settings: Arc<GoogleCalSettings>,
 }
 
 impl CalFetcher {
-    pub fn new(break_time_cache_dir: &Path, email: String) -> Result<Self, ()> {
-        let google_cal_dir_name = Path::new("google-calendar");
-        let google_cal_dir_path =
-            break_time_cache_dir.join(google_cal_dir_name);
-
-        std::fs::create_dir_all(&google_cal_dir_path).map_err(|_io_err| ())?;
-
-        let token_path = google_cal_dir_path.join(&email);
-
-        let token_path_string = token_path.to_string_lossy().into_owned();
-        let disk_token_storage: DiskTokenStorage = DiskTokenStorage::new(
-            &token_path_string,
-        )
-        .expect("Couldn't create a file to hold the google oauth token");
+    fn new(
+        break_time_cache_dir: &Path,
+        settings: Arc<GoogleCalSettings>,
+        account: &AccountSettings,
+    ) -> Result<Self, GoogleCalErr> {
+        let email = String::from(account.email());
+        let token_path = auth::token_path(break_time_cache_dir, &email);
+
+        // Don't start signing in from here, since this may be running in the
+        // background.  Signing in is done with `login`.
+        let is_signed_in = auth::read_stored_tokens(&token_path)
+            .map_or(false, |tokens| !tokens.is_empty());
+        if !is_signed_in {
+            return Err(GoogleCalErr::NotSignedIn { email });
+        }
 
-        let hub: CalHub = create_hub(disk_token_storage)?;
+        let secret =
+            auth::load_secret(settings.client_secret_file().as_deref())?;
+        let auth = auth::create_auth(
+            &secret,
+            auth::token_storage(&token_path)?,
+            None,
+        )?;
+        let hub: CalHub = create_hub_from_auth(auth);
 
-        let calendar_ids = get_all_calendar_ids(&hub);
+        let calendar_ids = select_calendar_ids(
+            &get_all_calendars(&hub, &email)?,
+            &email,
+            &account.calendars(),
+        );
+        println!(
+            "Google Calendar Plugin: Checking calendars for {}: {:?}",
+            email, calendar_ids
+        );
 
         Ok(Self {
             email,
             hub,
             calendar_ids,
+            settings,
         })
     }
+}
 
-    pub fn can_break(&self) -> Result<CanBreak, GoogleCalErr> {
-        let now: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
-        let ten_minutes_ago: chrono::DateTime<chrono::Utc> =
-            now - chrono::Duration::minutes(10);
-        let in_twenty_mins: chrono::DateTime<chrono::Utc> =
-            now + chrono::Duration::minutes(20);
-
-        let res = has_events(
-            &self.email,
-            &self.hub,
-            &self.calendar_ids,
-            ten_minutes_ago,
-            in_twenty_mins,
-        );
-
-        match res {
-            Err(err) => Err(err),
-            Ok(HasEvent::Yes) => Ok(CanBreak::No),
-            Ok(HasEvent::No) => Ok(CanBreak::Yes),
+/// Create a fetcher for each account.  Accounts that can't be used, like ones
+/// that haven't been signed in to, are returned along with the error instead,
+/// so that the other accounts still work.
+fn create_fetchers(
+    config: &Config,
+) -> (Vec<CalFetcher>, Vec<(String, GoogleCalErr)>) {
+    let break_time_cache_dir: &Path = &config.cache_dir;
+    let settings = Arc::new(read_settings(config));
+
+    let mut fetchers = vec![];
+    let mut failed = vec![];
+    for account in &settings.accounts {
+        match CalFetcher::new(
+            break_time_cache_dir,
+            Arc::clone(&settings),
+            account,
+        ) {
+            Err(err) => failed.push((String::from(account.email()), err)),
+            Ok(fetcher) => fetchers.push(fetcher),
         }
     }
+    (fetchers, failed)
 }
 
-const GOOGLE_CLIENT_ID: &str =
-    "728095687622-mpib9rmdtck7e8ln9egelnns6na0me08.apps.googleusercontent.com";
+const MIN_INIT_BACKOFF: std::time::Duration =
+    std::time::Duration::from_secs(30);
+const MAX_INIT_BACKOFF: std::time::Duration =
+    std::time::Duration::from_secs(30 * 60);
 
-// It is weird embedding something called a "client_secret" directly in the source
-// code, but it doesn't seem like this needs to be something that is actually kept
-// secret:
-// https://stackoverflow.com/questions/59416326/safely-distribute-oauth-2-0-client-secret-in-desktop-applications-in-python
-const GOOGLE_CLIENT_SECRET: &str = "mI7MmEnboy8jdYEBjK9rZ2M2";
+/// A problem with an account, like not being able to reach Google.  This is
+/// set by the background thread, and cleared once things work again.
+type Problem = Arc<Mutex<Option<String>>>;
 
-// TODO: Create a datatype to hold all the settings for the GoogleCalendar plugin.
-// Don't try parsing it out manually here.
-#[allow(clippy::let_and_return)]
-fn get_emails(plugin_settings: &PluginSettings) -> Result<Vec<String>, ()> {
-    let google_cal_settings: &toml::Value =
-        match plugin_settings.0.get("google_calendar") {
-            // If the "google_calendar" key doesn't exist, then just skip.
-            None => return Ok(vec![]),
-            Some(val) => val,
-        };
-    let google_cal_settings_table: &toml::value::Table =
-        google_cal_settings.as_table().ok_or(
-            // If the "google_calendar" key exists, but it doesn't contain a table, then throw an
-            // error.
-            (),
-        )?;
-    let all_accounts: &toml::Value =
-        match google_cal_settings_table.get("accounts") {
-            // If the "google_calendar" key exists, but it doesn't have an accounts field, then
-            // just skip.
-            None => return Ok(vec![]),
-            Some(all_accounts) => all_accounts,
-        };
+fn set_problem(problem: &Problem, new_problem: Option<String>) {
+    *problem
+        .lock()
+        .expect("Google Calendar Plugin: problem mutex was poisoned") =
+        new_problem;
+}
 
-    let all_emails = all_accounts.clone().try_into().map_err(|_err| ());
+/// Tells the background thread of a `CachedAccount` to stop once the account
+/// is dropped, like when the plugins are recreated after switching profiles.
+struct StopSignal(mpsc::Receiver<()>);
 
-    // println!("All emails: {:?}", all_emails);
+impl StopSignal {
+    /// Create the signal, and the sender that stops the thread when dropped.
+    fn new() -> (mpsc::Sender<()>, Self) {
+        let (sender, receiver) = mpsc::channel();
+        (sender, Self(receiver))
+    }
 
-    all_emails
-}
+    fn is_stopped(&self) -> bool {
+        matches!(self.0.try_recv(), Err(mpsc::TryRecvError::Disconnected))
+    }
 
-fn collect_first_err<T, E>(v: Vec<Result<T, E>>) -> Result<Vec<T>, E> {
-    let mut ok_vec = vec![];
+    /// Sleep for `duration`, or until the thread should stop.  Returns
+    /// whether it should keep going.
+    fn sleep(&self, duration: std::time::Duration) -> bool {
+        matches!(
+            self.0.recv_timeout(duration),
+            Err(mpsc::RecvTimeoutError::Timeout)
+        )
+    }
+}
 
-    for res in v {
-        match res {
-            Err(err) => return Err(err),
-            Ok(i) => ok_vec.push(i),
+/// Keep trying to create a `CalFetcher` for `account` until it works, or
+/// until `stop` says to give up.
+fn init_fetcher(
+    break_time_cache_dir: &Path,
+    settings: &Arc<GoogleCalSettings>,
+    account: &AccountSettings,
+    problem: &Problem,
+    stop: &StopSignal,
+) -> Option<CalFetcher> {
+    let mut backoff = Backoff::new(MIN_INIT_BACKOFF, MAX_INIT_BACKOFF);
+    loop {
+        match CalFetcher::new(
+            break_time_cache_dir,
+            Arc::clone(settings),
+            account,
+        ) {
+            Ok(fetcher) => return Some(fetcher),
+            Err(err) => {
+                let wait = backoff.next();
+                println!(
+                    "{}\nGoogle Calendar Plugin: Could not initialize {}, retrying in {} seconds",
+                    err,
+                    account.email(),
+                    wait.as_secs()
+                );
+                let problem_text = match err {
+                    GoogleCalErr::NotSignedIn { .. } => format!(
+                        "Google Calendar account {} is not signed in",
+                        account.email()
+                    ),
+                    _ => format!(
+                        "Google Calendar account {} could not be initialized",
+                        account.email()
+                    ),
+                };
+                set_problem(problem, Some(problem_text));
+                if !stop.sleep(wait) {
+                    return None;
+                }
+            }
         }
     }
-
-    Ok(ok_vec)
 }
 
-pub struct GoogleCalendar {
-    fetchers: Vec<CalFetcher>,
+/// An account whose events are synced into `cache` by a background thread.
+struct CachedAccount {
+    email: String,
+    settings: Arc<GoogleCalSettings>,
+    /// The `calendars` setting of the account.
+    selection: Vec<String>,
+    /// The IDs of the calendars that are checked.  This is `None` until the
+    /// background thread has fetched the account's calendar list.
+    calendar_ids: Arc<Mutex<Option<Vec<String>>>>,
+    cache: Arc<Mutex<cache::EventCache>>,
+    problem: Problem,
+    /// Stops the background thread when the account is dropped.
+    _stop: mpsc::Sender<()>,
 }
 
-impl GoogleCalendar {
-    pub fn new(config: &Config) -> Result<Self, ()> {
-        let break_time_cache_dir: &Path = &config.cache_dir;
-        let emails = get_emails(&config.settings.all_plugin_settings)?;
+impl CachedAccount {
+    /// Load the account's cached events and start the background thread.
+    /// Signing in and syncing happen on that thread, so an account that
+    /// can't be reached doesn't stop the other accounts from working.
+    fn new(
+        break_time_cache_dir: &Path,
+        settings: Arc<GoogleCalSettings>,
+        account_settings: AccountSettings,
+    ) -> Self {
+        let email = String::from(account_settings.email());
+        let cache_path = cache::cache_path(break_time_cache_dir, &email);
+        let cache = Arc::new(Mutex::new(cache::EventCache::load(&cache_path)));
+        let problem: Problem = Arc::new(Mutex::new(None));
+        let calendar_ids = Arc::new(Mutex::new(None));
+        let (stop_sender, stop) = StopSignal::new();
+
+        let account = Self {
+            email,
+            settings: Arc::clone(&settings),
+            selection: account_settings.calendars(),
+            calendar_ids: Arc::clone(&calendar_ids),
+            cache: Arc::clone(&cache),
+            problem: Arc::clone(&problem),
+            _stop: stop_sender,
+        };
+
+        let break_time_cache_dir: PathBuf = break_time_cache_dir.to_path_buf();
+        std::thread::spawn(move || {
+            let opt_fetcher = init_fetcher(
+                &break_time_cache_dir,
+                &settings,
+                &account_settings,
+                &problem,
+                &stop,
+            );
+            if let Some(fetcher) = opt_fetcher {
+                set_problem(&problem, None);
+                *calendar_ids.lock().expect(
+                    "Google Calendar Plugin: calendar ids mutex was poisoned",
+                ) = Some(fetcher.calendar_ids.clone());
+                cache::run_sync(&fetcher, &cache, &cache_path, &problem, &stop);
+            }
+        });
 
-        let fetchers_res = emails
+        account
+    }
+
+    /// Return a function that tells whether events from a calendar should be
+    /// checked.  Until the calendar list has been fetched, this goes by the
+    /// `calendars` setting alone.
+    fn calendar_filter(&self) -> impl Fn(&str) -> bool + '_ {
+        let opt_calendar_ids = self
+            .calendar_ids
+            .lock()
+            .expect("Google Calendar Plugin: calendar ids mutex was poisoned")
+            .clone();
+        move |calendar_id| match &opt_calendar_ids {
+            Some(calendar_ids) => {
+                calendar_ids.iter().any(|id| id == calendar_id)
+            }
+            None => {
+                self.selection
+                    .iter()
+                    .any(|selected| match selected.as_str() {
+                        ALL_CALENDARS => true,
+                        // The ID of the primary calendar is normally the email
+                        // address.
+                        PRIMARY_CALENDAR => calendar_id == self.email,
+                        selected => selected == calendar_id,
+                    })
+            }
+        }
+    }
+
+    /// The cached events from `start_time` to `end_time` that should stop a
+    /// break from happening.
+    fn cached_events<'a>(
+        &self,
+        cache: &'a cache::EventCache,
+        start_time: chrono::DateTime<chrono::Utc>,
+        end_time: chrono::DateTime<chrono::Utc>,
+    ) -> Vec<&'a google_calendar3::Event> {
+        cache
+            .events_between(start_time, end_time, self.calendar_filter())
             .into_iter()
-            .map(|email| CalFetcher::new(break_time_cache_dir, email))
-            .collect();
+            .filter(|event| filter_event(event, &self.settings.filter))
+            .collect()
+    }
 
-        let fetchers = collect_first_err(fetchers_res)?;
+    fn busy_blocks(
+        &self,
+        start_time: chrono::DateTime<chrono::Utc>,
+        end_time: chrono::DateTime<chrono::Utc>,
+    ) -> Vec<BusyBlock> {
+        let cache = self
+            .cache
+            .lock()
+            .expect("Google Calendar Plugin: cache mutex was poisoned");
+
+        self.cached_events(&cache, start_time, end_time)
+            .into_iter()
+            .filter_map(cache::event_time_range)
+            .map(|(start, end)| BusyBlock { start, end })
+            .collect()
+    }
 
-        Ok(Self { fetchers })
+    /// A problem with the account, or with how old its cached events are.
+    fn problem(&self) -> Option<String> {
+        self.problem
+            .lock()
+            .expect("Google Calendar Plugin: problem mutex was poisoned")
+            .clone()
+            .or_else(|| self.staleness(chrono::Utc::now()))
     }
 
-    fn can_break(&self) -> Result<CanBreak, GoogleCalErr> {
-        // println!("now: {}, after twenty: {}", now.to_rfc3339(), in_twenty_mins.to_rfc3339());
+    /// Describe how old the cached events are, if they haven't been synced in
+    /// twice the sync interval.
+    fn staleness(&self, now: chrono::DateTime<chrono::Utc>) -> Option<String> {
+        let age = self
+            .cache
+            .lock()
+            .expect("Google Calendar Plugin: cache mutex was poisoned")
+            .age(now)?;
+        let stale_minutes = 2 * self.settings.sync_interval_minutes;
+        if age.num_minutes() >= i64::from(stale_minutes) {
+            Some(format!(
+                "The cached Google Calendar events for {} are stale, they were last synced {} minutes ago",
+                self.email,
+                age.num_minutes()
+            ))
+        } else {
+            None
+        }
+    }
 
-        self.fetchers.iter().map(CalFetcher::can_break).fold(
-            Ok(CanBreak::Yes),
-            |accum, can_break_res| match (accum, can_break_res) {
-                (Err(err), _) => Err(err),
-                (_, Err(err)) => Err(err),
-                (Ok(CanBreak::No), _) => Ok(CanBreak::No),
-                (_, can_break) => can_break,
-            },
-        )
+    /// Check for events in the cache.  This doesn't touch the network, so it
+    /// works even when the computer is offline.  If the cache hasn't been
+    /// synced recently, its age is logged.
+    fn can_break(&self) -> CanBreak {
+        let now = chrono::Utc::now();
+        let (start_time, end_time) = self.settings.check_window();
+
+        if let Some(staleness) = self.staleness(now) {
+            println!("Google Calendar Plugin: {}", staleness);
+        }
+
+        let cache = self
+            .cache
+            .lock()
+            .expect("Google Calendar Plugin: cache mutex was poisoned");
+
+        if cache.age(now).is_none() {
+            println!(
+                "Google Calendar Plugin: Events for {} haven't been synced yet, so assuming there are no events",
+                self.email
+            );
+        }
+
+        let events = self.cached_events(&cache, start_time, end_time);
+
+        if events.is_empty() {
+            CanBreak::Yes
+        } else {
+            println!(
+                "There were some cached events for {}: {:?}",
+                self.email,
+                events
+                    .iter()
+                    .map(|event| (&event.id, &event.summary))
+                    .collect::<Vec<_>>()
+            );
+            CanBreak::No
+        }
     }
 }
 
-fn create_auth(disk_token_storage: DiskTokenStorage) -> Result<Auth, ()> {
-    let secret: ApplicationSecret = ApplicationSecret {
-        client_id: String::from(GOOGLE_CLIENT_ID),
-        client_secret: String::from(GOOGLE_CLIENT_SECRET),
-        token_uri: "https://oauth2.googleapis.com/token".to_string(),
-        auth_uri: "https://accounts.google.com/o/oauth2/auth".to_string(),
-        redirect_uris: vec![
-            "http://127.0.0.1".to_string(),
-            "urn:ietf:wg:oauth:2.0:oob".to_string(),
-        ],
-        ..ApplicationSecret::default()
-    };
+pub struct GoogleCalendar {
+    accounts: Vec<CachedAccount>,
+}
 
-    let http_client_for_auth: hyper::Client = hyper::Client::with_connector(
-        hyper::net::HttpsConnector::new(hyper_rustls::TlsClient::new()),
-    );
-    let first_available_port = get_available_port().ok_or(())?;
-
-    let auth: Auth = Authenticator::new(
-        &secret,
-        DefaultAuthenticatorDelegate,
-        http_client_for_auth,
-        disk_token_storage,
-        Some(yup_oauth2::FlowType::InstalledRedirect(
-            first_available_port.into(),
-        )),
-    );
+impl GoogleCalendar {
+    pub fn new(config: &Config) -> Result<Self, ()> {
+        let settings = Arc::new(GoogleCalSettings::from_plugin_settings(
+            &config.settings.all_plugin_settings,
+        )?);
+
+        let accounts = settings
+            .accounts
+            .iter()
+            .map(|account_settings| {
+                CachedAccount::new(
+                    &config.cache_dir,
+                    Arc::clone(&settings),
+                    account_settings.clone(),
+                )
+            })
+            .collect();
+
+        Ok(Self { accounts })
+    }
 
-    Ok(auth)
+    fn can_break(&self) -> CanBreak {
+        self.accounts
+            .iter()
+            .map(CachedAccount::can_break)
+            .fold(CanBreak::Yes, CanBreak::combine)
+    }
 }
 
-fn create_hub_from_auth(auth: Auth) -> CalHub {
+fn create_hub_from_auth(auth: auth::Auth) -> CalHub {
     let http_client_for_cal: hyper::Client = hyper::Client::with_connector(
         hyper::net::HttpsConnector::new(hyper_rustls::TlsClient::new()),
     );
@@ -213,69 +557,80 @@ fn create_hub_from_auth(auth: Auth) -> CalHub {
     CalendarHub::new(http_client_for_cal, auth)
 }
 
-fn create_hub(disk_token_storage: DiskTokenStorage) -> Result<CalHub, ()> {
-    let auth: Auth = create_auth(disk_token_storage)?;
-
-    let hub: CalHub = create_hub_from_auth(auth);
-
-    Ok(hub)
-}
-
-fn get_all_calendar_ids(hub: &CalHub) -> Vec<String> {
+/// Get the `(id, is_primary)` of every calendar in the account's calendar
+/// list.
+fn get_all_calendars(
+    hub: &CalHub,
+    email: &str,
+) -> Result<Vec<(String, bool)>, GoogleCalErr> {
     let (_, calendar_list_res) = hub
         .calendar_list()
         .list()
         .add_scope(Scope::Readonly)
         .add_scope(Scope::Event)
         .doit()
-        .expect("couldn't get a response from calendar_list");
+        .map_err(|google_cal_err| GoogleCalErr::ListingCalendars {
+            email: String::from(email),
+            google_cal_err,
+        })?;
 
-    let calendars = calendar_list_res
+    Ok(calendar_list_res
         .items
-        .expect("There should be some calendars available");
-
-    let calendar_ids: Vec<String> = calendars
+        .unwrap_or_default()
         .into_iter()
-        .map(|calendar: CalendarListEntry| {
-            calendar.id.expect("Calendars should always have ids")
+        .filter_map(|calendar: CalendarListEntry| {
+            Some((calendar.id?, calendar.primary == Some(true)))
         })
-        .collect();
-
-    calendar_ids
+        .collect())
 }
 
-/// Check whether or not any events occur during the `start_time` to `end_time`.
-fn has_events(
+/// Pick the IDs of the calendars to check out of all the `calendars` in an
+/// account, based on the `selection` from the config file.
+///
+/// IDs in `selection` that aren't in the account's calendar list (for
+/// instance, a shared calendar that hasn't been added to the list) are still
+/// checked.
+fn select_calendar_ids(
+    calendars: &[(String, bool)],
     email: &str,
-    hub: &CalHub,
-    calendar_ids: &[String],
-    start_time: chrono::DateTime<chrono::Utc>,
-    end_time: chrono::DateTime<chrono::Utc>,
-) -> Result<HasEvent, GoogleCalErr> {
-    calendar_ids
-        .iter()
-        .map(|calendar_id| {
-            // We only check calendar_ids that are equal to the email address we are looking for.
-            //
-            // TODO: Eventually, we probably want to let the user configure what email addresses
-            // they want to look for events on.
-            if email == calendar_id {
-                has_event(hub, calendar_id, start_time, end_time)
-            } else {
-                Ok(HasEvent::No)
+    selection: &[String],
+) -> Vec<String> {
+    let mut calendar_ids: Vec<String> = vec![];
+
+    let mut add = |calendar_id: &str| {
+        if !calendar_ids.iter().any(|id| id == calendar_id) {
+            calendar_ids.push(String::from(calendar_id));
+        }
+    };
+
+    for selected in selection {
+        match selected.as_str() {
+            ALL_CALENDARS => {
+                for (calendar_id, _) in calendars {
+                    add(calendar_id);
+                }
             }
-        })
-        .fold(Ok(HasEvent::No), |accum, res| match (accum, res) {
-            (Err(err), _) => Err(err),
-            (_, Err(err)) => Err(err),
-            (Ok(HasEvent::No), new) => new,
-            (Ok(HasEvent::Yes), _) => Ok(HasEvent::Yes),
-        })
-}
+            // The ID of the primary calendar is normally the email address,
+            // but check the `primary` flag as well.
+            PRIMARY_CALENDAR => {
+                let mut primary_ids = calendars
+                    .iter()
+                    .filter(|(calendar_id, is_primary)| {
+                        *is_primary || calendar_id == email
+                    })
+                    .peekable();
+                if primary_ids.peek().is_none() {
+                    add(email);
+                }
+                for (calendar_id, _) in primary_ids {
+                    add(calendar_id);
+                }
+            }
+            calendar_id => add(calendar_id),
+        }
+    }
 
-enum HasEvent {
-    No,
-    Yes,
+    calendar_ids
 }
 
 #[derive(Debug)]
@@ -284,6 +639,22 @@ pub enum GoogleCalErr {
         calendar_id: String,
         google_cal_err: google_calendar3::Error,
     },
+    ListingCalendars {
+        email: String,
+        google_cal_err: google_calendar3::Error,
+    },
+    NoAvailablePort,
+    ClientSecret {
+        path: PathBuf,
+        io_err: std::io::Error,
+    },
+    NotSignedIn {
+        email: String,
+    },
+    TokenStorage {
+        path: PathBuf,
+        io_err: std::io::Error,
+    },
 }
 
 impl std::error::Error for GoogleCalErr {}
@@ -299,88 +670,203 @@ impl std::fmt::Display for GoogleCalErr {
                 "Google Calendard Plugin: Error fetching calendar_id {}: {}",
                 calendar_id, google_cal_err
             ),
+            Self::ListingCalendars {
+                email,
+                google_cal_err,
+            } => write!(
+                f,
+                "Google Calendar Plugin: Error listing the calendars of {}: {}",
+                email, google_cal_err
+            ),
+            Self::NoAvailablePort => write!(
+                f,
+                "Google Calendar Plugin: Could not find a free port to listen on for the OAuth redirect"
+            ),
+            Self::ClientSecret { path, io_err } => write!(
+                f,
+                "Google Calendar Plugin: Could not read the client secret file {:?}: {}",
+                path, io_err
+            ),
+            Self::NotSignedIn { email } => write!(
+                f,
+                "Google Calendar Plugin: {} is not signed in.  Sign in with `break-time google-calendar login {}`.",
+                email, email
+            ),
+            Self::TokenStorage { path, io_err } => write!(
+                f,
+                "Google Calendar Plugin: Could not create the OAuth token storage at {:?}: {}",
+                path, io_err
+            ),
         }
     }
 }
 
-fn get_event(
+/// Get the ids of the events of the given `event_type` (like
+/// `workingLocation` or `focusTime`) during `start_time` to `end_time`.
+///
+/// The version of the Calendar API that `google_calendar3` was generated from
+/// doesn't know about the `eventType` field of events, so the only way to find
+/// these events is to ask for them specifically.
+fn get_event_ids_of_type(
     hub: &CalHub,
     calendar_id: &str,
+    event_type: &str,
     start_time: chrono::DateTime<chrono::Utc>,
     end_time: chrono::DateTime<chrono::Utc>,
-    // This is super hacky...
-    log: bool,
-) -> Result<Vec<google_calendar3::Event>, GoogleCalErr> {
+) -> Result<HashSet<String>, GoogleCalErr> {
     let result: google_calendar3::Result<(_, Events)> = hub
         .events()
         .list(calendar_id)
         .add_scope(Scope::Readonly)
         .add_scope(Scope::Event)
-        // all events that occur over the next 20 minutes
         .time_min(&start_time.to_rfc3339())
         .time_max(&end_time.to_rfc3339())
-        // Expand recurring events into single events.
         .single_events(true)
+        .param("eventTypes", event_type)
         .doit();
 
-    // println!("\n\nevents for {}: {:?}", calendar_id, result);
-
     match result {
         Err(err) => Err(GoogleCalErr::FetchingEvents {
             calendar_id: String::from(calendar_id),
             google_cal_err: err,
         }),
-        Ok((_, events)) => {
-            match events.items {
-                None => Ok(vec![]),
-                Some(event_items) => {
-                    let filtered_events: Vec<google_calendar3::Event> =
-                        filter_cal_events(event_items);
-                    if !filtered_events.is_empty() && log {
-                        println!("There were some event items from calendar id {}: {:?}", calendar_id, filtered_events);
-                    }
-                    Ok(filtered_events)
-                }
-            }
-        }
+        Ok((_, events)) => Ok(events
+            .items
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|event| event.id)
+            .collect()),
     }
 }
 
-fn has_event(
+/// Get the ids of the events that should be ignored because of their event
+/// type, according to `filter`.
+fn get_ignored_event_ids(
     hub: &CalHub,
     calendar_id: &str,
+    filter: &EventFilter,
     start_time: chrono::DateTime<chrono::Utc>,
     end_time: chrono::DateTime<chrono::Utc>,
-) -> Result<HasEvent, GoogleCalErr> {
-    let event_res = get_event(hub, calendar_id, start_time, end_time, true);
-    event_res.map(|filtered_events| {
-        if filtered_events.is_empty() {
-            HasEvent::No
-        } else {
-            HasEvent::Yes
+) -> Result<HashSet<String>, GoogleCalErr> {
+    let mut ignored_ids: HashSet<String> = HashSet::new();
+    if filter.ignore_working_location_events {
+        ignored_ids.extend(get_event_ids_of_type(
+            hub,
+            calendar_id,
+            "workingLocation",
+            start_time,
+            end_time,
+        )?);
+    }
+    if filter.ignore_focus_time_events {
+        ignored_ids.extend(get_event_ids_of_type(
+            hub,
+            calendar_id,
+            "focusTime",
+            start_time,
+            end_time,
+        )?);
+    }
+    Ok(ignored_ids)
+}
+
+/// Get all the events during `start_time` to `end_time`, along with the ids of
+/// the events that are ignored because of their event type.
+fn fetch_events(
+    hub: &CalHub,
+    calendar_id: &str,
+    filter: &EventFilter,
+    start_time: chrono::DateTime<chrono::Utc>,
+    end_time: chrono::DateTime<chrono::Utc>,
+) -> Result<(Vec<google_calendar3::Event>, HashSet<String>), GoogleCalErr> {
+    let result: google_calendar3::Result<(_, Events)> = hub
+        .events()
+        .list(calendar_id)
+        .add_scope(Scope::Readonly)
+        .add_scope(Scope::Event)
+        // all events that occur during the window
+        .time_min(&start_time.to_rfc3339())
+        .time_max(&end_time.to_rfc3339())
+        // Expand recurring events into single events.
+        .single_events(true)
+        .doit();
+
+    match result {
+        Err(err) => Err(GoogleCalErr::FetchingEvents {
+            calendar_id: String::from(calendar_id),
+            google_cal_err: err,
+        }),
+        Ok((_, events)) => {
+            let ignored_ids = get_ignored_event_ids(
+                hub,
+                calendar_id,
+                filter,
+                start_time,
+                end_time,
+            )?;
+            Ok((events.items.unwrap_or_default(), ignored_ids))
         }
-    })
+    }
 }
 
-fn filter_cal_events(
-    events: Vec<google_calendar3::Event>,
-) -> Vec<google_calendar3::Event> {
-    events.into_iter().filter(filter_event).collect()
+/// Why an event doesn't stop a break from starting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum IgnoreReason {
+    DescriptionMarker,
+    IgnoreProperty,
+    Cancelled,
+    Declined,
+    AllDay,
+    Free,
+    NoOtherAttendees,
+    SummaryPattern(String),
+    EventType,
 }
 
-fn filter_event(event: &google_calendar3::Event) -> bool {
-    if let Some(desc) = &event.description {
-        // Ignore events where the description contains the magic string
-        // "ignore break-time"
-        if desc.to_lowercase().contains("ignore break-time") {
-            return false;
+impl std::fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DescriptionMarker => {
+                write!(f, "the description contains `ignore break-time`")
+            }
+            Self::IgnoreProperty => write!(f, "it was ignored with ignore-event"),
+            Self::Cancelled => write!(f, "it is cancelled"),
+            Self::Declined => write!(f, "you declined it"),
+            Self::AllDay => {
+                write!(f, "it is an all-day event (ignore_all_day_events)")
+            }
+            Self::Free => write!(f, "it is marked as free (ignore_free_events)"),
+            Self::NoOtherAttendees => write!(
+                f,
+                "it has no other attendees (ignore_events_without_other_attendees)"
+            ),
+            Self::SummaryPattern(pattern) => write!(
+                f,
+                "the summary matches {:?} (ignore_summary_patterns)",
+                pattern
+            ),
+            Self::EventType => write!(
+                f,
+                "it is a working location or focus time event (ignore_working_location_events, ignore_focus_time_events)"
+            ),
         }
+    }
+}
+
+/// Whether an event stops a break from starting.
+fn filter_event(event: &google_calendar3::Event, filter: &EventFilter) -> bool {
+    ignore_reason(event, filter).is_none()
+}
 
-        // Ignore events where the description talks about being an out-of-office event.
-        // Even if we are out-of-office, we still may be on our personal computer, and
-        // want break-time to occassionally break.
-        if desc.to_lowercase().contains("out-of-office event") {
-            return false;
+/// The reason an event doesn't stop a break from starting, or `None` if it
+/// does.
+fn ignore_reason(
+    event: &google_calendar3::Event,
+    filter: &EventFilter,
+) -> Option<IgnoreReason> {
+    if let Some(desc) = &event.description {
+        if calendar::is_ignored_by_description(desc) {
+            return Some(IgnoreReason::DescriptionMarker);
         }
     }
 
@@ -389,7 +875,7 @@ fn filter_event(event: &google_calendar3::Event) -> bool {
         if let Some(props) = &extended_props.private {
             if let Some(ignore_break_time) = props.get("ignore-break-time") {
                 if ignore_break_time == "true" {
-                    return false;
+                    return Some(IgnoreReason::IgnoreProperty);
                 }
             }
         }
@@ -402,7 +888,7 @@ fn filter_event(event: &google_calendar3::Event) -> bool {
     // "needsAction" and ignore events with this status as well.
     if let Some(status) = &event.status {
         if status == "cancelled" || status == "needsAction" {
-            return false;
+            return Some(IgnoreReason::Cancelled);
         }
     }
 
@@ -413,131 +899,781 @@ fn filter_event(event: &google_calendar3::Event) -> bool {
             .find(|attendee| attendee.self_ == Some(true))
         {
             if me.response_status == Some(String::from("declined")) {
-                return false;
+                return Some(IgnoreReason::Declined);
             }
         }
     }
 
-    true
+    // All-day events only have a date, not a date and time.
+    if filter.ignore_all_day_events {
+        if let Some(start) = &event.start {
+            if start.date.is_some() && start.date_time.is_none() {
+                return Some(IgnoreReason::AllDay);
+            }
+        }
+    }
+
+    if filter.ignore_free_events
+        && event.transparency.as_deref() == Some("transparent")
+    {
+        return Some(IgnoreReason::Free);
+    }
+
+    if filter.ignore_events_without_other_attendees {
+        // Rooms and other resources are also listed as attendees, but they
+        // don't count as other people.
+        let has_other_attendees =
+            event.attendees.as_ref().map_or(false, |attendees| {
+                attendees.iter().any(|attendee| {
+                    attendee.self_ != Some(true)
+                        && attendee.resource != Some(true)
+                })
+            });
+        if !has_other_attendees {
+            return Some(IgnoreReason::NoOtherAttendees);
+        }
+    }
+
+    if let Some(summary) = &event.summary {
+        if let Some(pattern) = filter
+            .ignore_summary_patterns
+            .iter()
+            .find(|pattern| calendar::matches_pattern(pattern, summary))
+        {
+            return Some(IgnoreReason::SummaryPattern(pattern.clone()));
+        }
+    }
+
+    None
 }
 
 impl Plugin for GoogleCalendar {
     fn can_break_now(&self) -> Result<CanBreak, Box<dyn std::error::Error>> {
-        self.can_break().map_err(|google_cal_err| {
-            Box::new(google_cal_err) as Box<dyn std::error::Error>
-        })
+        // This only looks at the cache, so it never fails.  Errors from
+        // syncing the cache are logged by the background sync thread.
+        Ok(self.can_break())
     }
 
     fn name(&self) -> String {
         String::from("google_calendar")
     }
+
+    fn problems(&self) -> Vec<String> {
+        self.accounts
+            .iter()
+            .filter_map(CachedAccount::problem)
+            .collect()
+    }
+
+    fn busy_blocks(
+        &self,
+        start: chrono::DateTime<chrono::Utc>,
+        end: chrono::DateTime<chrono::Utc>,
+    ) -> Option<Vec<BusyBlock>> {
+        Some(
+            self.accounts
+                .iter()
+                .flat_map(|account| account.busy_blocks(start, end))
+                .collect(),
+        )
+    }
 }
 
-pub fn list_events(config: &Config) {
-    let google_calendar = GoogleCalendar::new(config)
-        .expect("Could not initialize Google Calendar.");
+/// Parse the `--from` and `--to` arguments of `list-events`.  These are either
+/// an RFC 3339 date and time, or a date and time (`2020-03-02 14:30`) or date
+/// (`2020-03-02`) in the local time zone.
+pub fn parse_time_arg(
+    time_str: &str,
+) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    if let Ok(date_time) = chrono::DateTime::parse_from_rfc3339(time_str) {
+        return Ok(date_time.with_timezone(&chrono::Utc));
+    }
 
-    let event_calendar_lists: Vec<_> = google_calendar
-        .fetchers
-        .iter()
-        .flat_map(get_events)
+    let naive = chrono::NaiveDateTime::parse_from_str(time_str, "%Y-%m-%d %H:%M")
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(time_str, "%Y-%m-%d")
+                .map(|date| date.and_hms(0, 0, 0))
+        })
+        .map_err(|_| {
+            format!(
+                "{:?} is not a date like 2020-03-02, a date and time like \"2020-03-02 14:30\", or an RFC 3339 date and time",
+                time_str
+            )
+        })?;
+
+    chrono::Local
+        .from_local_datetime(&naive)
+        .single()
+        .map(|date_time| date_time.with_timezone(&chrono::Utc))
+        .ok_or_else(|| {
+            format!("{:?} is ambiguous in the local time zone", time_str)
+        })
+}
+
+#[derive(Debug, Serialize)]
+struct ListedEvent {
+    id: Option<String>,
+    summary: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    /// Whether this event would stop a break from starting.
+    vetoes_break: bool,
+    ignored_because: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ListedCalendar {
+    email: String,
+    /// This is `None` for an account that couldn't be used at all.
+    calendar_id: Option<String>,
+    error: Option<String>,
+    events: Vec<ListedEvent>,
+}
+
+fn event_date_time_str(
+    event_date_time: Option<&google_calendar3::EventDateTime>,
+) -> Option<String> {
+    event_date_time
+        .and_then(|edt| edt.date_time.clone().or_else(|| edt.date.clone()))
+}
+
+fn list_calendar_events(
+    fetcher: &CalFetcher,
+    calendar_id: &str,
+    start_time: chrono::DateTime<chrono::Utc>,
+    end_time: chrono::DateTime<chrono::Utc>,
+) -> ListedCalendar {
+    let filter = &fetcher.settings.filter;
+    let res_events =
+        fetch_events(&fetcher.hub, calendar_id, filter, start_time, end_time);
+
+    let (error, events) = match res_events {
+        Err(err) => (Some(err.to_string()), vec![]),
+        Ok((events, ignored_ids)) => (
+            None,
+            events
+                .iter()
+                .map(|event| {
+                    let is_ignored_id = event
+                        .id
+                        .as_ref()
+                        .map_or(false, |id| ignored_ids.contains(id));
+                    let opt_reason = if is_ignored_id {
+                        Some(IgnoreReason::EventType)
+                    } else {
+                        ignore_reason(event, filter)
+                    };
+                    ListedEvent {
+                        id: event.id.clone(),
+                        summary: event.summary.clone(),
+                        start: event_date_time_str(event.start.as_ref()),
+                        end: event_date_time_str(event.end.as_ref()),
+                        vetoes_break: opt_reason.is_none(),
+                        ignored_because: opt_reason
+                            .map(|reason| reason.to_string()),
+                    }
+                })
+                .collect(),
+        ),
+    };
+
+    ListedCalendar {
+        email: fetcher.email.clone(),
+        calendar_id: Some(String::from(calendar_id)),
+        error,
+        events,
+    }
+}
+
+/// List the events between `opt_from` and `opt_to`, and whether or not each
+/// of them would stop a break from starting.  By default, this lists the
+/// events in the window that is checked before a break.
+pub fn list_events(
+    config: &Config,
+    opt_from: Option<chrono::DateTime<chrono::Utc>>,
+    opt_to: Option<chrono::DateTime<chrono::Utc>>,
+    json: bool,
+) {
+    let (fetchers, failed) = create_fetchers(config);
+
+    let (window_start, window_end) = read_settings(config).check_window();
+    let start_time = opt_from.unwrap_or(window_start);
+    let end_time = opt_to.unwrap_or(start_time + (window_end - window_start));
+    if end_time <= start_time {
+        println!("--to must be after --from.");
+        return;
+    }
+
+    let calendars: Vec<ListedCalendar> = failed
+        .into_iter()
+        .map(|(email, err)| ListedCalendar {
+            email,
+            calendar_id: None,
+            error: Some(err.to_string()),
+            events: vec![],
+        })
+        .chain(fetchers.iter().flat_map(|fetcher| {
+            fetcher.calendar_ids.iter().map(move |calendar_id| {
+                list_calendar_events(fetcher, calendar_id, start_time, end_time)
+            })
+        }))
         .collect();
 
-    for (email, res_event_list) in event_calendar_lists {
-        println!("{}:", email);
-        match res_event_list {
-            Err(err) => {
-                println!("ERROR with Google Calendar: {}", err);
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&calendars)
+                .expect("Serializing events to JSON should never fail")
+        );
+        return;
+    }
+
+    println!("Events from {} to {}:", start_time, end_time);
+    let mut last_email = None;
+    for calendar in &calendars {
+        if last_email != Some(&calendar.email) {
+            println!("{}:", calendar.email);
+            last_email = Some(&calendar.email);
+        }
+        let calendar_id = match &calendar.calendar_id {
+            None => {
+                println!(
+                    "  Skipped: {}",
+                    calendar.error.as_deref().unwrap_or("?")
+                );
+                continue;
+            }
+            Some(calendar_id) => calendar_id,
+        };
+        println!("  {}:", calendar_id);
+        if let Some(err) = &calendar.error {
+            println!("    ERROR with Google Calendar: {}", err);
+        }
+        for event in &calendar.events {
+            println!(
+                "    - id: {}, summary: {:?}, {} to {}",
+                event.id.as_deref().unwrap_or("?"),
+                event.summary.as_deref().unwrap_or(""),
+                event.start.as_deref().unwrap_or("?"),
+                event.end.as_deref().unwrap_or("?"),
+            );
+            match &event.ignored_because {
+                None => println!("      Stops a break from starting"),
+                Some(reason) => println!("      Ignored because {}", reason),
             }
-            Ok(event_list) => {
-                for event in event_list {
+        }
+    }
+}
+
+/// Whether a request failed with the HTTP status `status`.
+fn is_http_status(
+    err: &google_calendar3::Error,
+    status: hyper::status::StatusCode,
+) -> bool {
+    match err {
+        google_calendar3::Error::BadRequest(err_resp) => {
+            err_resp.error.code == status.to_u16()
+        }
+        google_calendar3::Error::Failure(response) => response.status == status,
+        _ => false,
+    }
+}
+
+const IGNORE_PROPERTY: &str = "ignore-break-time";
+
+/// Set the `ignore-break-time` extended property on an event.
+fn set_ignore_property(
+    hub: &CalHub,
+    calendar_id: &str,
+    event_id: &str,
+) -> google_calendar3::Result<()> {
+    let mut props = HashMap::new();
+    props.insert(IGNORE_PROPERTY.to_string(), "true".to_string());
+    let extended_props = google_calendar3::EventExtendedProperties {
+        private: Some(props),
+        ..google_calendar3::EventExtendedProperties::default()
+    };
+    let req = google_calendar3::Event {
+        extended_properties: Some(extended_props),
+        ..google_calendar3::Event::default()
+    };
+    hub.events()
+        .patch(req, calendar_id, event_id)
+        .add_scope(Scope::Readonly)
+        .add_scope(Scope::Event)
+        .doit()
+        .map(|_| ())
+}
+
+/// Remove the `ignore-break-time` extended property from an event.  A patch
+/// can't remove a property, so this gets the whole event and updates it.
+fn remove_ignore_property(
+    hub: &CalHub,
+    calendar_id: &str,
+    event_id: &str,
+) -> google_calendar3::Result<()> {
+    let (_, mut event) = hub
+        .events()
+        .get(calendar_id, event_id)
+        .add_scope(Scope::Readonly)
+        .add_scope(Scope::Event)
+        .doit()?;
+
+    let had_property = event
+        .extended_properties
+        .as_mut()
+        .and_then(|extended_props| extended_props.private.as_mut())
+        .map_or(false, |props| props.remove(IGNORE_PROPERTY).is_some());
+    if !had_property {
+        return Ok(());
+    }
+
+    hub.events()
+        .update(event, calendar_id, event_id)
+        .add_scope(Scope::Readonly)
+        .add_scope(Scope::Event)
+        .doit()
+        .map(|_| ())
+}
+
+/// Run `update` on the event in every calendar that is checked, and report
+/// what happened.  Calendars that don't have the event are skipped.
+fn update_event_everywhere(
+    config: &Config,
+    event_id: &str,
+    done: &str,
+    update: fn(&CalHub, &str, &str) -> google_calendar3::Result<()>,
+) {
+    let (fetchers, failed) = create_fetchers(config);
+    for (email, err) in &failed {
+        println!("Skipping {}: {}", email, err);
+    }
+
+    let mut found = false;
+    for fetcher in &fetchers {
+        for calendar_id in &fetcher.calendar_ids {
+            match update(&fetcher.hub, calendar_id, event_id) {
+                Ok(()) => {
+                    found = true;
                     println!(
-                        "    - id: {:?}, summary: {:?}",
-                        event.id, event.summary
+                        "{} event {} in calendar {} of {}",
+                        done, event_id, calendar_id, fetcher.email
+                    );
+                }
+                Err(err)
+                    if is_http_status(
+                        &err,
+                        hyper::status::StatusCode::NotFound,
+                    ) => {}
+                Err(err) => {
+                    found = true;
+                    println!(
+                        "ERROR updating event {} in calendar {} of {}: {}",
+                        event_id, calendar_id, fetcher.email, err
                     );
                 }
             }
         }
     }
+
+    if !found {
+        println!("Could not find event {} in any calendar.", event_id);
+    }
+}
+
+/// Make break-time ignore an event, by setting the `ignore-break-time`
+/// extended property on it.
+pub fn ignore_event(config: &Config, event_id: &str) {
+    update_event_everywhere(config, event_id, "Ignored", set_ignore_property);
+}
+
+/// Undo `ignore_event`.
+pub fn unignore_event(config: &Config, event_id: &str) {
+    update_event_everywhere(
+        config,
+        event_id,
+        "Stopped ignoring",
+        remove_ignore_property,
+    );
 }
 
-fn get_events(
-    cal_fetcher: &CalFetcher,
-) -> Vec<(String, Result<Vec<google_calendar3::Event>, GoogleCalErr>)> {
-    let now: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
-    let ten_minutes_ago: chrono::DateTime<chrono::Utc> =
-        now - chrono::Duration::minutes(10);
-    let in_twenty_mins: chrono::DateTime<chrono::Utc> =
-        now + chrono::Duration::minutes(20);
-    let start_time = ten_minutes_ago;
-    let end_time = in_twenty_mins;
+fn read_settings(config: &Config) -> GoogleCalSettings {
+    GoogleCalSettings::from_plugin_settings(
+        &config.settings.all_plugin_settings,
+    )
+    .expect("Could not read the Google Calendar settings.")
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    chrono::Utc
+        .timestamp(timestamp, 0)
+        .with_timezone(&chrono::Local)
+        .to_rfc2822()
+}
 
-    let events_list: Vec<(
-        String,
-        Result<Vec<google_calendar3::Event>, GoogleCalErr>,
-    )> = cal_fetcher
-        .calendar_ids
+/// Sign in to a Google account, and store the token in the cache directory.
+pub fn login(config: &Config, email: &str, opt_flow: Option<AuthFlow>) {
+    let settings = read_settings(config);
+    let flow = opt_flow.unwrap_or(settings.auth_flow);
+    let token_path = auth::token_path(&config.cache_dir, email);
+
+    if !settings
+        .accounts
         .iter()
-        .filter_map(|calendar_id| {
-            // We only check calendar_ids that are equal to the email address we are looking for.
-            //
-            // TODO: Eventually, we probably want to let the user configure what email addresses
-            // they want to look for events on.
-            if &cal_fetcher.email == calendar_id {
-                Some((
-                    String::from(calendar_id),
-                    get_event(
-                        &cal_fetcher.hub,
-                        calendar_id,
-                        start_time,
-                        end_time,
-                        false,
-                    ),
-                ))
-            } else {
-                None
+        .any(|account| account.email() == email)
+    {
+        println!(
+            "Warning: {} isn't in plugin.google_calendar.accounts in your config file, so its calendars won't be checked.",
+            email
+        );
+    }
+
+    let is_signed_in = auth::read_stored_tokens(&token_path)
+        .map_or(false, |tokens| !tokens.is_empty());
+    if is_signed_in {
+        println!(
+            "{} is already signed in.  To sign in again, first run `break-time google-calendar logout {}`.",
+            email, email
+        );
+        return;
+    }
+
+    let res_auth = auth::load_secret(settings.client_secret_file().as_deref())
+        .and_then(|secret| {
+            auth::create_auth(
+                &secret,
+                auth::token_storage(&token_path)?,
+                Some(flow),
+            )
+        });
+
+    match res_auth {
+        Err(err) => println!("{}", err),
+        Ok(mut auth) => match auth.token(&auth::scopes()) {
+            Err(err) => println!("Signing in {} failed: {}", email, err),
+            Ok(_) => {
+                // The token is as good as a password, so only let the user
+                // read it.
+                if let Err(io_err) = std::fs::set_permissions(
+                    &token_path,
+                    std::fs::Permissions::from_mode(0o600),
+                ) {
+                    println!(
+                        "Warning: could not make {:?} readable only by you: {}",
+                        token_path, io_err
+                    );
+                }
+                println!(
+                    "Signed in {}.  A running break-time will start checking its calendars the next time it retries, or when it is restarted.",
+                    email
+                );
             }
-        })
-        .collect();
+        },
+    }
+}
 
-    events_list
+/// Revoke and delete the stored token of a Google account, and delete its
+/// cached events.
+pub fn logout(config: &Config, email: &str) {
+    let token_path = auth::token_path(&config.cache_dir, email);
+
+    match auth::read_stored_tokens(&token_path) {
+        Err(io_err) => {
+            println!(
+                "Could not read the token in {:?}: {}",
+                token_path, io_err
+            );
+        }
+        Ok(tokens) if tokens.is_empty() => {
+            println!("{} is not signed in.", email);
+            return;
+        }
+        Ok(tokens) => {
+            for stored in tokens {
+                if let Err(err) =
+                    auth::revoke_token(&stored.token.refresh_token)
+                {
+                    println!(
+                        "Could not revoke the token of {} ({}), deleting it anyway",
+                        email, err
+                    );
+                }
+            }
+        }
+    }
+
+    for path in &[token_path, cache::cache_path(&config.cache_dir, email)] {
+        match std::fs::remove_file(path) {
+            Err(io_err) if io_err.kind() != std::io::ErrorKind::NotFound => {
+                println!("Could not delete {:?}: {}", path, io_err);
+            }
+            _ => (),
+        }
+    }
+
+    println!("Signed out {}.", email);
 }
 
-pub fn ignore_event(config: &Config, event_id: &str) {
-    let google_calendar = GoogleCalendar::new(config)
-        .expect("Could not initialize Google Calendar.");
-
-    // let event_calendar_lists: Vec<_> = google_calendar.fetchers.iter().flat_map(get_events).collect();
-    for fetcher in google_calendar.fetchers {
-        for calendar_id in fetcher.calendar_ids {
-            // We only check calendar_ids that are equal to the email address we are looking for.
-            //
-            // TODO: Eventually, we probably want to let the user configure what email addresses
-            // they want to look for events on.
-            if calendar_id == fetcher.email {
-                let mut props = HashMap::new();
-                props.insert(
-                    "ignore-break-time".to_string(),
-                    "true".to_string(),
+/// Show whether each account is signed in, and whether its token still works.
+pub fn status(config: &Config) {
+    let settings = read_settings(config);
+
+    if settings.accounts.is_empty() {
+        println!("There are no accounts in plugin.google_calendar.accounts in your config file.");
+    }
+
+    for account in &settings.accounts {
+        let email = account.email();
+        let token_path = auth::token_path(&config.cache_dir, email);
+        println!("{}:", email);
+
+        let tokens = match auth::read_stored_tokens(&token_path) {
+            Err(io_err) => {
+                println!(
+                    "  Could not read the token in {:?}: {}",
+                    token_path, io_err
                 );
-                let extended_props =
-                    google_calendar3::EventExtendedProperties {
-                        private: Some(props),
-                        ..google_calendar3::EventExtendedProperties::default()
-                    };
-                let req = google_calendar3::Event {
-                    extended_properties: Some(extended_props),
-                    ..google_calendar3::Event::default()
-                };
-                let _res = fetcher
-                    .hub
-                    .events()
-                    .patch(req, &calendar_id, event_id)
-                    .add_scope(Scope::Readonly)
-                    .add_scope(Scope::Event)
-                    .doit();
-                // println!("event_id: {}, calendar_id: {}, res: {:?}", event_id, calendar_id, res);
+                continue;
             }
+            Ok(tokens) => tokens,
+        };
+
+        if tokens.is_empty() {
+            println!(
+                "  Not signed in.  Sign in with `break-time google-calendar login {}`.",
+                email
+            );
+            continue;
+        }
+
+        for stored in &tokens {
+            println!(
+                "  Scopes: {}",
+                stored
+                    .scopes
+                    .as_ref()
+                    .map_or_else(|| String::from("unknown"), |s| s.join(", "))
+            );
+            match stored.token.expires_in_timestamp {
+                None => println!("  Access token expiry: unknown"),
+                Some(expires_at) => println!(
+                    "  Access token expiry: {}{}",
+                    format_timestamp(expires_at),
+                    if expires_at <= chrono::Utc::now().timestamp() {
+                        " (expired, it will be refreshed when needed)"
+                    } else {
+                        ""
+                    }
+                ),
+            }
+            println!(
+                "  Refresh token: {}",
+                if stored.token.refresh_token.is_empty() {
+                    "missing"
+                } else {
+                    "present"
+                }
+            );
         }
+
+        // Check that the token actually works, by refreshing it if it has
+        // expired.
+        let res_token =
+            auth::load_secret(settings.client_secret_file().as_deref())
+                .and_then(|secret| {
+                    auth::create_auth(
+                        &secret,
+                        auth::token_storage(&token_path)?,
+                        None,
+                    )
+                })
+                .map_err(|err| err.to_string())
+                .and_then(|mut auth| {
+                    auth.token(&auth::scopes()).map_err(|err| err.to_string())
+                });
+        match res_token {
+            Err(err) => println!("  Token is not valid: {}", err),
+            Ok(token) => println!(
+                "  Token is valid{}",
+                token.expires_in_timestamp.map_or_else(String::new, |ts| {
+                    format!(" until {}", format_timestamp(ts))
+                })
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_event() {
+        let settings: GoogleCalSettings = toml::from_str(
+            r#"
+            accounts = ["me@example.com"]
+            look_ahead_minutes = 5
+            ignore_all_day_events = true
+            ignore_events_without_other_attendees = true
+            ignore_summary_patterns = ["*lunch*"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.look_behind_minutes, 10);
+        assert_eq!(settings.look_ahead_minutes, 5);
+
+        let me = google_calendar3::EventAttendee {
+            self_: Some(true),
+            ..google_calendar3::EventAttendee::default()
+        };
+        let other = google_calendar3::EventAttendee {
+            email: Some(String::from("other@example.com")),
+            ..google_calendar3::EventAttendee::default()
+        };
+        let meeting = google_calendar3::Event {
+            summary: Some(String::from("Planning")),
+            attendees: Some(vec![me.clone(), other]),
+            ..google_calendar3::Event::default()
+        };
+        assert!(filter_event(&meeting, &settings.filter));
+
+        let lunch = google_calendar3::Event {
+            summary: Some(String::from("Team Lunch")),
+            ..meeting.clone()
+        };
+        assert_eq!(
+            ignore_reason(&lunch, &settings.filter),
+            Some(IgnoreReason::SummaryPattern(String::from("*lunch*")))
+        );
+
+        let alone = google_calendar3::Event {
+            attendees: Some(vec![me]),
+            ..meeting.clone()
+        };
+        assert_eq!(
+            ignore_reason(&alone, &settings.filter),
+            Some(IgnoreReason::NoOtherAttendees)
+        );
+
+        let all_day = google_calendar3::Event {
+            start: Some(google_calendar3::EventDateTime {
+                date: Some(String::from("2020-03-02")),
+                ..google_calendar3::EventDateTime::default()
+            }),
+            ..meeting
+        };
+        assert!(!filter_event(&all_day, &settings.filter));
+    }
+
+    #[test]
+    fn test_parse_time_arg() {
+        assert_eq!(
+            parse_time_arg("2020-03-02T10:00:00+09:00"),
+            Ok(chrono::Utc.ymd(2020, 3, 2).and_hms(1, 0, 0))
+        );
+        assert_eq!(
+            parse_time_arg("2020-03-02 14:30"),
+            Ok(chrono::Local
+                .ymd(2020, 3, 2)
+                .and_hms(14, 30, 0)
+                .with_timezone(&chrono::Utc))
+        );
+        assert_eq!(
+            parse_time_arg("2020-03-02"),
+            Ok(chrono::Local
+                .ymd(2020, 3, 2)
+                .and_hms(0, 0, 0)
+                .with_timezone(&chrono::Utc))
+        );
+        assert!(parse_time_arg("tomorrow").is_err());
+    }
+
+    #[test]
+    fn test_select_calendar_ids() {
+        let calendars = vec![
+            (String::from("me@example.com"), true),
+            (String::from("team@group.calendar.google.com"), false),
+            (String::from("focus@group.calendar.google.com"), false),
+        ];
+        let select = |selection: &[&str]| {
+            let selection: Vec<String> =
+                selection.iter().map(|s| String::from(*s)).collect();
+            select_calendar_ids(&calendars, "me@example.com", &selection)
+        };
+
+        assert_eq!(select(&["primary"]), vec!["me@example.com"]);
+        assert_eq!(
+            select(&["primary", "focus@group.calendar.google.com"]),
+            vec!["me@example.com", "focus@group.calendar.google.com"]
+        );
+        assert_eq!(
+            select(&["all", "primary", "other@example.com"]),
+            vec![
+                "me@example.com",
+                "team@group.calendar.google.com",
+                "focus@group.calendar.google.com",
+                "other@example.com",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cached_account_calendar_filter_and_staleness() {
+        let now = chrono::Utc.ymd(2020, 3, 2).and_hms(10, 0, 0);
+        let cache: cache::EventCache = serde_json::from_str(&format!(
+            r#"{{"last_synced": {}, "calendars": {{}}}}"#,
+            (now - chrono::Duration::minutes(45)).timestamp()
+        ))
+        .unwrap();
+        let account = CachedAccount {
+            email: String::from("me@example.com"),
+            settings: Arc::new(GoogleCalSettings {
+                sync_interval_minutes: 15,
+                ..GoogleCalSettings::default()
+            }),
+            selection: vec![
+                String::from("primary"),
+                String::from("team@group.calendar.google.com"),
+            ],
+            calendar_ids: Arc::new(Mutex::new(None)),
+            cache: Arc::new(Mutex::new(cache)),
+            problem: Arc::new(Mutex::new(None)),
+            _stop: StopSignal::new().0,
+        };
+
+        // Before the calendar list has been fetched, only the setting is
+        // used.
+        let is_checked = account.calendar_filter();
+        assert!(is_checked("me@example.com"));
+        assert!(is_checked("team@group.calendar.google.com"));
+        assert!(!is_checked("removed@group.calendar.google.com"));
+
+        *account.calendar_ids.lock().unwrap() =
+            Some(vec![String::from("primary-id@example.com")]);
+        let is_checked = account.calendar_filter();
+        assert!(is_checked("primary-id@example.com"));
+        assert!(!is_checked("me@example.com"));
+
+        assert!(account
+            .staleness(now)
+            .unwrap()
+            .contains("last synced 45 minutes ago"));
+        assert_eq!(
+            account.staleness(now - chrono::Duration::minutes(20)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stop_signal() {
+        let (stop_sender, stop) = StopSignal::new();
+        assert!(!stop.is_stopped());
+        assert!(stop.sleep(std::time::Duration::from_millis(1)));
+
+        // Dropping the account drops the sender, which stops the thread
+        // without waiting out the sleep.
+        drop(stop_sender);
+        assert!(stop.is_stopped());
+        assert!(!stop.sleep(std::time::Duration::from_secs(3600)));
     }
 }
@@ -0,0 +1,259 @@
+// This module handles signing in to Google accounts.  Signing in only happens
+// with `break-time google-calendar login`.  The background sync thread only
+// ever uses (and refreshes) tokens that are already stored on disk.
+
+use super::GoogleCalErr;
+
+use std::io;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use google_calendar3::Scope;
+use serde::Deserialize;
+use yup_oauth2::{
+    ApplicationSecret, Authenticator, AuthenticatorDelegate, DiskTokenStorage,
+    FlowType, PollInformation,
+};
+
+pub type Auth = Authenticator<Delegate, DiskTokenStorage, hyper::Client>;
+
+const GOOGLE_CLIENT_ID: &str =
+    "728095687622-mpib9rmdtck7e8ln9egelnns6na0me08.apps.googleusercontent.com";
+
+// It is weird embedding something called a "client_secret" directly in the source
+// code, but it doesn't seem like this needs to be something that is actually kept
+// secret:
+// https://stackoverflow.com/questions/59416326/safely-distribute-oauth-2-0-client-secret-in-desktop-applications-in-python
+const GOOGLE_CLIENT_SECRET: &str = "mI7MmEnboy8jdYEBjK9rZ2M2";
+
+/// The scopes that break-time asks for.  `ignore-event` needs to be able to
+/// edit events.
+pub fn scopes() -> [&'static str; 2] {
+    [Scope::Readonly.as_ref(), Scope::Event.as_ref()]
+}
+
+/// How to get the authorization code when signing in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthFlow {
+    /// Open a web server on localhost, and have Google redirect the browser to
+    /// it.
+    #[default]
+    Redirect,
+    /// Print a URL, and have the user paste back the code that Google shows.
+    /// This works when the browser is on another machine.
+    CopyPaste,
+    /// Print a short code to enter at google.com/device.  This needs a client
+    /// secret for a "TVs and Limited Input devices" client.
+    Device,
+}
+
+impl FromStr for AuthFlow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redirect" => Ok(Self::Redirect),
+            "copy-paste" => Ok(Self::CopyPaste),
+            "device" => Ok(Self::Device),
+            _ => Err(format!(
+                "unknown flow {:?}, expected one of redirect, copy-paste, device",
+                s
+            )),
+        }
+    }
+}
+
+/// Either the client secret embedded in break-time, or one read from the
+/// client secret JSON file downloaded from the Google Cloud Console.
+pub fn load_secret(
+    client_secret_file: Option<&Path>,
+) -> Result<ApplicationSecret, GoogleCalErr> {
+    match client_secret_file {
+        None => Ok(ApplicationSecret {
+            client_id: String::from(GOOGLE_CLIENT_ID),
+            client_secret: String::from(GOOGLE_CLIENT_SECRET),
+            token_uri: "https://oauth2.googleapis.com/token".to_string(),
+            auth_uri: "https://accounts.google.com/o/oauth2/auth".to_string(),
+            redirect_uris: vec![
+                "http://127.0.0.1".to_string(),
+                "urn:ietf:wg:oauth:2.0:oob".to_string(),
+            ],
+            ..ApplicationSecret::default()
+        }),
+        Some(path) => {
+            yup_oauth2::read_application_secret(path).map_err(|io_err| {
+                GoogleCalErr::ClientSecret {
+                    path: path.to_path_buf(),
+                    io_err,
+                }
+            })
+        }
+    }
+}
+
+pub fn token_path(break_time_cache_dir: &Path, email: &str) -> PathBuf {
+    break_time_cache_dir.join("google-calendar").join(email)
+}
+
+/// Open the token storage for an account, creating its directory if needed.
+pub fn token_storage(
+    token_path: &Path,
+) -> Result<DiskTokenStorage, GoogleCalErr> {
+    let to_storage_err = |io_err| GoogleCalErr::TokenStorage {
+        path: token_path.to_path_buf(),
+        io_err,
+    };
+
+    if let Some(parent) = token_path.parent() {
+        std::fs::create_dir_all(parent).map_err(to_storage_err)?;
+    }
+    let token_path_string = token_path.to_string_lossy().into_owned();
+    DiskTokenStorage::new(&token_path_string).map_err(to_storage_err)
+}
+
+/// Ask the OS for a free port for the redirect flow.
+fn get_available_port() -> Option<u16> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).ok()?;
+    listener.local_addr().ok().map(|addr| addr.port())
+}
+
+/// Create an authenticator.  With a `flow` of `None`, signing in is never
+/// started, so requests fail if there is no stored token for the account.
+pub fn create_auth(
+    secret: &ApplicationSecret,
+    disk_token_storage: DiskTokenStorage,
+    flow: Option<AuthFlow>,
+) -> Result<Auth, GoogleCalErr> {
+    let flow_type = match flow {
+        None | Some(AuthFlow::CopyPaste) => FlowType::InstalledInteractive,
+        Some(AuthFlow::Redirect) => {
+            let port =
+                get_available_port().ok_or(GoogleCalErr::NoAvailablePort)?;
+            FlowType::InstalledRedirect(port.into())
+        }
+        Some(AuthFlow::Device) => {
+            FlowType::Device(yup_oauth2::GOOGLE_DEVICE_CODE_URL.to_string())
+        }
+    };
+
+    let http_client_for_auth: hyper::Client = hyper::Client::with_connector(
+        hyper::net::HttpsConnector::new(hyper_rustls::TlsClient::new()),
+    );
+
+    Ok(Authenticator::new(
+        secret,
+        Delegate {
+            interactive: flow.is_some(),
+        },
+        http_client_for_auth,
+        disk_token_storage,
+        Some(flow_type),
+    ))
+}
+
+pub struct Delegate {
+    interactive: bool,
+}
+
+impl AuthenticatorDelegate for Delegate {
+    fn present_user_url(
+        &mut self,
+        url: &String,
+        need_code: bool,
+    ) -> Option<String> {
+        if !self.interactive {
+            println!("Google Calendar Plugin: Not signed in.  Sign in with `break-time google-calendar login <email>`.");
+            return None;
+        }
+
+        if need_code {
+            println!("Please direct your browser to {}, follow the instructions, and paste the code displayed there here:", url);
+            let mut code = String::new();
+            io::stdin().read_line(&mut code).ok().map(|_| code)
+        } else {
+            println!("Please direct your browser to {} and follow the instructions displayed there.", url);
+            None
+        }
+    }
+
+    fn present_user_code(&mut self, pi: &PollInformation) {
+        println!(
+            "Please go to {} and enter the code {}.  You have until {}.",
+            pi.verification_url,
+            pi.user_code,
+            pi.expires_at.with_timezone(&chrono::Local)
+        );
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StoredToken {
+    pub scopes: Option<Vec<String>>,
+    pub token: yup_oauth2::Token,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredTokens {
+    tokens: Vec<StoredToken>,
+}
+
+fn parse_stored_tokens(
+    tokens_str: &str,
+) -> serde_json::Result<Vec<StoredToken>> {
+    serde_json::from_str(tokens_str).map(|stored: StoredTokens| stored.tokens)
+}
+
+/// Read the tokens that `DiskTokenStorage` has stored for an account.  There
+/// are none if the account hasn't been signed in to.
+pub fn read_stored_tokens(token_path: &Path) -> io::Result<Vec<StoredToken>> {
+    match std::fs::read_to_string(token_path) {
+        Err(io_err) if io_err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(io_err) => Err(io_err),
+        Ok(tokens_str) => parse_stored_tokens(&tokens_str)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+    }
+}
+
+/// Revoke a token, so that it can't be used any more.
+pub fn revoke_token(token: &str) -> Result<(), String> {
+    let http_client: hyper::Client = hyper::Client::with_connector(
+        hyper::net::HttpsConnector::new(hyper_rustls::TlsClient::new()),
+    );
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("token", token)
+        .finish();
+    let resp = http_client
+        .post("https://oauth2.googleapis.com/revoke")
+        .header(hyper::header::ContentType::form_url_encoded())
+        .body(&body)
+        .send()
+        .map_err(|err| err.to_string())?;
+
+    if resp.status.is_success() {
+        Ok(())
+    } else {
+        Err(resp.status.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stored_tokens() {
+        let tokens = parse_stored_tokens(
+            r#"{"tokens":[{"hash":1,"scopes":["https://www.googleapis.com/auth/calendar.readonly"],"token":{"access_token":"a","refresh_token":"r","token_type":"Bearer","expires_in":3600,"expires_in_timestamp":1583146800}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].token.refresh_token, "r");
+        assert_eq!(tokens[0].token.expires_in_timestamp, Some(1_583_146_800));
+        assert_eq!(
+            tokens[0].scopes,
+            Some(vec![String::from(Scope::Readonly.as_ref())])
+        );
+    }
+}
@@ -0,0 +1,497 @@
+// This module holds the on-disk cache of upcoming Google Calendar events.  The
+// cache is kept up to date by a background thread using incremental sync
+// tokens, so that `GoogleCalendar::can_break` doesn't have to make any network
+// requests, and keeps working when the computer is offline.
+
+use super::{
+    get_ignored_event_ids, is_http_status, set_problem, CalFetcher,
+    GoogleCalErr, Problem, StopSignal,
+};
+use crate::scheduler::plugins::backoff::Backoff;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use google_calendar3::{Event, EventDateTime, Events, Scope};
+use serde::{Deserialize, Serialize};
+
+/// How far ahead to fetch events when doing a full sync.  A new full sync is
+/// done once this runs out.
+const SYNC_HORIZON_HOURS: i64 = 24;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// How many times a cache has been saved, to give each save its own
+/// temporary file.
+static SAVE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CalendarCache {
+    sync_token: Option<String>,
+    /// Unix timestamp of the end of the window fetched by the last full sync.
+    synced_until: i64,
+    events: HashMap<String, Event>,
+    /// Ids of events that are ignored because of their event type (like
+    /// working location or focus time events).
+    ignored_ids: HashSet<String>,
+}
+
+impl CalendarCache {
+    /// Apply the events from a sync.  Events that have been deleted come back
+    /// with a status of "cancelled".
+    fn apply_changes(&mut self, events: Vec<Event>) {
+        for event in events {
+            if let Some(id) = event.id.clone() {
+                if event.status.as_deref() == Some("cancelled") {
+                    self.events.remove(&id);
+                } else {
+                    self.events.insert(id, event);
+                }
+            }
+        }
+    }
+
+    /// Drop events that ended before `before`.
+    fn prune(&mut self, before: DateTime<Utc>) {
+        self.events.retain(|_, event| {
+            event_time_range(event).map_or(true, |(_, end)| end >= before)
+        });
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct EventCache {
+    /// Unix timestamp of the last time all the calendars were synced.
+    last_synced: Option<i64>,
+    calendars: HashMap<String, CalendarCache>,
+}
+
+impl EventCache {
+    /// Load the cache from `path`.  If it doesn't exist or can't be read, start
+    /// with an empty cache.
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|cache_str| serde_json::from_str(&cache_str).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let cache_str = serde_json::to_string(self)
+            .expect("Serializing the event cache to JSON should never fail");
+        // Write to a temporary file first, so that the cache isn't left
+        // half-written if break-time is killed.  Each save gets its own
+        // temporary file, in case another thread is saving the same cache.
+        let tmp_path = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            SAVE_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&tmp_path, cache_str)?;
+        std::fs::rename(&tmp_path, path)
+    }
+
+    /// How long ago the calendars were last synced, or `None` if they have
+    /// never been synced.
+    pub fn age(&self, now: DateTime<Utc>) -> Option<chrono::Duration> {
+        self.last_synced
+            .map(|last_synced| now - Utc.timestamp(last_synced, 0))
+    }
+
+    /// All the cached events that overlap the `start` to `end` window, from
+    /// the calendars that `is_checked` accepts.  The cache may still hold
+    /// calendars that were removed from the config file since the last sync,
+    /// so they are filtered out here.
+    pub fn events_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        is_checked: impl Fn(&str) -> bool,
+    ) -> Vec<&Event> {
+        self.calendars
+            .iter()
+            .filter(|(calendar_id, _)| is_checked(calendar_id))
+            .flat_map(|(_, cal_cache)| {
+                cal_cache.events.iter().filter_map(move |(id, event)| {
+                    if cal_cache.ignored_ids.contains(id) {
+                        return None;
+                    }
+                    let (event_start, event_end) = event_time_range(event)?;
+                    if event_start < end && event_end > start {
+                        Some(event)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+/// The start and end time of an event.  All-day events are taken to start and
+/// end at midnight in the local time zone.
+pub fn event_time_range(
+    event: &Event,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let start = parse_event_date_time(event.start.as_ref()?)?;
+    let end = parse_event_date_time(event.end.as_ref()?)?;
+    Some((start, end))
+}
+
+fn parse_event_date_time(date_time: &EventDateTime) -> Option<DateTime<Utc>> {
+    if let Some(date_time) = &date_time.date_time {
+        return DateTime::parse_from_rfc3339(date_time)
+            .ok()
+            .map(|date_time| date_time.with_timezone(&Utc));
+    }
+
+    let date =
+        chrono::NaiveDate::parse_from_str(date_time.date.as_ref()?, "%Y-%m-%d")
+            .ok()?;
+    chrono::Local
+        .from_local_datetime(&date.and_hms(0, 0, 0))
+        .earliest()
+        .map(|date_time| date_time.with_timezone(&Utc))
+}
+
+/// The API returns `410 Gone` when a sync token has expired.  A full sync is
+/// needed after that.
+fn is_sync_token_expired(err: &google_calendar3::Error) -> bool {
+    is_http_status(err, hyper::status::StatusCode::Gone)
+}
+
+fn list_events_page(
+    fetcher: &CalFetcher,
+    calendar_id: &str,
+    sync_token: Option<&str>,
+    window: (DateTime<Utc>, DateTime<Utc>),
+    page_token: Option<&str>,
+) -> google_calendar3::Result<Events> {
+    let mut call = fetcher
+        .hub
+        .events()
+        .list(calendar_id)
+        .add_scope(Scope::Readonly)
+        .add_scope(Scope::Event)
+        // Expand recurring events into single events.
+        .single_events(true);
+
+    // The time window can only be given on a full sync.  An incremental sync
+    // returns all the changes since the sync token was created.
+    call = match sync_token {
+        Some(sync_token) => call.sync_token(sync_token),
+        None => call
+            .time_min(&window.0.to_rfc3339())
+            .time_max(&window.1.to_rfc3339()),
+    };
+    if let Some(page_token) = page_token {
+        call = call.page_token(page_token);
+    }
+
+    call.doit().map(|(_, events)| events)
+}
+
+fn sync_calendar(
+    fetcher: &CalFetcher,
+    calendar_id: &str,
+    cal_cache: &mut CalendarCache,
+    now: DateTime<Utc>,
+) -> Result<(), GoogleCalErr> {
+    let settings = &fetcher.settings;
+    let window_start =
+        now - chrono::Duration::minutes(settings.look_behind_minutes);
+    let look_ahead_end =
+        now + chrono::Duration::minutes(settings.look_ahead_minutes);
+
+    if cal_cache.sync_token.is_none()
+        || cal_cache.synced_until < look_ahead_end.timestamp()
+    {
+        *cal_cache = CalendarCache::default();
+    }
+
+    let mut page_token: Option<String> = None;
+
+    loop {
+        if cal_cache.sync_token.is_none() && page_token.is_none() {
+            // This is the start of a full sync.
+            cal_cache.synced_until =
+                (now + chrono::Duration::hours(SYNC_HORIZON_HOURS)).timestamp();
+        }
+        let window = (window_start, Utc.timestamp(cal_cache.synced_until, 0));
+
+        let res = list_events_page(
+            fetcher,
+            calendar_id,
+            cal_cache.sync_token.as_deref(),
+            window,
+            page_token.as_deref(),
+        );
+
+        let events = match res {
+            Err(err)
+                if cal_cache.sync_token.is_some()
+                    && is_sync_token_expired(&err) =>
+            {
+                println!(
+                    "Google Calendar Plugin: Sync token for {} expired, doing a full sync",
+                    calendar_id
+                );
+                *cal_cache = CalendarCache::default();
+                page_token = None;
+                continue;
+            }
+            Err(err) => {
+                return Err(GoogleCalErr::FetchingEvents {
+                    calendar_id: String::from(calendar_id),
+                    google_cal_err: err,
+                })
+            }
+            Ok(events) => events,
+        };
+
+        cal_cache.apply_changes(events.items.unwrap_or_default());
+
+        match events.next_page_token {
+            Some(next_page_token) => page_token = Some(next_page_token),
+            None => {
+                cal_cache.sync_token = events.next_sync_token;
+                break;
+            }
+        }
+    }
+
+    cal_cache.ignored_ids = get_ignored_event_ids(
+        &fetcher.hub,
+        calendar_id,
+        &settings.filter,
+        window_start,
+        Utc.timestamp(cal_cache.synced_until, 0),
+    )?;
+
+    cal_cache.prune(window_start);
+
+    Ok(())
+}
+
+fn sync_account(
+    fetcher: &CalFetcher,
+    cache: &Mutex<EventCache>,
+) -> Result<(), GoogleCalErr> {
+    let now = Utc::now();
+
+    for calendar_id in &fetcher.calendar_ids {
+        // Sync a copy of the calendar's cache, so that the cache isn't locked
+        // while waiting on the network.
+        let mut cal_cache = cache
+            .lock()
+            .expect("Google Calendar Plugin: cache mutex was poisoned")
+            .calendars
+            .get(calendar_id)
+            .cloned()
+            .unwrap_or_default();
+
+        sync_calendar(fetcher, calendar_id, &mut cal_cache, now)?;
+
+        cache
+            .lock()
+            .expect("Google Calendar Plugin: cache mutex was poisoned")
+            .calendars
+            .insert(calendar_id.clone(), cal_cache);
+    }
+
+    let mut cache = cache
+        .lock()
+        .expect("Google Calendar Plugin: cache mutex was poisoned");
+    cache.last_synced = Some(now.timestamp());
+    // Forget about calendars that are no longer being checked.
+    cache
+        .calendars
+        .retain(|calendar_id, _| fetcher.calendar_ids.contains(calendar_id));
+
+    Ok(())
+}
+
+/// Keep `cache` in sync with the calendars of `fetcher` until `stop` says to
+/// stop, saving it to `cache_path` after every successful sync.  Failures
+/// are retried with exponential backoff.
+pub fn run_sync(
+    fetcher: &CalFetcher,
+    cache: &Arc<Mutex<EventCache>>,
+    cache_path: &Path,
+    problem: &Problem,
+    stop: &StopSignal,
+) {
+    let sync_interval = Duration::from_secs(
+        u64::from(fetcher.settings.sync_interval_minutes) * 60,
+    );
+    let mut backoff = Backoff::new(MIN_BACKOFF, MAX_BACKOFF);
+
+    loop {
+        let res = sync_account(fetcher, cache);
+        // A newer thread for the same account may be saving the cache by now.
+        if stop.is_stopped() {
+            return;
+        }
+        let wait = match res {
+            Ok(()) => {
+                backoff.reset();
+                set_problem(problem, None);
+                let save_res = cache
+                    .lock()
+                    .expect("Google Calendar Plugin: cache mutex was poisoned")
+                    .save(cache_path);
+                if let Err(io_err) = save_res {
+                    println!(
+                        "Google Calendar Plugin: Could not save the event cache to {:?}: {}",
+                        cache_path, io_err
+                    );
+                }
+                sync_interval
+            }
+            Err(err) => {
+                let wait = backoff.next();
+                println!(
+                    "{}\nGoogle Calendar Plugin: Could not sync events for {}, retrying in {} seconds",
+                    err,
+                    fetcher.email,
+                    wait.as_secs()
+                );
+                set_problem(
+                    problem,
+                    Some(format!(
+                        "Google Calendar events for {} could not be synced",
+                        fetcher.email
+                    )),
+                );
+                wait
+            }
+        };
+        if !stop.sleep(wait) {
+            return;
+        }
+    }
+}
+
+/// The file that the event cache for `email` is stored in.
+pub fn cache_path(break_time_cache_dir: &Path, email: &str) -> PathBuf {
+    break_time_cache_dir
+        .join("google-calendar-events")
+        .join(format!("{}.json", email))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str, start: &str, end: &str, status: &str) -> Event {
+        Event {
+            id: Some(String::from(id)),
+            status: Some(String::from(status)),
+            start: Some(EventDateTime {
+                date_time: Some(String::from(start)),
+                ..EventDateTime::default()
+            }),
+            end: Some(EventDateTime {
+                date_time: Some(String::from(end)),
+                ..EventDateTime::default()
+            }),
+            ..Event::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_apply_changes_and_events_between() {
+        let mut cal_cache = CalendarCache::default();
+        cal_cache.apply_changes(vec![
+            event(
+                "a",
+                "2020-03-02T10:00:00Z",
+                "2020-03-02T11:00:00Z",
+                "confirmed",
+            ),
+            event(
+                "b",
+                "2020-03-02T12:00:00Z",
+                "2020-03-02T13:00:00Z",
+                "confirmed",
+            ),
+            event(
+                "c",
+                "2020-03-02T08:00:00Z",
+                "2020-03-02T09:00:00Z",
+                "confirmed",
+            ),
+        ]);
+        // An incremental sync that deletes "b".
+        cal_cache.apply_changes(vec![event(
+            "b",
+            "2020-03-02T12:00:00Z",
+            "2020-03-02T13:00:00Z",
+            "cancelled",
+        )]);
+        cal_cache.prune(Utc.ymd(2020, 3, 2).and_hms(9, 30, 0));
+        assert_eq!(cal_cache.events.len(), 1);
+
+        let mut cache = EventCache::default();
+        cache
+            .calendars
+            .insert(String::from("me@example.com"), cal_cache);
+
+        let overlapping = cache.events_between(
+            Utc.ymd(2020, 3, 2).and_hms(10, 50, 0),
+            Utc.ymd(2020, 3, 2).and_hms(11, 20, 0),
+            |_| true,
+        );
+        assert_eq!(overlapping.len(), 1);
+
+        let not_overlapping = cache.events_between(
+            Utc.ymd(2020, 3, 2).and_hms(11, 0, 0),
+            Utc.ymd(2020, 3, 2).and_hms(11, 30, 0),
+            |_| true,
+        );
+        assert!(not_overlapping.is_empty());
+
+        // A calendar that is no longer checked doesn't count.
+        let not_checked = cache.events_between(
+            Utc.ymd(2020, 3, 2).and_hms(10, 50, 0),
+            Utc.ymd(2020, 3, 2).and_hms(11, 20, 0),
+            |calendar_id| calendar_id != "me@example.com",
+        );
+        assert!(not_checked.is_empty());
+    }
+
+    #[test]
+    fn test_save_from_several_threads() {
+        let cache_dir = std::env::temp_dir()
+            .join(format!("break-time-event-cache-{}", std::process::id()));
+        let path = cache_path(&cache_dir, "me@example.com");
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || EventCache::default().save(&path))
+            })
+            .collect();
+        for thread in threads {
+            assert!(thread.join().unwrap().is_ok());
+        }
+
+        // Only the cache itself is left behind.
+        let files = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+        assert_eq!(
+            files,
+            vec![std::ffi::OsString::from("me@example.com.json")]
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use super::calendar::{self, expand_home, ical, BusyEvent};
+use super::{BusyBlock, CanBreak, Plugin};
+
+use crate::config::{Config, PluginSettings};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Deserialize;
+
+use chrono::{DateTime, Utc};
+
+use ical::Event;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct IcsCalendarSettings {
+    /// Paths to `.ics` files, or directories containing `.ics` files.
+    #[serde(default)]
+    paths: Vec<PathBuf>,
+    /// The user's own email addresses.  These are used to find the user in
+    /// the attendee list of an event.
+    #[serde(default)]
+    emails: Vec<String>,
+}
+
+impl IcsCalendarSettings {
+    fn from_plugin_settings(
+        plugin_settings: &PluginSettings,
+    ) -> Result<Self, ()> {
+        match plugin_settings.0.get("ics_calendar") {
+            // If the "ics_calendar" key doesn't exist, then just skip.
+            None => Ok(Self::default()),
+            Some(val) => val.clone().try_into().map_err(|_err| ()),
+        }
+    }
+}
+
+struct CachedFile {
+    modified: SystemTime,
+    events: Vec<Event>,
+}
+
+pub struct IcsCalendar {
+    paths: Vec<PathBuf>,
+    emails: Vec<String>,
+    files: Mutex<HashMap<PathBuf, CachedFile>>,
+    /// The files and directories that couldn't be read or parsed the last
+    /// time they were loaded.
+    problems: Mutex<Vec<String>>,
+}
+
+impl IcsCalendar {
+    pub fn new(config: &Config) -> Result<Self, ()> {
+        let settings = IcsCalendarSettings::from_plugin_settings(
+            &config.settings.all_plugin_settings,
+        )?;
+
+        Ok(Self {
+            paths: settings
+                .paths
+                .iter()
+                .map(|path| expand_home(path))
+                .collect(),
+            emails: settings
+                .emails
+                .iter()
+                .map(|email| email.to_lowercase())
+                .collect(),
+            files: Mutex::new(HashMap::new()),
+            problems: Mutex::new(vec![]),
+        })
+    }
+
+    /// Find all the `.ics` files in the configured paths.  Directories are
+    /// searched recursively.  Paths that can't be read are skipped, and
+    /// returned as errors.
+    fn ics_files(&self) -> (Vec<PathBuf>, Vec<IcsCalErr>) {
+        fn go(
+            path: &Path,
+            files: &mut Vec<PathBuf>,
+            errs: &mut Vec<IcsCalErr>,
+        ) {
+            let metadata = match std::fs::metadata(path) {
+                Err(io_err) => {
+                    errs.push(IcsCalErr::io(path, io_err));
+                    return;
+                }
+                Ok(metadata) => metadata,
+            };
+
+            if metadata.is_dir() {
+                let entries = match std::fs::read_dir(path) {
+                    Err(io_err) => {
+                        errs.push(IcsCalErr::io(path, io_err));
+                        return;
+                    }
+                    Ok(entries) => entries,
+                };
+                for entry in entries {
+                    let entry_path = match entry {
+                        Err(io_err) => {
+                            errs.push(IcsCalErr::io(path, io_err));
+                            continue;
+                        }
+                        Ok(entry) => entry.path(),
+                    };
+                    let is_ics = entry_path
+                        .extension()
+                        .map_or(false, |ext| ext.eq_ignore_ascii_case("ics"));
+                    if is_ics || entry_path.is_dir() {
+                        go(&entry_path, files, errs);
+                    }
+                }
+            } else {
+                files.push(path.to_path_buf());
+            }
+        }
+
+        let mut files = vec![];
+        let mut errs = vec![];
+        for path in &self.paths {
+            go(path, &mut files, &mut errs);
+        }
+        (files, errs)
+    }
+
+    /// Read the `.ics` file at `path`, unless it hasn't changed on disk since
+    /// the last time it was read.
+    fn reload_file(
+        path: &Path,
+        files: &mut HashMap<PathBuf, CachedFile>,
+    ) -> Result<(), IcsCalErr> {
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|io_err| IcsCalErr::io(path, io_err))?;
+
+        let is_fresh = files
+            .get(path)
+            .map_or(false, |cached| cached.modified == modified);
+
+        if !is_fresh {
+            let src = std::fs::read_to_string(path)
+                .map_err(|io_err| IcsCalErr::io(path, io_err))?;
+            let events = ical::parse_events(&src).map_err(|parse_err| {
+                IcsCalErr::Parsing {
+                    path: path.to_path_buf(),
+                    parse_err,
+                }
+            })?;
+            println!(
+                "ICS Calendar Plugin: Loaded {} events from {:?}",
+                events.len(),
+                path
+            );
+            files.insert(path.to_path_buf(), CachedFile { modified, events });
+        }
+
+        Ok(())
+    }
+
+    /// Re-read any `.ics` files that have changed on disk since the last
+    /// time they were read, and forget about files that have been removed.
+    /// Files that can't be read or parsed are skipped, and kept as problems.
+    fn reload_changed_files(&self, files: &mut HashMap<PathBuf, CachedFile>) {
+        let (ics_files, mut errs) = self.ics_files();
+
+        files.retain(|path, _| ics_files.contains(path));
+
+        for path in ics_files {
+            if let Err(err) = Self::reload_file(&path, files) {
+                files.remove(&path);
+                errs.push(err);
+            }
+        }
+
+        for err in &errs {
+            println!("{}, skipping it", err);
+        }
+        *self
+            .problems
+            .lock()
+            .expect("ICS Calendar Plugin: problems mutex was poisoned") =
+            errs.iter().map(ToString::to_string).collect();
+    }
+
+    /// The events in all the files from `start_time` to `end_time` that
+    /// should stop a break from happening.
+    fn busy_events(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Vec<BusyEvent> {
+        let mut files = self
+            .files
+            .lock()
+            .expect("ICS Calendar Plugin: files mutex was poisoned");
+
+        self.reload_changed_files(&mut files);
+
+        files
+            .iter()
+            .flat_map(|(path, cached)| {
+                ical::occurrences_between(&cached.events, start_time, end_time)
+                    .into_iter()
+                    .filter(|occurrence| {
+                        calendar::filter_ical_event(
+                            occurrence.event,
+                            &self.emails,
+                        )
+                    })
+                    .map(move |occurrence| {
+                        BusyEvent::new(
+                            &path.display(),
+                            occurrence.event.summary.clone(),
+                            occurrence.start,
+                            occurrence.end,
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    fn can_break(&self) -> CanBreak {
+        let (start_time, end_time) =
+            calendar::break_check_window(chrono::Utc::now());
+        calendar::can_break_with(&self.busy_events(start_time, end_time))
+    }
+}
+
+#[derive(Debug)]
+pub enum IcsCalErr {
+    Io {
+        path: PathBuf,
+        io_err: std::io::Error,
+    },
+    Parsing {
+        path: PathBuf,
+        parse_err: ical::ParseErr,
+    },
+}
+
+impl IcsCalErr {
+    fn io(path: &Path, io_err: std::io::Error) -> Self {
+        Self::Io {
+            path: path.to_path_buf(),
+            io_err,
+        }
+    }
+}
+
+impl std::error::Error for IcsCalErr {}
+
+impl std::fmt::Display for IcsCalErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, io_err } => write!(
+                f,
+                "ICS Calendar Plugin: Error reading {:?}: {}",
+                path, io_err
+            ),
+            Self::Parsing { path, parse_err } => write!(
+                f,
+                "ICS Calendar Plugin: Error parsing {:?}: {}",
+                path, parse_err
+            ),
+        }
+    }
+}
+
+impl Plugin for IcsCalendar {
+    fn can_break_now(&self) -> Result<CanBreak, Box<dyn std::error::Error>> {
+        Ok(self.can_break())
+    }
+
+    fn name(&self) -> String {
+        String::from("ics_calendar")
+    }
+
+    fn problems(&self) -> Vec<String> {
+        self.problems
+            .lock()
+            .expect("ICS Calendar Plugin: problems mutex was poisoned")
+            .clone()
+    }
+
+    fn busy_blocks(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Option<Vec<BusyBlock>> {
+        Some(
+            self.busy_events(start, end)
+                .into_iter()
+                .map(|busy_event| busy_event.block)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    fn ics(uid: &str, start: &str, end: &str) -> String {
+        format!(
+            "BEGIN:VCALENDAR\r\n\
+             BEGIN:VEVENT\r\n\
+             UID:{}\r\n\
+             SUMMARY:{}\r\n\
+             DTSTART:{}\r\n\
+             DTEND:{}\r\n\
+             END:VEVENT\r\n\
+             END:VCALENDAR\r\n",
+            uid, uid, start, end
+        )
+    }
+
+    /// Write `contents` to `path`, and give it the modification time
+    /// `modified`, so that tests don't depend on the timestamp resolution of
+    /// the file system.
+    fn write_file(path: &Path, contents: &[u8], modified: SystemTime) {
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    fn test_calendar(test_name: &str) -> (IcsCalendar, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "break-time-ics-{}-{}",
+            test_name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let calendar = IcsCalendar {
+            paths: vec![dir.clone()],
+            emails: vec![],
+            files: Mutex::new(HashMap::new()),
+            problems: Mutex::new(vec![]),
+        };
+        (calendar, dir)
+    }
+
+    fn block(start: (u32, u32), end: (u32, u32)) -> BusyBlock {
+        BusyBlock {
+            start: Utc.ymd(2020, 3, 2).and_hms(start.0, start.1, 0),
+            end: Utc.ymd(2020, 3, 2).and_hms(end.0, end.1, 0),
+        }
+    }
+
+    #[test]
+    fn test_reloads_changed_files() {
+        let (calendar, dir) = test_calendar("reload");
+        let path = dir.join("work.ics");
+        let window = block((10, 0), (11, 0));
+        let modified =
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_583_000_000);
+
+        write_file(
+            &path,
+            ics("standup", "20200302T101500Z", "20200302T104500Z").as_bytes(),
+            modified,
+        );
+        let before = calendar.busy_blocks(window.start, window.end);
+
+        write_file(
+            &path,
+            ics("standup", "20200302T103000Z", "20200302T105000Z").as_bytes(),
+            modified + Duration::from_secs(60),
+        );
+        let after = calendar.busy_blocks(window.start, window.end);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(before, Some(vec![block((10, 15), (10, 45))]));
+        assert_eq!(after, Some(vec![block((10, 30), (10, 50))]));
+    }
+
+    #[test]
+    fn test_skips_bad_files() {
+        let (calendar, dir) = test_calendar("bad-files");
+        let window = block((10, 0), (11, 0));
+        let modified =
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_583_000_000);
+
+        write_file(
+            &dir.join("good.ics"),
+            ics("standup", "20200302T101500Z", "20200302T104500Z").as_bytes(),
+            modified,
+        );
+        write_file(
+            &dir.join("malformed.ics"),
+            b"BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n",
+            modified,
+        );
+        // Not UTF-8, so it can't be read as text.
+        write_file(&dir.join("binary.ics"), &[0xff, 0xfe, 0x00], modified);
+
+        let busy_blocks = calendar.busy_blocks(window.start, window.end);
+        let problems = calendar.problems();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(busy_blocks, Some(vec![block((10, 15), (10, 45))]));
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        for file_name in &["binary.ics", "malformed.ics"] {
+            assert!(
+                problems.iter().any(|problem| problem.contains(file_name)),
+                "{:?}",
+                problems
+            );
+        }
+    }
+}
@@ -0,0 +1,843 @@
+mod auth;
+
+use super::calendar::{self, BusyEvent};
+use super::{BusyBlock, CanBreak, Plugin};
+
+use crate::config::{Config, PluginSettings};
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use hyper::header::{Authorization, Bearer, ContentType, Headers};
+use hyper::Url;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use auth::Authenticator;
+
+#[derive(Clone, Debug, Deserialize)]
+struct MicrosoftCalSettings {
+    /// The email addresses of the accounts to check.  Each one has to be
+    /// signed in with `break-time microsoft-calendar login`.
+    #[serde(default)]
+    accounts: Vec<String>,
+    /// The application (client) ID of an app registered in Azure AD with the
+    /// device code flow enabled.
+    #[serde(default)]
+    client_id: String,
+    #[serde(default = "default_tenant")]
+    tenant: String,
+    #[serde(default = "default_graph_url")]
+    graph_url: String,
+    #[serde(default = "default_login_url")]
+    login_url: String,
+}
+
+fn default_tenant() -> String {
+    String::from("common")
+}
+
+fn default_graph_url() -> String {
+    String::from("https://graph.microsoft.com/v1.0")
+}
+
+fn default_login_url() -> String {
+    String::from("https://login.microsoftonline.com")
+}
+
+impl Default for MicrosoftCalSettings {
+    fn default() -> Self {
+        Self {
+            accounts: vec![],
+            client_id: String::new(),
+            tenant: default_tenant(),
+            graph_url: default_graph_url(),
+            login_url: default_login_url(),
+        }
+    }
+}
+
+impl MicrosoftCalSettings {
+    fn from_plugin_settings(
+        plugin_settings: &PluginSettings,
+    ) -> Result<Self, ()> {
+        match plugin_settings.0.get("microsoft_calendar") {
+            // If the "microsoft_calendar" key doesn't exist, then just skip.
+            None => Ok(Self::default()),
+            Some(val) => val.clone().try_into().map_err(|_err| ()),
+        }
+    }
+}
+
+/// The signed-in user, as returned by `GET /me`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Me {
+    mail: Option<String>,
+    user_principal_name: Option<String>,
+}
+
+impl Me {
+    fn is_account(&self, email: &str) -> bool {
+        [&self.mail, &self.user_principal_name]
+            .iter()
+            .filter_map(|opt_address| opt_address.as_deref())
+            .any(|address| address.eq_ignore_ascii_case(email))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CalendarView {
+    value: Vec<GraphEvent>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphEvent {
+    subject: Option<String>,
+    body: Option<ItemBody>,
+    show_as: Option<String>,
+    #[serde(default)]
+    is_cancelled: bool,
+    response_status: Option<ResponseStatus>,
+    start: Option<GraphDateTime>,
+    end: Option<GraphDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ItemBody {
+    content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseStatus {
+    response: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphDateTime {
+    date_time: String,
+}
+
+impl GraphDateTime {
+    /// The `Prefer: outlook.timezone="UTC"` header makes Graph return times
+    /// in UTC, without an offset, like `2020-03-02T10:00:00.0000000`.
+    fn to_utc(&self) -> Option<DateTime<Utc>> {
+        chrono::NaiveDateTime::parse_from_str(
+            &self.date_time,
+            "%Y-%m-%dT%H:%M:%S%.f",
+        )
+        .ok()
+        .map(|naive| DateTime::from_utc(naive, Utc))
+    }
+}
+
+/// Return `true` if a Graph `event` should stop a break from happening.
+fn filter_event(event: &GraphEvent) -> bool {
+    if let Some(content) = event.body.as_ref().and_then(|b| b.content.as_ref())
+    {
+        if calendar::is_ignored_by_description(content) {
+            return false;
+        }
+    }
+
+    // Ignore cancelled events.
+    if event.is_cancelled {
+        return false;
+    }
+
+    // Ignore events that are marked as "free" instead of "busy".
+    if event.show_as.as_deref() == Some("free") {
+        return false;
+    }
+
+    // Ignore events that you have declined.
+    let declined = event
+        .response_status
+        .as_ref()
+        .and_then(|status| status.response.as_deref())
+        == Some("declined");
+
+    !declined
+}
+
+/// Send a `POST` request with a form-encoded body, and return the status and
+/// body of the response.
+fn post_form(
+    http_client: &hyper::Client,
+    url: &str,
+    params: &[(&str, &str)],
+) -> Result<(hyper::status::StatusCode, String), MicrosoftCalErr> {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+
+    let mut response = http_client
+        .post(url)
+        .header(ContentType::form_url_encoded())
+        .body(&body)
+        .send()
+        .map_err(|http_err| MicrosoftCalErr::Http {
+            url: url.to_string(),
+            http_err,
+        })?;
+
+    let response_body = read_body(url, &mut response)?;
+    Ok((response.status, response_body))
+}
+
+fn read_body(
+    url: &str,
+    response: &mut hyper::client::Response,
+) -> Result<String, MicrosoftCalErr> {
+    let mut response_body = String::new();
+    response
+        .read_to_string(&mut response_body)
+        .map_err(|io_err| MicrosoftCalErr::Io {
+            url: url.to_string(),
+            io_err,
+        })?;
+    Ok(response_body)
+}
+
+fn check_status(
+    url: &str,
+    status: hyper::status::StatusCode,
+    body: String,
+) -> Result<String, MicrosoftCalErr> {
+    if status.is_success() {
+        Ok(body)
+    } else {
+        Err(MicrosoftCalErr::Status {
+            url: url.to_string(),
+            status,
+            body,
+        })
+    }
+}
+
+fn parse_json<T: DeserializeOwned>(
+    url: &str,
+    body: &str,
+) -> Result<T, MicrosoftCalErr> {
+    serde_json::from_str(body).map_err(|json_err| MicrosoftCalErr::Json {
+        url: url.to_string(),
+        json_err,
+    })
+}
+
+/// The URL of the `calendarView` of the signed-in user for the `start_time`
+/// to `end_time` window.
+fn calendar_view_url(
+    graph_url: &str,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+) -> Result<Url, MicrosoftCalErr> {
+    let base = format!("{}/me/calendarView", graph_url.trim_end_matches('/'));
+    let mut url =
+        Url::parse(&base).map_err(|url_err| MicrosoftCalErr::Url {
+            url: base.clone(),
+            url_err,
+        })?;
+    url.query_pairs_mut()
+        .append_pair("startDateTime", &start_time.to_rfc3339())
+        .append_pair("endDateTime", &end_time.to_rfc3339())
+        .append_pair(
+            "$select",
+            "subject,body,showAs,isCancelled,responseStatus,start,end",
+        );
+    Ok(url)
+}
+
+fn token_path(break_time_cache_dir: &Path, email: &str) -> PathBuf {
+    break_time_cache_dir.join("microsoft-calendar").join(email)
+}
+
+struct MicrosoftAccount {
+    email: String,
+    graph_url: String,
+    http_client: hyper::Client,
+    auth: Authenticator,
+    problem: Mutex<Option<String>>,
+}
+
+impl MicrosoftAccount {
+    fn new(
+        break_time_cache_dir: &Path,
+        settings: &MicrosoftCalSettings,
+        email: String,
+    ) -> Self {
+        let auth = Authenticator::new(
+            &settings.login_url,
+            &settings.tenant,
+            settings.client_id.clone(),
+            token_path(break_time_cache_dir, &email),
+        );
+
+        let http_client: hyper::Client = hyper::Client::with_connector(
+            hyper::net::HttpsConnector::new(hyper_rustls::TlsClient::new()),
+        );
+
+        Self {
+            email,
+            graph_url: settings.graph_url.clone(),
+            http_client,
+            auth,
+            problem: Mutex::new(None),
+        }
+    }
+
+    fn set_problem(&self, problem: Option<String>) {
+        *self
+            .problem
+            .lock()
+            .expect("Microsoft Calendar Plugin: problem mutex was poisoned") =
+            problem;
+    }
+
+    fn problem(&self) -> Option<String> {
+        self.problem
+            .lock()
+            .expect("Microsoft Calendar Plugin: problem mutex was poisoned")
+            .clone()
+    }
+
+    fn get(&self, url: &str) -> Result<String, MicrosoftCalErr> {
+        self.get_with_token(url, self.auth.access_token()?)
+    }
+
+    fn get_with_token(
+        &self,
+        url: &str,
+        access_token: String,
+    ) -> Result<String, MicrosoftCalErr> {
+        let mut headers = Headers::new();
+        headers.set(Authorization(Bearer {
+            token: access_token,
+        }));
+        // Get the start and end times in UTC, and the body as plain text
+        // instead of HTML.
+        headers.set_raw(
+            "Prefer",
+            vec![
+                b"outlook.timezone=\"UTC\"".to_vec(),
+                b"outlook.body-content-type=\"text\"".to_vec(),
+            ],
+        );
+
+        let mut response =
+            self.http_client.get(url).headers(headers).send().map_err(
+                |http_err| MicrosoftCalErr::Http {
+                    url: url.to_string(),
+                    http_err,
+                },
+            )?;
+
+        let response_body = read_body(url, &mut response)?;
+        check_status(url, response.status, response_body)
+    }
+
+    /// Check that `access_token` belongs to this account, and not to some
+    /// other account the user happened to be signed in to in their browser.
+    fn check_account(&self, access_token: &str) -> Result<(), MicrosoftCalErr> {
+        let url = format!(
+            "{}/me?$select=mail,userPrincipalName",
+            self.graph_url.trim_end_matches('/')
+        );
+        let me: Me = parse_json(
+            &url,
+            &self.get_with_token(&url, access_token.to_string())?,
+        )?;
+        if me.is_account(&self.email) {
+            Ok(())
+        } else {
+            Err(MicrosoftCalErr::Auth(format!(
+                "Signed in to {} instead of {}",
+                me.mail
+                    .or(me.user_principal_name)
+                    .unwrap_or_else(|| String::from("an unknown account")),
+                self.email
+            )))
+        }
+    }
+
+    fn get_events(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<GraphEvent>, MicrosoftCalErr> {
+        let mut events = vec![];
+        let mut opt_url = Some(
+            calendar_view_url(&self.graph_url, start_time, end_time)?
+                .into_string(),
+        );
+
+        while let Some(url) = opt_url {
+            let calendar_view: CalendarView =
+                parse_json(&url, &self.get(&url)?)?;
+            events.extend(calendar_view.value);
+            opt_url = calendar_view.next_link;
+        }
+
+        Ok(events)
+    }
+
+    /// The events from `start_time` to `end_time` that should stop a break
+    /// from happening.  Returns `None` if the account isn't signed in.
+    fn busy_events(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Option<Vec<BusyEvent>>, MicrosoftCalErr> {
+        if !self.auth.is_signed_in() {
+            self.set_problem(Some(format!(
+                "Microsoft Calendar account {} is not signed in.  Run `break-time microsoft-calendar login {}`.",
+                self.email, self.email
+            )));
+            return Ok(None);
+        }
+        self.set_problem(None);
+
+        let busy_events = self
+            .get_events(start_time, end_time)?
+            .into_iter()
+            .filter(filter_event)
+            .map(|event| {
+                // If Graph sends a time that can't be parsed, treat the
+                // event as taking up the whole window, since it was returned
+                // for it.
+                BusyEvent::new(
+                    &self.email,
+                    event.subject,
+                    event
+                        .start
+                        .and_then(|start| start.to_utc())
+                        .unwrap_or(start_time),
+                    event.end.and_then(|end| end.to_utc()).unwrap_or(end_time),
+                )
+            })
+            .collect();
+
+        Ok(Some(busy_events))
+    }
+
+    fn can_break(&self) -> Result<CanBreak, MicrosoftCalErr> {
+        let (start_time, end_time) =
+            calendar::break_check_window(chrono::Utc::now());
+        Ok(self
+            .busy_events(start_time, end_time)?
+            .map_or(CanBreak::Yes, |busy_events| {
+                calendar::can_break_with(&busy_events)
+            }))
+    }
+}
+
+pub struct MicrosoftCalendar {
+    accounts: Vec<MicrosoftAccount>,
+}
+
+impl MicrosoftCalendar {
+    pub fn new(config: &Config) -> Result<Self, ()> {
+        let settings = MicrosoftCalSettings::from_plugin_settings(
+            &config.settings.all_plugin_settings,
+        )?;
+
+        if !settings.accounts.is_empty() && settings.client_id.is_empty() {
+            println!(
+                "Microsoft Calendar Plugin: plugin.microsoft_calendar.client_id must be set in order to use plugin.microsoft_calendar.accounts"
+            );
+            return Err(());
+        }
+
+        let accounts = settings
+            .accounts
+            .iter()
+            .map(|email| {
+                MicrosoftAccount::new(
+                    &config.cache_dir,
+                    &settings,
+                    email.clone(),
+                )
+            })
+            .collect();
+
+        Ok(Self { accounts })
+    }
+
+    fn can_break(&self) -> Result<CanBreak, MicrosoftCalErr> {
+        self.accounts.iter().map(MicrosoftAccount::can_break).fold(
+            Ok(CanBreak::Yes),
+            |accum, can_break_res| match (accum, can_break_res) {
+                (Err(err), _) => Err(err),
+                (_, Err(err)) => Err(err),
+                (Ok(CanBreak::No), _) => Ok(CanBreak::No),
+                (_, can_break) => can_break,
+            },
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum MicrosoftCalErr {
+    Http {
+        url: String,
+        http_err: hyper::Error,
+    },
+    Status {
+        url: String,
+        status: hyper::status::StatusCode,
+        body: String,
+    },
+    Io {
+        url: String,
+        io_err: std::io::Error,
+    },
+    Json {
+        url: String,
+        json_err: serde_json::Error,
+    },
+    Url {
+        url: String,
+        url_err: hyper::error::ParseError,
+    },
+    TokenStorage {
+        path: PathBuf,
+        io_err: std::io::Error,
+    },
+    Auth(String),
+}
+
+impl std::error::Error for MicrosoftCalErr {}
+
+impl std::fmt::Display for MicrosoftCalErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Http { url, http_err } => write!(
+                f,
+                "Microsoft Calendar Plugin: Error sending request to {}: {}",
+                url, http_err
+            ),
+            Self::Status { url, status, body } => write!(
+                f,
+                "Microsoft Calendar Plugin: Unexpected response status from {}: {}\n{}",
+                url, status, body
+            ),
+            Self::Io { url, io_err } => write!(
+                f,
+                "Microsoft Calendar Plugin: Error reading response from {}: {}",
+                url, io_err
+            ),
+            Self::Json { url, json_err } => write!(
+                f,
+                "Microsoft Calendar Plugin: Error parsing JSON response from {}: {}",
+                url, json_err
+            ),
+            Self::Url { url, url_err } => write!(
+                f,
+                "Microsoft Calendar Plugin: Error parsing url {}: {}",
+                url, url_err
+            ),
+            Self::TokenStorage { path, io_err } => write!(
+                f,
+                "Microsoft Calendar Plugin: Error writing token to {:?}: {}",
+                path, io_err
+            ),
+            Self::Auth(msg) => {
+                write!(f, "Microsoft Calendar Plugin: {}", msg)
+            }
+        }
+    }
+}
+
+impl Plugin for MicrosoftCalendar {
+    fn can_break_now(&self) -> Result<CanBreak, Box<dyn std::error::Error>> {
+        self.can_break().map_err(|ms_cal_err| {
+            Box::new(ms_cal_err) as Box<dyn std::error::Error>
+        })
+    }
+
+    fn name(&self) -> String {
+        String::from("microsoft_calendar")
+    }
+
+    fn problems(&self) -> Vec<String> {
+        self.accounts
+            .iter()
+            .filter_map(MicrosoftAccount::problem)
+            .collect()
+    }
+
+    fn busy_blocks(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Option<Vec<BusyBlock>> {
+        calendar::combine_busy_blocks(
+            self.accounts
+                .iter()
+                .map(|account| account.busy_events(start, end)),
+        )
+    }
+}
+
+fn read_settings(config: &Config) -> MicrosoftCalSettings {
+    MicrosoftCalSettings::from_plugin_settings(
+        &config.settings.all_plugin_settings,
+    )
+    .expect("Could not parse the plugin.microsoft_calendar settings")
+}
+
+/// Sign in to a Microsoft account with the device code flow, check that the
+/// right account signed in, and store the token in the cache directory.
+pub fn login(config: &Config, email: &str) {
+    let settings = read_settings(config);
+
+    if settings.client_id.is_empty() {
+        println!(
+            "plugin.microsoft_calendar.client_id must be set in your config file to sign in."
+        );
+        return;
+    }
+
+    if !settings.accounts.iter().any(|account| account == email) {
+        println!(
+            "Warning: {} isn't in plugin.microsoft_calendar.accounts in your config file, so its calendar won't be checked.",
+            email
+        );
+    }
+
+    let account =
+        MicrosoftAccount::new(&config.cache_dir, &settings, email.to_string());
+
+    if account.auth.is_signed_in() {
+        println!(
+            "{} is already signed in.  To sign in again, first run `break-time microsoft-calendar logout {}`.",
+            email, email
+        );
+        return;
+    }
+
+    match account
+        .auth
+        .login(email, |access_token| account.check_account(access_token))
+    {
+        Err(err) => {
+            println!("{}", err);
+            println!("Signing in {} failed, so no token was stored.", email);
+        }
+        Ok(()) => println!(
+            "Signed in {}.  A running break-time starts checking its calendar at the next check.",
+            email
+        ),
+    }
+}
+
+/// Delete the stored token of a Microsoft account.
+pub fn logout(config: &Config, email: &str) {
+    let token_path = token_path(&config.cache_dir, email);
+
+    match std::fs::remove_file(&token_path) {
+        Err(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => {
+            println!("{} is not signed in.", email);
+        }
+        Err(io_err) => {
+            println!("Could not delete {:?}: {}", token_path, io_err);
+        }
+        Ok(()) => println!("Signed out {}.", email),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// Answer one request with each of `bodies` in turn, and send back the
+    /// request line and headers of each request.
+    fn serve(listener: TcpListener, bodies: Vec<String>) -> Receiver<String> {
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            for body in bodies {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+                sender.send(request).unwrap();
+            }
+        });
+        receiver
+    }
+
+    /// Create an account that talks to `graph_url`, with a token stored in a
+    /// cache directory named after `test_name` if `signed_in` is set.
+    fn test_account(
+        test_name: &str,
+        graph_url: &str,
+        signed_in: bool,
+    ) -> (PathBuf, MicrosoftAccount) {
+        let cache_dir = std::env::temp_dir().join(format!(
+            "break-time-{}-{}",
+            test_name,
+            std::process::id()
+        ));
+        let email = "me@example.com";
+        if signed_in {
+            let token_path = token_path(&cache_dir, email);
+            std::fs::create_dir_all(token_path.parent().unwrap()).unwrap();
+            std::fs::write(
+                &token_path,
+                r#"{"access_token":"test-token","refresh_token":null,"expires_at":4102444800}"#,
+            )
+            .unwrap();
+        }
+        let settings = MicrosoftCalSettings {
+            accounts: vec![email.to_string()],
+            client_id: String::from("test-client"),
+            graph_url: graph_url.to_string(),
+            ..MicrosoftCalSettings::default()
+        };
+        let account =
+            MicrosoftAccount::new(&cache_dir, &settings, email.to_string());
+        (cache_dir, account)
+    }
+
+    #[test]
+    fn test_get_events_follows_next_link() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let graph_url =
+            format!("http://{}/v1.0", listener.local_addr().unwrap());
+        let requests = serve(
+            listener,
+            vec![
+                format!(
+                    r#"{{"value": [{{"subject": "Lunch", "showAs": "free"}}],
+                        "@odata.nextLink": "{}/me/calendarView?$skiptoken=2"}}"#,
+                    graph_url
+                ),
+                String::from(
+                    r#"{"value": [{"subject": "Standup", "showAs": "busy",
+                        "start": {"dateTime": "2020-03-02T10:00:00.0000000", "timeZone": "UTC"}}]}"#,
+                ),
+            ],
+        );
+        let (cache_dir, account) =
+            test_account("microsoft-get-events", &graph_url, true);
+
+        let now = Utc::now();
+        let events = account
+            .get_events(now, now + chrono::Duration::minutes(10))
+            .unwrap();
+        let subjects: Vec<_> = events
+            .iter()
+            .filter_map(|event| event.subject.as_deref())
+            .collect();
+        assert_eq!(subjects, vec!["Lunch", "Standup"]);
+        assert_eq!(
+            events[1].start.as_ref().and_then(GraphDateTime::to_utc),
+            Some(Utc.ymd(2020, 3, 2).and_hms(10, 0, 0))
+        );
+
+        let first = requests.recv().unwrap();
+        assert!(first.starts_with("GET /v1.0/me/calendarView?startDateTime="));
+        assert!(first.contains("Authorization: Bearer test-token"));
+        let second = requests.recv().unwrap();
+        assert!(second.starts_with("GET /v1.0/me/calendarView?$skiptoken=2 "));
+        assert!(second.contains("Authorization: Bearer test-token"));
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_account() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let graph_url =
+            format!("http://{}/v1.0", listener.local_addr().unwrap());
+        let requests = serve(
+            listener,
+            vec![
+                String::from(
+                    r#"{"mail": null, "userPrincipalName": "Me@Example.com"}"#,
+                ),
+                String::from(
+                    r#"{"mail": "someone@example.com", "userPrincipalName": "someone@example.com"}"#,
+                ),
+            ],
+        );
+        let (cache_dir, account) =
+            test_account("microsoft-check-account", &graph_url, false);
+
+        assert!(account.check_account("new-token").is_ok());
+        assert!(account.check_account("new-token").is_err());
+
+        let first = requests.recv().unwrap();
+        assert!(first.starts_with("GET /v1.0/me?"));
+        assert!(first.contains("Authorization: Bearer new-token"));
+
+        // Nothing is stored until the account has been checked.
+        assert!(!cache_dir.exists());
+    }
+
+    #[test]
+    fn test_not_signed_in_is_a_problem() {
+        let (_, account) = test_account(
+            "microsoft-not-signed-in",
+            "http://127.0.0.1:1/v1.0",
+            false,
+        );
+
+        assert!(account.can_break().unwrap().into_bool());
+        assert!(account.problem().unwrap().contains("is not signed in"));
+    }
+
+    #[test]
+    fn test_filter_event() {
+        let calendar_view: CalendarView = serde_json::from_str(
+            r#"{
+  "value": [
+    { "subject": "Standup", "showAs": "busy", "isCancelled": false,
+      "responseStatus": { "response": "accepted" },
+      "start": { "dateTime": "2020-03-02T10:00:00.0000000", "timeZone": "UTC" },
+      "end": { "dateTime": "2020-03-02T10:15:00.0000000", "timeZone": "UTC" } },
+    { "subject": "Lunch", "showAs": "free" },
+    { "subject": "Offsite", "isCancelled": true },
+    { "subject": "Sync", "responseStatus": { "response": "declined" } },
+    { "subject": "Focus", "body": { "contentType": "text", "content": "Ignore break-time" } }
+  ],
+  "@odata.nextLink": "http://localhost:8080/me/calendarView?$skip=5"
+}"#,
+        )
+        .unwrap();
+
+        let kept: Vec<_> = calendar_view
+            .value
+            .iter()
+            .filter(|event| filter_event(event))
+            .filter_map(|event| event.subject.as_deref())
+            .collect();
+
+        assert_eq!(kept, vec!["Standup"]);
+        assert!(calendar_view.next_link.is_some());
+    }
+}
@@ -0,0 +1,324 @@
+// This module implements the OAuth 2.0 device authorization grant (the
+// "device code flow") against the Microsoft identity platform, and stores the
+// resulting tokens on disk so that they can be re-used the next time
+// break-time starts.  Signing in only happens from
+// `break-time microsoft-calendar login`, never while checking for events.
+
+use super::MicrosoftCalErr;
+
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+const SCOPES: &str = "offline_access User.Read Calendars.Read";
+
+/// Refresh the access token this many seconds before it actually expires, so
+/// that it doesn't expire in the middle of a request.
+const EXPIRY_MARGIN_SECONDS: i64 = 60;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Token {
+    access_token: String,
+    refresh_token: Option<String>,
+    /// Unix timestamp of when `access_token` expires.
+    expires_at: i64,
+}
+
+impl Token {
+    fn is_expired(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        now.timestamp() + EXPIRY_MARGIN_SECONDS >= self.expires_at
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+impl std::fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error_description {
+            None => write!(f, "{}", self.error),
+            Some(desc) => write!(f, "{}: {}", self.error, desc),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    expires_in: u64,
+    #[serde(default = "default_interval")]
+    interval: u64,
+    message: Option<String>,
+}
+
+const fn default_interval() -> u64 {
+    5
+}
+
+pub struct Authenticator {
+    http_client: hyper::Client,
+    /// The OAuth 2.0 endpoint of the tenant, for instance
+    /// `https://login.microsoftonline.com/common/oauth2/v2.0`.
+    oauth_url: String,
+    client_id: String,
+    token_path: PathBuf,
+    /// The stored token, or `None` if the account hasn't been signed in.
+    token: Mutex<Option<Token>>,
+}
+
+fn load_token(token_path: &Path) -> Option<Token> {
+    std::fs::read_to_string(token_path)
+        .ok()
+        .and_then(|token_str| serde_json::from_str(&token_str).ok())
+}
+
+impl Authenticator {
+    /// Load the token for an account from `token_path`, if it has been signed
+    /// in.
+    pub fn new(
+        login_url: &str,
+        tenant: &str,
+        client_id: String,
+        token_path: PathBuf,
+    ) -> Self {
+        let http_client: hyper::Client = hyper::Client::with_connector(
+            hyper::net::HttpsConnector::new(hyper_rustls::TlsClient::new()),
+        );
+        let oauth_url = format!(
+            "{}/{}/oauth2/v2.0",
+            login_url.trim_end_matches('/'),
+            tenant
+        );
+
+        Self {
+            http_client,
+            oauth_url,
+            client_id,
+            token: Mutex::new(load_token(&token_path)),
+            token_path,
+        }
+    }
+
+    /// Whether the account has a stored token.  This looks for one again if
+    /// there wasn't one before, so that signing in doesn't need a restart.
+    pub fn is_signed_in(&self) -> bool {
+        let mut opt_token = self
+            .token
+            .lock()
+            .expect("Microsoft Calendar Plugin: token mutex was poisoned");
+        if opt_token.is_none() {
+            *opt_token = load_token(&self.token_path);
+        }
+        opt_token.is_some()
+    }
+
+    /// Sign in to the account for `email` with the device code flow.  The
+    /// token is only stored if `check_account` accepts its access token, so
+    /// that signing in to the wrong account doesn't check the wrong calendar.
+    pub fn login(
+        &self,
+        email: &str,
+        check_account: impl FnOnce(&str) -> Result<(), MicrosoftCalErr>,
+    ) -> Result<(), MicrosoftCalErr> {
+        let token = device_code_flow(
+            &self.http_client,
+            &self.oauth_url,
+            &self.client_id,
+            email,
+        )?;
+        check_account(&token.access_token)?;
+        save_token(&self.token_path, &token)?;
+        *self
+            .token
+            .lock()
+            .expect("Microsoft Calendar Plugin: token mutex was poisoned") =
+            Some(token);
+        Ok(())
+    }
+
+    /// Return an access token that can be used with the Graph API, refreshing
+    /// it first if it has expired.
+    pub fn access_token(&self) -> Result<String, MicrosoftCalErr> {
+        let mut opt_token = self
+            .token
+            .lock()
+            .expect("Microsoft Calendar Plugin: token mutex was poisoned");
+        let token = opt_token.as_mut().ok_or_else(|| {
+            MicrosoftCalErr::Auth(format!(
+                "There is no token in {:?}.  Sign in with `break-time microsoft-calendar login`.",
+                self.token_path
+            ))
+        })?;
+
+        if token.is_expired(chrono::Utc::now()) {
+            let refresh_token =
+                token.refresh_token.clone().ok_or_else(|| {
+                    MicrosoftCalErr::Auth(format!(
+                        "The access token in {:?} has expired and there is no refresh token.  Sign in again with `break-time microsoft-calendar login`.",
+                        self.token_path
+                    ))
+                })?;
+
+            let mut new_token = request_token(
+                &self.http_client,
+                &self.oauth_url,
+                &[
+                    ("grant_type", "refresh_token"),
+                    ("client_id", &self.client_id),
+                    ("refresh_token", &refresh_token),
+                    ("scope", SCOPES),
+                ],
+            )?
+            .map_err(|err_resp| {
+                MicrosoftCalErr::Auth(format!(
+                    "Could not refresh the access token in {:?} ({}).  Sign in again with `break-time microsoft-calendar login`.",
+                    self.token_path, err_resp
+                ))
+            })?;
+
+            // The token endpoint doesn't always send back a new refresh token.
+            if new_token.refresh_token.is_none() {
+                new_token.refresh_token = Some(refresh_token);
+            }
+
+            save_token(&self.token_path, &new_token)?;
+            *token = new_token;
+        }
+
+        Ok(token.access_token.clone())
+    }
+}
+
+fn device_code_flow(
+    http_client: &hyper::Client,
+    oauth_url: &str,
+    client_id: &str,
+    email: &str,
+) -> Result<Token, MicrosoftCalErr> {
+    let device_code_url = format!("{}/devicecode", oauth_url);
+    let response_body = super::post_form(
+        http_client,
+        &device_code_url,
+        &[("client_id", client_id), ("scope", SCOPES)],
+    )
+    .and_then(|(status, body)| {
+        super::check_status(&device_code_url, status, body)
+    })?;
+    let device_code: DeviceCodeResponse =
+        super::parse_json(&device_code_url, &response_body)?;
+
+    match &device_code.message {
+        Some(message) => println!("Signing in {}: {}", email, message),
+        None => println!(
+            "Signing in {}: To sign in, use a web browser to open the page {} and enter the code {} to authenticate.",
+            email, device_code.verification_uri, device_code.user_code
+        ),
+    }
+
+    let deadline =
+        std::time::Instant::now() + Duration::from_secs(device_code.expires_in);
+    let mut interval = device_code.interval;
+
+    while std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_secs(interval));
+
+        let res = request_token(
+            http_client,
+            oauth_url,
+            &[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                ("client_id", client_id),
+                ("device_code", &device_code.device_code),
+            ],
+        )?;
+
+        match res {
+            Ok(token) => return Ok(token),
+            Err(err_resp) => match err_resp.error.as_str() {
+                "authorization_pending" => (),
+                "slow_down" => interval += 5,
+                _ => {
+                    return Err(MicrosoftCalErr::Auth(format!(
+                        "Signing in {} failed: {}",
+                        email, err_resp
+                    )))
+                }
+            },
+        }
+    }
+
+    Err(MicrosoftCalErr::Auth(format!(
+        "Signing in {} failed: the device code expired",
+        email
+    )))
+}
+
+/// Send a request to the token endpoint.  The inner `Err` is an OAuth error
+/// response, like `authorization_pending` or `invalid_grant`.
+fn request_token(
+    http_client: &hyper::Client,
+    oauth_url: &str,
+    params: &[(&str, &str)],
+) -> Result<Result<Token, ErrorResponse>, MicrosoftCalErr> {
+    let token_url = format!("{}/token", oauth_url);
+    let (status, response_body) =
+        super::post_form(http_client, &token_url, params)?;
+
+    if status.is_success() {
+        let token_resp: TokenResponse =
+            super::parse_json(&token_url, &response_body)?;
+        Ok(Ok(Token {
+            access_token: token_resp.access_token,
+            refresh_token: token_resp.refresh_token,
+            expires_at: chrono::Utc::now().timestamp() + token_resp.expires_in,
+        }))
+    } else {
+        match serde_json::from_str(&response_body) {
+            Ok(err_resp) => Ok(Err(err_resp)),
+            Err(_) => Err(MicrosoftCalErr::Status {
+                url: token_url,
+                status,
+                body: response_body,
+            }),
+        }
+    }
+}
+
+fn save_token(token_path: &Path, token: &Token) -> Result<(), MicrosoftCalErr> {
+    let to_storage_err = |io_err| MicrosoftCalErr::TokenStorage {
+        path: token_path.to_path_buf(),
+        io_err,
+    };
+
+    if let Some(parent) = token_path.parent() {
+        std::fs::create_dir_all(parent).map_err(to_storage_err)?;
+    }
+    let mut token_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(token_path)
+        .map_err(to_storage_err)?;
+    let token_str = serde_json::to_string(token)
+        .expect("Serializing a token to JSON should never fail");
+    token_file
+        .write_all(token_str.as_bytes())
+        .map_err(to_storage_err)
+}
@@ -5,6 +5,20 @@ use crate::prelude::*;
 
 use crate::x11::X11;
 
+/// A top-level window, with its geometry on the root window.
+#[derive(Clone, Debug)]
+pub struct TopLevelWin {
+    pub win: xcb::Window,
+    /// The window with the `WM_CLASS`.  This is `win` itself, or one of its
+    /// children when `win` is a frame from the window manager.
+    pub client: xcb::Window,
+    pub class: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
 pub struct WindowTitles {
     x11: X11,
     net_wm_name_atom: xcb::Atom,
@@ -108,15 +122,72 @@ impl WindowTitles {
 
     fn get_all_wins(&self) -> Result<Vec<xcb::Window>, ()> {
         let root_win = self.get_root_win()?;
+        self.get_child_wins(root_win)
+    }
 
+    fn get_child_wins(&self, win: xcb::Window) -> Result<Vec<xcb::Window>, ()> {
         let query_tree_reply: xcb::QueryTreeReply =
-            xcb::xproto::query_tree(&self.x11.conn, root_win)
+            xcb::xproto::query_tree(&self.x11.conn, win)
                 .get_reply()
                 .map_err(|_| ())?;
 
         Ok(query_tree_reply.children().to_vec())
     }
 
+    fn is_viewable(&self, win: xcb::Window) -> bool {
+        xcb::xproto::get_window_attributes(&self.x11.conn, win)
+            .get_reply()
+            .map_or(false, |attrs| {
+                u32::from(attrs.map_state()) == xcb::xproto::MAP_STATE_VIEWABLE
+            })
+    }
+
+    /// The shown top-level windows with a `WM_CLASS` that `is_wanted` accepts,
+    /// on the window itself or on one of its children.  `is_wanted` is passed
+    /// the name and the class from the `WM_CLASS`.
+    pub fn find_wins<F>(&self, is_wanted: F) -> Result<Vec<TopLevelWin>, ()>
+    where
+        F: Fn(&str, &str) -> bool,
+    {
+        let mut found = vec![];
+        for win in self.get_all_wins()? {
+            if !self.is_viewable(win) {
+                continue;
+            }
+            let mut candidates = vec![win];
+            candidates.extend(self.get_child_wins(win).unwrap_or_default());
+            let all_win_props = self.get_all_win_props_from_wins(&candidates);
+            let opt_client = candidates.iter().zip(all_win_props).find_map(
+                |(&client, win_props)| match (
+                    &win_props.class_name,
+                    &win_props.class,
+                ) {
+                    (Ok(class_name), Ok(class))
+                        if is_wanted(class_name, class) =>
+                    {
+                        Some((client, class.clone()))
+                    }
+                    _ => None,
+                },
+            );
+            if let Some((client, class)) = opt_client {
+                let geometry = xcb::xproto::get_geometry(&self.x11.conn, win)
+                    .get_reply()
+                    .map_err(|_| ())?;
+                found.push(TopLevelWin {
+                    win,
+                    client,
+                    class,
+                    x: geometry.x().into(),
+                    y: geometry.y().into(),
+                    width: geometry.width().into(),
+                    height: geometry.height().into(),
+                });
+            }
+        }
+        Ok(found)
+    }
+
     fn can_break(&self) -> Result<CanBreak, ()> {
         let all_win_props: Vec<WinProps> = self.get_all_win_props()?;
         let all_can_break_preds = CanBreakPreds::all();
@@ -0,0 +1,98 @@
+// This module counts the work periods in Pomodoro mode.  Every
+// `cycles_before_long_break`th work period is followed by a long break instead
+// of a short one.
+
+use crate::config::PomodoroSettings;
+
+use std::time::Duration;
+
+pub struct Pomodoro {
+    cycles_before_long_break: u32,
+    /// Idling for at least this long starts the cycles over.  Never if `None`.
+    reset_after_idle: Option<Duration>,
+    completed_cycles: u32,
+}
+
+impl Pomodoro {
+    pub fn new(settings: &PomodoroSettings) -> Self {
+        Self {
+            cycles_before_long_break: std::cmp::max(
+                1,
+                settings.cycles_before_long_break,
+            ),
+            reset_after_idle: match settings.reset_cycle_after_idle_seconds {
+                0 => None,
+                secs => Some(Duration::from_secs(secs.into())),
+            },
+            completed_cycles: 0,
+        }
+    }
+
+    /// The work period the user is in now, counting from 1, and the number of
+    /// work periods before a long break.
+    pub const fn current_cycle(&self) -> (u32, u32) {
+        (
+            self.completed_cycles % self.cycles_before_long_break + 1,
+            self.cycles_before_long_break,
+        )
+    }
+
+    pub const fn next_break_is_long(&self) -> bool {
+        (self.completed_cycles + 1) % self.cycles_before_long_break == 0
+    }
+
+    /// Keep counting the work periods from where `old` was, after the
+    /// settings changed.
+    pub const fn continue_from(&mut self, old: &Self) {
+        self.completed_cycles = old.completed_cycles;
+    }
+
+    pub fn complete_cycle(&mut self) {
+        self.completed_cycles += 1;
+    }
+
+    /// Start the cycles over if the user has been idle for long enough.
+    /// Returns whether they were started over.
+    pub fn reset_if_idle_for(&mut self, idle: Duration) -> bool {
+        match self.reset_after_idle {
+            Some(reset_after_idle)
+                if idle >= reset_after_idle && self.completed_cycles != 0 =>
+            {
+                self.completed_cycles = 0;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pomodoro() {
+        let mut pomodoro = Pomodoro::new(&PomodoroSettings {
+            cycles_before_long_break: 3,
+            reset_cycle_after_idle_seconds: 1800,
+            ..PomodoroSettings::default()
+        });
+
+        assert_eq!(pomodoro.current_cycle(), (1, 3));
+        assert!(!pomodoro.next_break_is_long());
+        pomodoro.complete_cycle();
+        assert!(!pomodoro.next_break_is_long());
+        pomodoro.complete_cycle();
+        assert_eq!(pomodoro.current_cycle(), (3, 3));
+        assert!(pomodoro.next_break_is_long());
+        pomodoro.complete_cycle();
+        assert_eq!(pomodoro.current_cycle(), (1, 3));
+        assert!(!pomodoro.next_break_is_long());
+
+        pomodoro.complete_cycle();
+        assert!(!pomodoro.reset_if_idle_for(Duration::from_secs(600)));
+        assert_eq!(pomodoro.current_cycle(), (2, 3));
+        assert!(pomodoro.reset_if_idle_for(Duration::from_secs(1800)));
+        assert_eq!(pomodoro.current_cycle(), (1, 3));
+    }
+}
@@ -0,0 +1,271 @@
+// Each break tier has its own countdown.  This module keeps track of when the
+// break for each tier is due, and decides which tier's break comes next.
+
+use super::clock::{self, FixedTime};
+use crate::config::BreakTier;
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, Utc};
+
+/// How long a fixed break that plugins put off is retried for.
+const FIXED_BREAK_RETRY_FOR: Duration = Duration::from_secs(60 * 60);
+
+/// How often a fixed break that plugins put off is retried.
+const FIXED_BREAK_RETRY_EVERY: Duration = Duration::from_secs(5 * 60);
+
+/// The longest that a short break is skipped for because a longer break is
+/// due soon after it.
+const MAX_CONFLICT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone, Copy, Debug)]
+pub enum TierKind {
+    /// A break every `interval` after the last one.
+    Interval,
+    /// A break at every multiple of `interval` after local midnight plus
+    /// `offset`.
+    Aligned { offset: Duration },
+    /// A break at a fixed time.
+    Fixed(FixedTime),
+}
+
+pub struct Tier {
+    pub settings: BreakTier,
+    pub kind: TierKind,
+    pub interval: Duration,
+    pub break_duration: Duration,
+    /// When the countdown for this tier last started over.
+    pub countdown_started: Instant,
+    pub due: DateTime<Utc>,
+    /// When the break was due before any plugins put it off.
+    first_due: DateTime<Utc>,
+    /// How much shorter the next work period is, because breaks were put off
+    /// for too long before the last break.
+    pub work_period_reduction: Duration,
+}
+
+impl Tier {
+    pub fn new(settings: BreakTier, kind: TierKind) -> Self {
+        let interval = match kind {
+            TierKind::Interval | TierKind::Aligned { .. } => {
+                Duration::from_secs(settings.seconds_between_breaks.into())
+            }
+            TierKind::Fixed(FixedTime::Daily(_)) => {
+                Duration::from_secs(24 * 60 * 60)
+            }
+            TierKind::Fixed(FixedTime::Hourly(_)) => {
+                Duration::from_secs(60 * 60)
+            }
+        };
+        let break_duration =
+            Duration::from_secs(settings.break_duration_seconds.into());
+        let now = Utc::now();
+        let mut tier = Self {
+            settings,
+            kind,
+            interval,
+            break_duration,
+            countdown_started: Instant::now(),
+            due: now,
+            first_due: now,
+            work_period_reduction: Duration::from_secs(0),
+        };
+        tier.restart_countdown();
+        tier
+    }
+
+    pub const fn is_fixed(&self) -> bool {
+        matches!(self.kind, TierKind::Fixed(_))
+    }
+
+    /// When the next break is due, if the work period starts now and lasts
+    /// `work_period`.
+    fn next_due(&self, work_period: Duration) -> DateTime<Utc> {
+        let now = Local::now();
+        let due = match self.kind {
+            TierKind::Interval => now + to_chrono_duration(work_period),
+            TierKind::Aligned { offset } => {
+                // Skip the next time on the clock if it would make for a
+                // really short work period.
+                clock::next_aligned_time(
+                    &(now + to_chrono_duration(work_period / 2)),
+                    to_chrono_duration(self.interval),
+                    to_chrono_duration(offset),
+                )
+            }
+            TierKind::Fixed(fixed_time) => {
+                clock::next_fixed_time(&now, fixed_time)
+            }
+        };
+        due.with_timezone(&Utc)
+    }
+
+    /// Start the countdown to this tier's next break over.
+    pub fn restart_countdown(&mut self) {
+        let work_period = self
+            .interval
+            .checked_sub(self.work_period_reduction)
+            .unwrap_or_else(|| Duration::from_secs(0));
+        self.work_period_reduction = Duration::from_secs(0);
+        self.countdown_started = Instant::now();
+        self.due = self.next_due(work_period);
+        self.first_due = self.due;
+    }
+
+    /// Keep counting down from where `old`, the same tier before the settings
+    /// changed, was.  An interval break stays due the same time after the
+    /// countdown started, with the new interval.  Breaks that follow the clock
+    /// are due at the next time on the clock for the new settings.
+    pub fn continue_countdown(&mut self, old: &Self) {
+        self.countdown_started = old.countdown_started;
+        self.work_period_reduction = old.work_period_reduction;
+        if matches!(
+            (self.kind, old.kind),
+            (TierKind::Interval, TierKind::Interval)
+        ) {
+            let change = to_chrono_duration(self.interval)
+                - to_chrono_duration(old.interval);
+            self.due = old.due + change;
+            self.first_due = old.first_due + change;
+        }
+    }
+
+    /// Put the break off for `postpone`, at the user's request.
+    pub fn postpone(&mut self, postpone: Duration) {
+        self.due = Utc::now() + to_chrono_duration(postpone);
+    }
+
+    /// Put this tier's break off, without starting the countdown over.  A
+    /// fixed break is retried every few minutes for a while, and other breaks
+    /// are put off for another work period.
+    pub fn put_off(&mut self) {
+        let now = Utc::now();
+        let retry_at = now + to_chrono_duration(FIXED_BREAK_RETRY_EVERY);
+        self.due = match self.kind {
+            TierKind::Fixed(_)
+                if retry_at
+                    < self.first_due
+                        + to_chrono_duration(FIXED_BREAK_RETRY_FOR) =>
+            {
+                retry_at
+            }
+            _ => self.next_due(self.interval),
+        };
+    }
+}
+
+/// Pick the tier whose break comes next, out of the tiers that `is_eligible`.
+/// `tiers` must be sorted from the shortest break to the longest.
+///
+/// This is usually the tier that is due first.  However, if a longer tier is
+/// due soon after that break would end, the longer break is taken instead, so
+/// that a long break doesn't come right after a short one.
+pub fn next_tier(tiers: &[Tier], is_eligible: impl Fn(usize) -> bool) -> usize {
+    let eligible: Vec<usize> = (0..tiers.len())
+        .filter(|&index| is_eligible(index))
+        .collect();
+    let index = next_tier_index(
+        &eligible
+            .iter()
+            .map(|&index| {
+                let tier = &tiers[index];
+                (tier.due, tier.break_duration, tier.interval)
+            })
+            .collect::<Vec<_>>(),
+    );
+    eligible[index]
+}
+
+fn next_tier_index(dues: &[(DateTime<Utc>, Duration, Duration)]) -> usize {
+    let (first_index, &(first_due, first_break_duration, first_interval)) =
+        dues.iter()
+            .enumerate()
+            .min_by_key(|(_, (due, _, _))| *due)
+            .expect("There should always be at least one break tier");
+    let conflict_window =
+        std::cmp::min(first_interval / 2, MAX_CONFLICT_WINDOW);
+    let conflict_end = first_due
+        + to_chrono_duration(first_break_duration)
+        + to_chrono_duration(conflict_window);
+
+    dues.iter()
+        .enumerate()
+        .rev()
+        .find(|(_, (due, break_duration, _))| {
+            *break_duration > first_break_duration && *due < conflict_end
+        })
+        .map_or(first_index, |(index, _)| index)
+}
+
+pub fn to_chrono_duration(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).expect(
+        "Durations between breaks should always fit in a chrono::Duration",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_next_tier_index() {
+        let at = |min, sec| Utc.ymd(2020, 3, 2).and_hms(10, min, sec);
+        let eye = (Duration::from_secs(20), Duration::from_secs(20 * 60));
+        let long = (Duration::from_secs(600), Duration::from_secs(60 * 60));
+        let lunch = (Duration::from_secs(1800), Duration::from_secs(86400));
+        let tier =
+            |due, (break_duration, interval)| (due, break_duration, interval);
+
+        assert_eq!(
+            next_tier_index(&[tier(at(20, 0), eye), tier(at(59, 0), long)]),
+            0
+        );
+        assert_eq!(
+            next_tier_index(&[tier(at(21, 0), eye), tier(at(20, 0), long)]),
+            1
+        );
+
+        // The long break is due 9 seconds into the eye break, so only the long
+        // break is taken.
+        assert_eq!(
+            next_tier_index(&[tier(at(59, 50), eye), tier(at(59, 59), long)]),
+            1
+        );
+
+        // Lunch is 10 minutes after the long break would end, so the long
+        // break is skipped.
+        assert_eq!(
+            next_tier_index(&[tier(at(10, 0), long), tier(at(30, 0), lunch)]),
+            1
+        );
+        assert_eq!(
+            next_tier_index(&[tier(at(0, 0), long), tier(at(30, 0), lunch)]),
+            0
+        );
+    }
+
+    #[test]
+    fn test_continue_countdown() {
+        let break_tier = |seconds_between_breaks| BreakTier {
+            name: String::from("break"),
+            seconds_between_breaks,
+            break_duration_seconds: 300,
+            clicks_to_end_break_early: 0,
+            enforcement: Default::default(),
+            plugins: Default::default(),
+            align_to_clock: false,
+            clock_offset_seconds: 0,
+        };
+        let mut old = Tier::new(break_tier(3600), TierKind::Interval);
+        old.due = old.due - chrono::Duration::minutes(40);
+
+        // Twenty minutes into the old hour-long work period, so the break is
+        // ten minutes away with a half-hour work period.
+        let mut tier = Tier::new(break_tier(1800), TierKind::Interval);
+        tier.continue_countdown(&old);
+        assert_eq!(tier.countdown_started, old.countdown_started);
+        assert_eq!(tier.due, old.due - chrono::Duration::minutes(30));
+    }
+}
@@ -101,6 +101,14 @@ pub struct Tray {
     menu_right_click_signal_handler_id: Option<glib::signal::SignalHandlerId>,
     is_idle_detector_enabled: IsIdleDetectorEnabled,
     is_paused: IsPaused,
+    /// Problems with the plugins.  When there are any, break-time is running
+    /// in a degraded state.
+    problems: Vec<String>,
+    tooltip_text: String,
+    /// The current work period and the number of work periods before a long
+    /// break, in Pomodoro mode.
+    pomodoro_cycle: Option<(u32, u32)>,
+    profiles: Profiles,
 }
 
 fn load_pixbuf(image_bytes: &[u8]) -> gdk_pixbuf::Pixbuf {
@@ -152,6 +160,13 @@ impl Tray {
             menu_right_click_signal_handler_id,
             is_idle_detector_enabled,
             is_paused: IsPaused::No,
+            problems: vec![],
+            tooltip_text: String::from("break-time"),
+            pomodoro_cycle: None,
+            profiles: Profiles {
+                names: config.profile_names.clone(),
+                active: config.active_profile.clone(),
+            },
         };
 
         tray.render_normal_icon();
@@ -159,7 +174,27 @@ impl Tray {
         tray
     }
 
-    fn set_tooltip_text(&self, tooltip_text: &str) {
+    fn set_tooltip_text(&mut self, tooltip_text: &str) {
+        self.tooltip_text = String::from(tooltip_text);
+        self.render_tooltip();
+    }
+
+    fn render_tooltip(&self) {
+        let mut tooltip_text = self.tooltip_text.clone();
+        if let Some((cycle, cycles_before_long_break)) = self.pomodoro_cycle {
+            tooltip_text = format!(
+                "{}\nPomodoro {} of {}",
+                tooltip_text, cycle, cycles_before_long_break
+            );
+        }
+        if !self.problems.is_empty() {
+            tooltip_text.push_str("\nNot working:");
+            for problem in &self.problems {
+                tooltip_text.push_str("\n- ");
+                tooltip_text.push_str(problem);
+            }
+        }
+
         unsafe {
             gtk_sys::gtk_status_icon_set_tooltip_text(
                 self.status_icon,
@@ -168,6 +203,21 @@ impl Tray {
         }
     }
 
+    pub fn set_problems(&mut self, problems: Vec<String>) {
+        self.problems = problems;
+        self.render_tooltip();
+        self.conn_popup_menu();
+    }
+
+    pub fn set_pomodoro_cycle(
+        &mut self,
+        cycle: u32,
+        cycles_before_long_break: u32,
+    ) {
+        self.pomodoro_cycle = Some((cycle, cycles_before_long_break));
+        self.render_tooltip();
+    }
+
     pub fn render_break_starting(&self) {
         self.render_normal_icon();
     }
@@ -256,6 +306,24 @@ impl Tray {
         self.render_normal_icon();
     }
 
+    /// Show that the break is over, and that the next work period starts
+    /// when the user comes back.
+    pub fn wait_for_user(&mut self) {
+        self.render_pause_icon();
+        self.set_tooltip_text("break-time: break over, waiting for you");
+    }
+
+    /// Stop showing that break-time is waiting for the user to come back.
+    pub fn stop_waiting_for_user(&mut self) {
+        self.render_normal_icon();
+        self.set_tooltip_text("break-time");
+    }
+
+    pub fn set_active_profile(&mut self, name: &str) {
+        self.profiles.active = String::from(name);
+        self.conn_popup_menu();
+    }
+
     pub fn set_is_idle_detector_enabled(
         &mut self,
         is_idle_detector_enabled: IsIdleDetectorEnabled,
@@ -273,6 +341,8 @@ impl Tray {
 
         let is_idle_detector_enabled = self.is_idle_detector_enabled;
         let is_paused = self.is_paused;
+        let problems = self.problems.clone();
+        let profiles = self.profiles.clone();
 
         let sender = self.sender.clone();
         let signal_handler_id = connect_popup_menu(
@@ -282,6 +352,18 @@ impl Tray {
                   activate_time| {
                 let menu = gtk::Menu::new();
 
+                for problem in &problems {
+                    let problem_item = gtk::MenuItem::new_with_label(&format!(
+                        "Not working: {}",
+                        problem
+                    ));
+                    problem_item.set_sensitive(false);
+                    menu.append(&problem_item);
+                }
+                if !problems.is_empty() {
+                    menu.append(&gtk::SeparatorMenuItem::new());
+                }
+
                 match is_paused {
                     IsPaused::No => {
                         let pause_item = gtk::MenuItem::new_with_label("Pause");
@@ -339,6 +421,8 @@ impl Tray {
                     }
                 }
 
+                profiles.append_menu_item(&menu, &sender);
+
                 let quit_item = gtk::MenuItem::new_with_label("Quit");
                 let sender_clone = sender.clone();
                 quit_item.connect_activate(move |_| {
@@ -355,19 +439,63 @@ impl Tray {
         self.menu_right_click_signal_handler_id = Some(signal_handler_id);
     }
 
-    fn set_time_remaining_tool_tip(&self, remaining_time: Duration) {
+    fn set_time_remaining_tool_tip(
+        &mut self,
+        remaining_time: Duration,
+        tier_name: &str,
+    ) {
         self.set_tooltip_text(&format!(
-            "break-time: {} until next break",
-            remaining_duration_to_text(remaining_time)
+            "break-time: {} until next {}",
+            remaining_duration_to_text(remaining_time),
+            tier_name
         ));
     }
 
-    pub fn update_time_remaining(&self, remaining_time: Duration) {
+    pub fn update_time_remaining(
+        &mut self,
+        remaining_time: Duration,
+        tier_name: &str,
+    ) {
         if remaining_time <= Duration::from_secs(5 * 60) {
             self.render_time_remaining_before_break(remaining_time);
         }
 
-        self.set_time_remaining_tool_tip(remaining_time);
+        self.set_time_remaining_tool_tip(remaining_time, tier_name);
+    }
+}
+
+/// The names of all the settings profiles, and the active one.
+#[derive(Clone, Debug)]
+struct Profiles {
+    names: Vec<String>,
+    active: String,
+}
+
+impl Profiles {
+    /// Add a menu item with a submenu for switching profiles to `menu`, if
+    /// there are any profiles to switch to.
+    fn append_menu_item(&self, menu: &gtk::Menu, sender: &glib::Sender<Msg>) {
+        if self.names.len() <= 1 {
+            return;
+        }
+        let profile_menu = gtk::Menu::new();
+        for name in &self.names {
+            let name_item = gtk::CheckMenuItem::new_with_label(name);
+            name_item.set_draw_as_radio(true);
+            name_item.set_active(*name == self.active);
+            let sender_clone = sender.clone();
+            let name = name.clone();
+            name_item.connect_activate(move |_| {
+                sender_clone
+                    .send(Msg::SwitchProfile(name.clone()))
+                    .expect("Could not send Msg::SwitchProfile");
+            });
+            profile_menu.append(&name_item);
+        }
+        let profile_item =
+            gtk::MenuItem::new_with_label(&format!("Profile: {}", self.active));
+        profile_item.set_submenu(Some(&profile_menu));
+        menu.append(&profile_item);
     }
 }
 
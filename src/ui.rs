@@ -1,18 +1,31 @@
+pub mod activity;
 pub mod builder;
+pub mod challenge;
 pub mod prelude;
+pub mod warning;
 
+mod allowed_windows;
 mod css;
+mod grab;
 mod state;
 
 use glib::clone;
 use glib::source::Continue;
 use gtk::Inhibit;
-use std::time::{Duration, SystemTime};
+use std::io::{self, Read};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant, SystemTime};
 
 use super::Msg;
-use crate::config::Config;
+use crate::config::{Config, Enforcement};
+use crate::notification::Notifier;
+use crate::scheduler::BreakInfo;
+use allowed_windows::{AllowedWindows, Change};
+use challenge::Key;
+use grab::XcbEvent;
 use prelude::*;
-use state::{Message, State};
+use state::{Message, Monitor, Screen, State};
 
 use crate::x11::X11;
 
@@ -30,6 +43,11 @@ fn handle_msg_recv(
 
     match msg {
         Message::End => {
+            for (object, handler_id) in
+                state.monitor_signal_handlers.borrow_mut().drain(..)
+            {
+                object.disconnect(handler_id);
+            }
             for window in state.get_app_wins() {
                 window.hide();
                 window.destroy();
@@ -92,41 +110,268 @@ fn end_break(state: &State) {
     state.end();
 }
 
-fn decrement_presses_remaining(state: &State) {
-    let remaining = state.decrement_presses_remaining();
-
-    if remaining == 0 {
+/// End the break early if the challenge is done.  Returns whether it was.
+fn end_break_if_challenge_done(state: &State) -> bool {
+    let is_done = state.read_challenge().is_done();
+    if is_done && !state.ended_early.swap(true, Ordering::Relaxed) {
+        state.notify_app_ended_early();
         end_break(state);
     }
+    is_done
 }
 
-fn setup(state: &State) {
-    for window in state.get_app_wins() {
-        css::setup(window.upcast_ref());
+fn key_from_event(event_key: &gdk::EventKey) -> Key {
+    let keyval = event_key.get_keyval();
+    Key {
+        keyval,
+        ch: gdk::keyval_to_unicode(keyval),
     }
 }
 
-fn connect_events(config: &Config, state: &State) {
-    for window in state.get_app_wins() {
-        window.connect_key_release_event(
-            clone!(@strong state => move |_, event_key| {
-                if event_key.get_keyval() == gdk::enums::key::space {
-                    decrement_presses_remaining(&state);
-                    redisplay(&state);
-                }
-                Inhibit(false)
-            }),
-        );
+/// What the break screens show that doesn't change during the break.
+#[derive(Clone, Debug)]
+struct Content {
+    title: String,
+    reason: String,
+    pomodoro: String,
+    activity_image: Option<gdk_pixbuf::Pixbuf>,
+    enforcement: Enforcement,
+    css_provider: gtk::CssProvider,
+}
+
+/// Fill in the break screen `screen`, connect its events, and show it.
+fn setup_screen(state: &State, content: &Content, screen: &Screen) {
+    let window = screen.app_win();
+    css::setup(window.upcast_ref(), &content.css_provider);
+
+    let builder = &screen.builder;
+    let set_label_text = |id: &str, text: &str| {
+        if let Some(label) = builder.get_object::<gtk::Label>(id) {
+            label.set_text(text);
+        }
+    };
+    set_label_text("title_label", &content.title);
+    set_label_text("break_reason_label", &content.reason);
+    set_label_text("pomodoro_label", &content.pomodoro);
+    if let Some(image) = builder.get_object::<gtk::Image>("activity_image") {
+        image.set_from_pixbuf(content.activity_image.as_ref());
     }
 
-    // the full time we want to wait for
-    let full_time =
-        Duration::new(config.settings.break_duration_seconds.into(), 0);
+    match content.enforcement {
+        Enforcement::Overlay => connect_dismiss_events(state, &window),
+        Enforcement::Lock => connect_grab_events(state, &window),
+        _ => (),
+    }
+    window.connect_key_press_event(
+        clone!(@strong state => move |_, event_key| {
+            state
+                .write_challenge()
+                .key_pressed(key_from_event(event_key), Instant::now());
+            end_break_if_challenge_done(&state);
+            redisplay(&state);
+            Inhibit(false)
+        }),
+    );
+    window.connect_key_release_event(
+        clone!(@strong state => move |_, event_key| {
+            state
+                .write_challenge()
+                .key_released(key_from_event(event_key), Instant::now());
+            end_break_if_challenge_done(&state);
+            redisplay(&state);
+            Inhibit(false)
+        }),
+    );
 
-    gtk::timeout_add(
-        200,
-        clone!(@strong state => move || update_time_remaining(&state, full_time)),
+    if content.enforcement == Enforcement::Overlay {
+        window.get_style_context().add_class("overlay");
+        window.set_opacity(OVERLAY_OPACITY);
+    }
+    window.show_all();
+    place_window(&window, &screen.monitor);
+}
+
+/// Make `window` cover `monitor`.
+fn place_window(window: &gtk::ApplicationWindow, monitor: &Monitor) {
+    let monitor_rect = monitor.get_geometry();
+    window.set_default_size(monitor_rect.width, monitor_rect.height);
+    window.resize(monitor_rect.width, monitor_rect.height);
+    window.move_(monitor_rect.x, monitor_rect.y);
+}
+
+/// Make the break screens match the monitors that are connected now.
+/// Screens are added for new monitors, removed for unplugged ones, and moved
+/// to follow monitors whose geometry changed.
+fn sync_screens(state: &State, content: &Content) {
+    let monitors = Monitor::all();
+    let mut added = vec![];
+    {
+        let mut screens = state.screens.borrow_mut();
+        let (mut kept, removed): (Vec<Screen>, Vec<Screen>) =
+            screens.drain(..).partition(|screen| {
+                monitors
+                    .iter()
+                    .any(|monitor| monitor.monitor == screen.monitor.monitor)
+            });
+        for screen in removed {
+            println!(
+                "Removing the break screen from monitor {}",
+                screen.monitor.id
+            );
+            screen.app_win().destroy();
+        }
+        for screen in &mut kept {
+            if let Some(monitor) = monitors
+                .iter()
+                .find(|monitor| monitor.monitor == screen.monitor.monitor)
+            {
+                screen.monitor = monitor.clone();
+            }
+            place_window(&screen.app_win(), &screen.monitor);
+        }
+        for monitor in monitors {
+            if !kept
+                .iter()
+                .any(|screen| screen.monitor.monitor == monitor.monitor)
+            {
+                println!("Adding a break screen to monitor {}", monitor.id);
+                added.push(state.create_screen(monitor));
+            }
+        }
+        kept.extend(added.iter().cloned());
+        *screens = kept;
+    }
+
+    for screen in &added {
+        setup_screen(state, content, screen);
+    }
+    redisplay(state);
+    if content.enforcement == Enforcement::Lock {
+        grab_first_window(state);
+    }
+}
+
+/// Follow monitors being plugged in, unplugged, and changing geometry until
+/// the break ends.
+fn connect_monitor_events(state: &State, content: &Content) {
+    let display = gdk::Display::get_default()
+        .expect("gdk should always find a Display when it runs");
+    let mut handlers = state.monitor_signal_handlers.borrow_mut();
+    handlers.push((
+        display.clone().upcast(),
+        display.connect_monitor_added(
+            clone!(@strong state, @strong content => move |_, _| {
+                sync_screens(&state, &content);
+            }),
+        ),
+    ));
+    handlers.push((
+        display.clone().upcast(),
+        display.connect_monitor_removed(
+            clone!(@strong state, @strong content => move |_, _| {
+                sync_screens(&state, &content);
+            }),
+        ),
+    ));
+    let screen = display.get_default_screen();
+    handlers.push((
+        screen.clone().upcast(),
+        screen.connect_size_changed(
+            clone!(@strong state, @strong content => move |_| {
+                sync_screens(&state, &content);
+            }),
+        ),
+    ));
+    handlers.push((
+        screen.clone().upcast(),
+        screen.connect_monitors_changed(
+            clone!(@strong state, @strong content => move |_| {
+                sync_screens(&state, &content);
+            }),
+        ),
+    ));
+}
+
+/// Let the user dismiss an overlay break with Escape or a click.
+fn connect_dismiss_events(state: &State, window: &gtk::ApplicationWindow) {
+    window.add_events(gdk::EventMask::BUTTON_PRESS_MASK);
+    window.connect_button_press_event(clone!(@strong state => move |_, _| {
+        end_break(&state);
+        Inhibit(true)
+    }));
+    window.connect_key_press_event(
+        clone!(@strong state => move |_, event_key| {
+            if event_key.get_keyval() == gdk::enums::key::Escape {
+                end_break(&state);
+                Inhibit(true)
+            } else {
+                Inhibit(false)
+            }
+        }),
+    );
+}
+
+/// Take the grab back when another client breaks it, or when the break
+/// screen loses focus.  Clicks on an allowed window go to the break screen
+/// while it has the grab, so they are passed on.
+fn connect_grab_events(state: &State, window: &gtk::ApplicationWindow) {
+    window.add_events(gdk::EventMask::BUTTON_PRESS_MASK);
+    window.connect_button_press_event(
+        clone!(@strong state => move |_, event_button| {
+            let (x, y) = event_button.get_root();
+            #[allow(clippy::cast_possible_truncation)]
+            state.allowed_windows.clicked(x as i32, y as i32);
+            Inhibit(false)
+        }),
     );
+    window.connect_grab_broken_event(clone!(@strong state => move |_, _| {
+        println!("The keyboard/mouse grab was broken, grabbing them again.");
+        state.grab.lost(Instant::now());
+        grab_first_window(&state);
+        Inhibit(false)
+    }));
+    // This only grabs again if the grab isn't held and isn't already being
+    // taken, since `grab_first_window` waits between tries.
+    window.connect_focus_out_event(clone!(@strong state => move |_, _| {
+        grab_first_window(&state);
+        Inhibit(false)
+    }));
+}
+
+/// Pass the keys pressed while the keyboard is grabbed through XCB on to the
+/// challenge, and the mouse clicks on to the allowed windows.
+fn handle_xcb_events(state: &State) {
+    for event in state.grab.xcb_events() {
+        match event {
+            XcbEvent::Key(key, true) => {
+                state.write_challenge().key_pressed(key, Instant::now());
+            }
+            XcbEvent::Key(key, false) => {
+                state.write_challenge().key_released(key, Instant::now());
+            }
+            XcbEvent::Click(x, y) => state.allowed_windows.clicked(x, y),
+        }
+    }
+}
+
+/// Raise the allowed windows, and let go of the grab during a locked break
+/// while one of them is used.
+fn update_allowed_windows(state: &State, enforcement: Enforcement) {
+    match state.allowed_windows.update() {
+        Change::Started if enforcement == Enforcement::Lock => {
+            state.grab.suspend(Instant::now());
+            if let Some(seat) = gdk::Display::get_default()
+                .and_then(|display| display.get_default_seat())
+            {
+                seat.ungrab();
+            }
+        }
+        Change::Stopped if enforcement == Enforcement::Lock => {
+            state.grab.resume(Instant::now());
+            grab_first_window(state);
+        }
+        _ => (),
+    }
 }
 
 fn update_time_remaining(state: &State, full_time: Duration) -> Continue {
@@ -142,6 +387,23 @@ fn update_time_remaining(state: &State, full_time: Duration) -> Continue {
             Continue(false)
         }
         Some(system_time_remaining) => {
+            if !state.grab.check_xcb(Instant::now()) {
+                grab_first_window(state);
+            }
+            handle_xcb_events(state);
+            state.write_challenge().tick(Instant::now());
+            if end_break_if_challenge_done(&state) {
+                return Continue(false);
+            }
+            redisplay(&state);
+            if let (Some(activity), Some(elapsed)) =
+                (&state.activity, option_system_time_diff)
+            {
+                let activity_text = activity::text(activity, elapsed);
+                for label in state.get_activity_labels() {
+                    label.set_text(&activity_text);
+                }
+            }
             for label in state.get_time_remaining_labels() {
                 let total_secs_remaining = system_time_remaining.as_secs();
                 let mins: u64 = total_secs_remaining / 60;
@@ -154,83 +416,319 @@ fn update_time_remaining(state: &State, full_time: Duration) -> Continue {
 }
 
 fn redisplay(state: &State) {
-    let presses_remaining = state.read_presses_remaining();
-
-    for label in state.get_presses_remaining_labels() {
-        label.set_text(&format!("{}", presses_remaining));
+    let challenge = state.read_challenge();
+    let progress = challenge.progress();
+    let details = challenge.details();
+
+    for (progress_label, challenge_label) in state.get_challenge_labels() {
+        match challenge_label {
+            Some(label) => {
+                progress_label.set_text(&progress);
+                label.set_text(&details);
+            }
+            // Without a challenge label, show what to type on the progress
+            // label.
+            None if details.is_empty() => progress_label.set_text(&progress),
+            None => {
+                progress_label.set_text(&format!("{}\n{}", progress, details));
+            }
+        }
     }
 }
 
-fn setup_windows(state: &State) {
-    let app_wins_with_monitors = state.get_app_wins_with_monitors();
+/// How opaque the break screen is for overlay breaks.
+const OVERLAY_OPACITY: f64 = 0.85;
 
-    for (i, (window, monitor)) in app_wins_with_monitors.into_iter().enumerate()
-    {
-        window.show_all();
+/// Grab the mouse and keyboard on the break screen of the first monitor.
+/// If that keeps failing, they are grabbed through XCB instead.  They aren't
+/// grabbed while an allowed window is used.
+fn grab_first_window(state: &State) {
+    if state.allowed_windows.is_in_use() {
+        return;
+    }
+    let window = match state.get_app_wins().into_iter().next() {
+        Some(window) => window,
+        None => return,
+    };
+    if !state.grab.start_grabbing() {
+        return;
+    }
+    let gdk_window: gdk::Window = window.get_window().expect(
+        "Gtk::Window should always be able to be converted to Gdk::Window",
+    );
 
-        let monitor_rect = monitor.get_geometry();
-        window.set_default_size(monitor_rect.width, monitor_rect.height);
-        window.resize(monitor_rect.width, monitor_rect.height);
-        window.move_(monitor_rect.x, monitor_rect.y);
+    let state = state.clone();
+    let mut seat_grab_check_times = 0;
+    // For some reason, grab() fails unless we wait for a while until the window is fully
+    // shown.
+    gtk::idle_add(move || {
+        if state.allowed_windows.is_in_use() {
+            return Continue(false);
+        }
+        seat_grab_check_times += 1;
+        let ten_millis = std::time::Duration::from_millis(200);
+        std::thread::sleep(ten_millis);
 
-        let gdk_window: gdk::Window = window.get_window().expect(
-            "Gtk::Window should always be able to be converted to Gdk::Window",
-        );
+        let default_display = gdk::Display::get_default()
+            .expect("gdk should always find a Display when it runs");
 
-        // Grab the mouse and keyboard on the first Window.
-        if i == 0 {
-            let mut seat_grab_check_times = 0;
-            // For some reason, grab() fails unless we wait for a while until the window is fully
-            // shown.
-            gtk::idle_add(move || {
-                seat_grab_check_times += 1;
-                let ten_millis = std::time::Duration::from_millis(200);
-                std::thread::sleep(ten_millis);
-
-                let default_display = gdk::Display::get_default()
-                    .expect("gdk should always find a Display when it runs");
-
-                let default_seat = default_display
-                    .get_default_seat()
-                    .expect("gdk Display should always have a deafult Seat");
-
-                let grab_status = default_seat.grab(
-                    &gdk_window,
-                    gdk::SeatCapabilities::ALL,
-                    false,
-                    None,
-                    None,
-                    None,
-                );
+        let default_seat = default_display
+            .get_default_seat()
+            .expect("gdk Display should always have a deafult Seat");
 
-                match grab_status {
-                    gdk::GrabStatus::Success => {
-                        println!(
-                            "Successfully grabbed screen after {} {}.",
-                            seat_grab_check_times,
-                            if seat_grab_check_times > 1 {
-                                "tries"
-                            } else {
-                                "try"
-                            }
-                        );
-                        Continue(false)
+        let grab_status = default_seat.grab(
+            &gdk_window,
+            gdk::SeatCapabilities::ALL,
+            false,
+            None,
+            None,
+            None,
+        );
+
+        match grab_status {
+            gdk::GrabStatus::Success => {
+                state.grab.grabbed_with_gdk(Instant::now());
+                println!(
+                    "Successfully grabbed screen after {} {}.",
+                    seat_grab_check_times,
+                    if seat_grab_check_times > 1 {
+                        "tries"
+                    } else {
+                        "try"
                     }
-                    _ => {
-                        if seat_grab_check_times >= 20 {
-                            println!("Tried grabbing keyboard/mouse {} times, but never succeeded.", seat_grab_check_times);
-                            Continue(false)
-                        } else {
-                            Continue(true)
-                        }
+                );
+                Continue(false)
+            }
+            _ => {
+                if seat_grab_check_times >= 20 {
+                    println!("Tried grabbing keyboard/mouse {} times, but never succeeded.", seat_grab_check_times);
+                    match state.grab.grab_with_xcb() {
+                        Ok(()) => println!("Grabbed the keyboard/mouse through XCB instead."),
+                        Err(err) => println!("Could not grab the keyboard/mouse through XCB either, so the break is not enforced: {}", err),
                     }
+                    Continue(false)
+                } else {
+                    Continue(true)
                 }
+            }
+        }
+    });
+}
+
+/// How often to check whether a command run for a break has exited.
+const COMMAND_POLL_MILLISECONDS: u32 = 200;
+
+/// How long to give the screen locker to lock the session, before checking
+/// that it did.
+const LOCK_CHECK_DELAY_MILLISECONDS: u32 = 3000;
+
+/// Run `command` without waiting for it, and call `done` with its exit status
+/// and output once it has exited.
+fn run_command(
+    mut command: Command,
+    done: impl FnOnce(io::Result<(ExitStatus, String)>) + 'static,
+) {
+    let mut child = match command.stdout(Stdio::piped()).spawn() {
+        Err(err) => {
+            done(Err(err));
+            return;
+        }
+        Ok(child) => child,
+    };
+    let mut done = Some(done);
+    gtk::timeout_add(COMMAND_POLL_MILLISECONDS, move || {
+        let res = match child.try_wait() {
+            Ok(None) => return Continue(true),
+            Ok(Some(status)) => {
+                let mut output = String::new();
+                match child.stdout.take() {
+                    Some(mut stdout) => stdout.read_to_string(&mut output),
+                    None => Ok(0),
+                }
+                .map(|_| (status, output))
+            }
+            Err(err) => Err(err),
+        };
+        if let Some(done) = done.take() {
+            done(res);
+        }
+        Continue(false)
+    });
+}
+
+/// Check whether the screen locker has locked the session, from its
+/// `LockedHint`, and call `done` with why not if it hasn't.
+fn check_session_locked(done: impl FnOnce(Result<(), String>) + 'static) {
+    let session = std::env::var("XDG_SESSION_ID")
+        .unwrap_or_else(|_| String::from("self"));
+    let mut command = Command::new("loginctl");
+    command.args([
+        "show-session",
+        &session,
+        "--property=LockedHint",
+        "--value",
+    ]);
+    run_command(command, move |res| {
+        done(match res {
+            Err(err) => Err(format!(
+                "Could not run loginctl to check that the screen is locked: {}",
+                err
+            )),
+            Ok((_, output)) if output.trim() == "yes" => Ok(()),
+            Ok(_) => {
+                Err(String::from("The screen locker didn't lock the screen"))
+            }
+        });
+    });
+}
+
+/// Lock the screen with `loginctl lock-session`, and call `done` once the
+/// session is locked, or with why it isn't.
+fn lock_session(done: impl FnOnce(Result<(), String>) + 'static) {
+    let mut command = Command::new("loginctl");
+    command.arg("lock-session");
+    run_command(command, move |res| match res {
+        Err(err) => done(Err(format!(
+            "Could not run loginctl to lock the screen: {}",
+            err
+        ))),
+        Ok((status, _)) if !status.success() => done(Err(format!(
+            "loginctl failed to lock the screen: {}",
+            status
+        ))),
+        Ok(_) => {
+            let mut done = Some(done);
+            gtk::timeout_add(LOCK_CHECK_DELAY_MILLISECONDS, move || {
+                if let Some(done) = done.take() {
+                    check_session_locked(done);
+                }
+                Continue(false)
             });
         }
+    });
+}
+
+/// Start a break without a break screen, only telling the app when it is
+/// over, `duration` from now.
+fn start_break_without_screen(
+    app_sender: glib::Sender<Msg>,
+    duration: Duration,
+) {
+    let break_millis = u32::try_from(duration.as_millis()).unwrap_or(u32::MAX);
+    gtk::timeout_add(break_millis, move || {
+        app_sender.send(Msg::EndBreak).expect(
+            "TODO: figure out what to do about channels potentially failing",
+        );
+        Continue(false)
+    });
+}
+
+/// Start a break with `enforcement = "lock-session"`.  The break is counted
+/// from now, but if the session doesn't lock, the break screen is shown
+/// instead.
+fn start_lock_session_break(
+    app_sender: glib::Sender<Msg>,
+    break_info: &BreakInfo,
+    difficulty: u32,
+    config: &Config,
+    notifier: &Notifier,
+) {
+    let started = Instant::now();
+    let break_info = break_info.clone();
+    let config = config.clone();
+    let notifier = notifier.clone();
+    lock_session(move |res| match res {
+        Ok(()) => start_break_without_screen(
+            app_sender,
+            break_info.duration.saturating_sub(started.elapsed()),
+        ),
+        Err(err) => {
+            println!("{}, showing the break screen instead", err);
+            let break_info = BreakInfo {
+                enforcement: Enforcement::Lock,
+                ..break_info
+            };
+            start_break(
+                app_sender,
+                &break_info,
+                difficulty,
+                &config,
+                &notifier,
+            );
+        }
+    });
+}
+
+/// The text of the notification for a break with `enforcement = "notify"`.
+fn notification_body(break_info: &BreakInfo) -> String {
+    let end_time = chrono::Local::now()
+        + chrono::Duration::from_std(break_info.duration)
+            .unwrap_or_else(|_| chrono::Duration::zero());
+    let mut body = format!("Take a break until {}.", end_time.format("%H:%M"));
+    if let Some(reason) = &break_info.reason {
+        body = format!("{}\n{}", body, reason);
+    }
+    if let Some(activity) = &break_info.activity {
+        body = format!(
+            "{}\n{}",
+            body,
+            activity::text(activity, Duration::from_secs(0))
+        );
     }
+    body
+}
+
+/// Create the challenge for ending the break early.
+fn create_challenge(
+    break_info: &BreakInfo,
+    difficulty: u32,
+) -> Box<dyn challenge::Challenge> {
+    let keyval = match gdk::keyval_from_name(&break_info.challenge.key) {
+        0 | gdk::enums::key::VoidSymbol => {
+            println!(
+                "Unknown key {:?} for the challenge, using space instead",
+                break_info.challenge.key
+            );
+            gdk::enums::key::space
+        }
+        keyval => keyval,
+    };
+    challenge::create(
+        &break_info.challenge,
+        break_info.clicks_to_end_break_early,
+        keyval,
+        difficulty,
+    )
 }
 
-pub fn start_break(config: &Config, app_sender: glib::Sender<Msg>) {
+/// Start a break.  `difficulty` is how much harder than normal to make the
+/// challenge for ending the break early.
+///
+/// The look of the break screen can be changed with `ui.glade` and
+/// `style.css` in the config directory.
+pub fn start_break(
+    app_sender: glib::Sender<Msg>,
+    break_info: &BreakInfo,
+    difficulty: u32,
+    config: &Config,
+    notifier: &Notifier,
+) {
+    let conf_dir = config.conf_dir();
+    let enforcement = match break_info.enforcement {
+        Enforcement::Notify => {
+            notifier.notify("Break-Time!", &notification_body(break_info));
+            start_break_without_screen(app_sender, break_info.duration);
+            return;
+        }
+        Enforcement::LockSession => {
+            start_lock_session_break(
+                app_sender, break_info, difficulty, config, notifier,
+            );
+            return;
+        }
+        enforcement => enforcement,
+    };
+
     let x11 = X11::connect();
 
     let net_active_win_atom = x11.create_atom("_NET_ACTIVE_WINDOW").expect(
@@ -246,19 +744,71 @@ pub fn start_break(config: &Config, app_sender: glib::Sender<Msg>) {
     let (sender, receiver) =
         glib::MainContext::channel(glib::source::PRIORITY_DEFAULT);
 
-    let state = State::new(config, app_sender, sender);
-
-    setup(&state);
+    let challenge = create_challenge(break_info, difficulty);
+    let title = format!(
+        "Break-Time!                \nWork-Time in:        \n{}",
+        challenge.title()
+    );
 
-    connect_events(&config, &state);
+    let glade_src = builder::load_src(conf_dir);
+    let state = State::new(
+        &glade_src,
+        challenge,
+        break_info.activity.clone(),
+        AllowedWindows::new(config, &break_info.tier_name),
+        app_sender,
+        sender,
+    );
 
+    let content = Content {
+        title,
+        reason: break_info.reason.clone().unwrap_or_default(),
+        pomodoro: break_info.pomodoro_cycle.map_or_else(
+            String::new,
+            |(cycle, cycles_before_long_break)| {
+                format!(
+                    "Pomodoro {} of {} done",
+                    cycle, cycles_before_long_break
+                )
+            },
+        ),
+        activity_image: break_info
+            .activity
+            .as_ref()
+            .and_then(activity::load_image),
+        enforcement,
+        css_provider: css::create_provider(conf_dir),
+    };
+    for screen in state.screens.borrow().iter() {
+        setup_screen(&state, &content, screen);
+    }
     redisplay(&state);
+    if enforcement == Enforcement::Lock {
+        grab_first_window(&state);
+    }
+    connect_monitor_events(&state, &content);
 
-    setup_windows(&state);
+    let full_time = break_info.duration;
+    gtk::timeout_add(
+        200,
+        clone!(@strong state => move || {
+            update_allowed_windows(&state, enforcement);
+            update_time_remaining(&state, full_time)
+        }),
+    );
 
+    let cache_dir = config.cache_dir.clone();
+    let tier_name = break_info.tier_name.clone();
     receiver.attach(
         None,
-        clone!(@strong state => move |msg|
+        clone!(@strong state => move |msg| {
+            if msg == Message::End {
+                state.allowed_windows.stop();
+            }
+            if enforcement == Enforcement::Lock && msg == Message::End {
+                state.grab.release();
+                state.grab.record(&cache_dir, &tier_name, Instant::now());
+            }
             handle_msg_recv(
                 &state,
                 msg,
@@ -267,6 +817,6 @@ pub fn start_break(config: &Config, app_sender: glib::Sender<Msg>) {
                 net_active_win_atom,
                 old_active_win
             )
-        ),
+        }),
     );
 }
@@ -0,0 +1,98 @@
+// This module shows the activity for a break on the break screen, moving on
+// to each of its steps as the break goes on.
+
+use std::time::Duration;
+
+use crate::config::Activity;
+
+/// The step of `activity` that is running `elapsed` into the break, and the
+/// time left in it.  `None` once all the steps are over.
+fn current_step(
+    activity: &Activity,
+    elapsed: Duration,
+) -> Option<(usize, Duration)> {
+    let mut step_end = Duration::from_secs(0);
+    for (index, step) in activity.steps.iter().enumerate() {
+        step_end += Duration::from_secs(step.seconds.into());
+        if let Some(remaining) = step_end.checked_sub(elapsed) {
+            if remaining > Duration::from_secs(0) {
+                return Some((index, remaining));
+            }
+        }
+    }
+    None
+}
+
+/// The text to show for `activity`, `elapsed` into the break.
+#[must_use]
+pub fn text(activity: &Activity, elapsed: Duration) -> String {
+    let mut text = activity.name.clone();
+    if !activity.text.is_empty() {
+        text = format!("{}\n{}", text, activity.text);
+    }
+    if let Some((index, remaining)) = current_step(activity, elapsed) {
+        // Round up, so that the countdown ends at 0:01 instead of 0:00.
+        let remaining_secs =
+            remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        text = format!(
+            "{}\n\nStep {} of {}: {} ({}:{:02})",
+            text,
+            index + 1,
+            activity.steps.len(),
+            activity.steps[index].text,
+            remaining_secs / 60,
+            remaining_secs % 60
+        );
+    }
+    text
+}
+
+/// Load the image for `activity`, scaled down to fit on the break screen.
+#[must_use]
+pub fn load_image(activity: &Activity) -> Option<gdk_pixbuf::Pixbuf> {
+    let image_path = activity.image.as_ref()?;
+    match gdk_pixbuf::Pixbuf::new_from_file_at_scale(image_path, 480, 360, true)
+    {
+        Err(err) => {
+            println!(
+                "Could not load the image {:?} for the activity {:?}: {}",
+                image_path, activity.name, err
+            );
+            None
+        }
+        Ok(pixbuf) => Some(pixbuf),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::ActivityStep;
+
+    #[test]
+    fn test_text() {
+        let step = |text: &str, seconds| ActivityStep {
+            text: String::from(text),
+            seconds,
+        };
+        let activity = Activity {
+            name: String::from("Neck stretch"),
+            text: String::from("Relax your shoulders."),
+            image: None,
+            steps: vec![step("Left", 30), step("Right", 30)],
+            tiers: vec![],
+        };
+        let at = |millis| text(&activity, Duration::from_millis(millis));
+
+        assert_eq!(
+            at(0),
+            "Neck stretch\nRelax your shoulders.\n\nStep 1 of 2: Left (0:30)"
+        );
+        assert_eq!(
+            at(45_500),
+            "Neck stretch\nRelax your shoulders.\n\nStep 2 of 2: Right (0:15)"
+        );
+        assert_eq!(at(60_000), "Neck stretch\nRelax your shoulders.");
+    }
+}
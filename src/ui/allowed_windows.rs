@@ -0,0 +1,421 @@
+// This module lets some windows stay usable during a break, like a softphone
+// or an incident pager.  They are picked by their WM_CLASS, with
+// `allowed_windows`.
+//
+// The allowed windows are raised above the break screens.  During a locked
+// break, an allowed window that is clicked is given the keyboard focus, and
+// the keyboard and mouse grab is let go while the pointer is over it and it
+// has the focus.  The grab is taken back as soon as the pointer leaves it or
+// the focus moves anywhere else, like with Alt-Tab, and it takes another
+// click to use the window again.  Each change of focus and each time an
+// allowed window is used is logged.
+//
+// Walking the window tree to find the allowed windows is slow, so they are
+// only looked for again when a window is mapped, unmapped, reparented or
+// destroyed.  Only their geometry is checked on every update.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use super::grab;
+use crate::config::Config;
+use crate::scheduler::plugins::{TopLevelWin, WindowTitles};
+use crate::x11::X11;
+
+/// Whether `allowed` has an entry matching the name or the class from a
+/// `WM_CLASS`, ignoring case.
+fn is_allowed(allowed: &[String], class_name: &str, class: &str) -> bool {
+    allowed.iter().any(|allowed_class| {
+        allowed_class.eq_ignore_ascii_case(class_name)
+            || allowed_class.eq_ignore_ascii_case(class)
+    })
+}
+
+/// Have `x11` get the events for windows being mapped, unmapped, reparented
+/// or destroyed.
+fn watch_wins(x11: &X11) {
+    if let Some(root_win) = x11.get_root_win() {
+        xcb::change_window_attributes(
+            &x11.conn,
+            root_win,
+            &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_SUBSTRUCTURE_NOTIFY)],
+        );
+        x11.conn.flush();
+    }
+}
+
+fn contains(win: &TopLevelWin, x: i32, y: i32) -> bool {
+    x >= win.x && x < win.x + win.width && y >= win.y && y < win.y + win.height
+}
+
+/// Whether `win` has the keyboard focus, given the focused window and its
+/// ancestors in `focus_chain`.
+fn has_focus(win: &TopLevelWin, focus_chain: &[xcb::Window]) -> bool {
+    focus_chain
+        .iter()
+        .any(|&focus| focus == win.win || focus == win.client)
+}
+
+/// The allowed window that can be used, which is the one under the pointer,
+/// if it has the keyboard focus and it is `chosen`.
+fn find_active<'a>(
+    wins: &'a [TopLevelWin],
+    pointer: Option<(i32, i32)>,
+    focus_chain: &[xcb::Window],
+    chosen: Option<xcb::Window>,
+) -> Option<&'a TopLevelWin> {
+    let (x, y) = pointer?;
+    wins.iter()
+        .find(|win| contains(win, x, y))
+        .filter(|win| has_focus(win, focus_chain))
+        .filter(|win| chosen == Some(win.win))
+}
+
+/// Whether `response_type` is for an event after which the allowed windows
+/// have to be looked for again.
+fn changes_wins(response_type: u8) -> bool {
+    [
+        xcb::MAP_NOTIFY,
+        xcb::UNMAP_NOTIFY,
+        xcb::REPARENT_NOTIFY,
+        xcb::DESTROY_NOTIFY,
+    ]
+    .contains(&(response_type & !0x80))
+}
+
+/// What changed about the allowed window in use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Started,
+    Stopped,
+    Unchanged,
+}
+
+pub struct AllowedWindows {
+    classes: Vec<String>,
+    window_titles: Option<WindowTitles>,
+    x11: X11,
+    cache_dir: PathBuf,
+    tier_name: String,
+    /// The class of the allowed window in use, and when it started being
+    /// used.
+    in_use: RefCell<Option<(String, Instant)>>,
+    /// The allowed window that has the keyboard focus, if any.
+    focused: RefCell<Option<xcb::Window>>,
+    /// The allowed window that was last clicked, or that the keyboard focus
+    /// moved to.  Only this window can start being used, so that moving the
+    /// pointer over an allowed window doesn't count as using it.
+    chosen: RefCell<Option<xcb::Window>>,
+    /// The allowed windows, until a window is mapped, unmapped, reparented
+    /// or destroyed.
+    wins: RefCell<Option<Vec<TopLevelWin>>>,
+}
+
+impl std::fmt::Debug for AllowedWindows {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AllowedWindows")
+            .field("classes", &self.classes)
+            .field("in_use", &self.in_use)
+            .field("focused", &self.focused)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AllowedWindows {
+    /// The allowed windows for a break from the break tier called
+    /// `tier_name`.
+    pub fn new(config: &Config, tier_name: &str) -> Self {
+        let classes = config.settings.allowed_windows.clone();
+        let window_titles = if classes.is_empty() {
+            None
+        } else {
+            WindowTitles::new(config)
+                .map_err(|()| {
+                    println!("Could not look for the allowed windows");
+                })
+                .ok()
+        };
+        let x11 = X11::connect();
+        if window_titles.is_some() {
+            watch_wins(&x11);
+        }
+        Self {
+            classes,
+            window_titles,
+            x11,
+            cache_dir: config.cache_dir.clone(),
+            tier_name: String::from(tier_name),
+            in_use: RefCell::new(None),
+            focused: RefCell::new(None),
+            chosen: RefCell::new(None),
+            wins: RefCell::new(None),
+        }
+    }
+
+    pub fn is_in_use(&self) -> bool {
+        self.in_use.borrow().is_some()
+    }
+
+    /// Raise the allowed windows above the break screens, and keep track of
+    /// which of them is in use.  The window that was clicked is in use while
+    /// the pointer is over it and it keeps the keyboard focus.
+    pub fn update(&self) -> Change {
+        let window_titles = match &self.window_titles {
+            None => return Change::Unchanged,
+            Some(window_titles) => window_titles,
+        };
+        let mut is_changed = false;
+        while let Some(event) = self.x11.conn.poll_for_event() {
+            is_changed |= changes_wins(event.response_type());
+        }
+        if is_changed || self.wins.borrow().is_none() {
+            let wins = window_titles
+                .find_wins(|class_name, class| {
+                    is_allowed(&self.classes, class_name, class)
+                })
+                .unwrap_or_default();
+            self.wins.replace(Some(wins));
+        } else {
+            self.update_geometry();
+        }
+        let wins_ref = self.wins.borrow();
+        let wins = wins_ref.as_deref().unwrap_or_default();
+        if wins.is_empty() {
+            self.update_focused(wins, &[]);
+            return if self.is_in_use() {
+                self.stop();
+                Change::Stopped
+            } else {
+                Change::Unchanged
+            };
+        }
+        for win in wins {
+            self.raise(win.win);
+        }
+
+        let pointer = self.pointer_position();
+        let focus_chain = self.focus_chain();
+        self.update_focused(wins, &focus_chain);
+
+        let active =
+            find_active(wins, pointer, &focus_chain, *self.chosen.borrow());
+        let change = match (active, self.is_in_use()) {
+            (Some(win), false) => {
+                self.started(&win.class);
+                Change::Started
+            }
+            (None, true) => {
+                self.stop();
+                // It takes another click to use it again.
+                self.chosen.replace(None);
+                Change::Stopped
+            }
+            _ => Change::Unchanged,
+        };
+        self.x11.conn.flush();
+        change
+    }
+
+    /// Give the keyboard focus to the allowed window at `x` and `y` on the
+    /// root window, if there is one, after it was clicked during a locked
+    /// break.
+    pub fn clicked(&self, x: i32, y: i32) {
+        let wins_ref = self.wins.borrow();
+        let win = match wins_ref
+            .as_deref()
+            .and_then(|wins| wins.iter().find(|win| contains(win, x, y)))
+        {
+            None => return,
+            Some(win) => win,
+        };
+        self.log(&format!(
+            "the allowed window {:?} was clicked, giving it the keyboard focus",
+            win.class
+        ));
+        self.chosen.replace(Some(win.win));
+        self.focus(win.client);
+        self.x11.conn.flush();
+    }
+
+    /// Get the geometry of the allowed windows again, in case they were moved
+    /// or resized.
+    fn update_geometry(&self) {
+        let mut wins_ref = self.wins.borrow_mut();
+        let wins = match wins_ref.as_mut() {
+            None => return,
+            Some(wins) => wins,
+        };
+        let cookies: Vec<_> = wins
+            .iter()
+            .map(|win| xcb::get_geometry(&self.x11.conn, win.win))
+            .collect();
+        for (win, cookie) in wins.iter_mut().zip(cookies) {
+            if let Ok(geometry) = cookie.get_reply() {
+                win.x = geometry.x().into();
+                win.y = geometry.y().into();
+                win.width = geometry.width().into();
+                win.height = geometry.height().into();
+            }
+        }
+    }
+
+    /// Log each time the keyboard focus moves to, from, or between allowed
+    /// windows.  An allowed window the focus moves to can be used.
+    fn update_focused(
+        &self,
+        wins: &[TopLevelWin],
+        focus_chain: &[xcb::Window],
+    ) {
+        let focused = wins.iter().find(|win| has_focus(win, focus_chain));
+        let old_focused = self.focused.replace(focused.map(|win| win.win));
+        if old_focused == focused.map(|win| win.win) {
+            return;
+        }
+        match focused {
+            Some(win) => {
+                self.log(&format!(
+                    "the keyboard focus moved to the allowed window {:?}",
+                    win.class
+                ));
+                self.chosen.replace(Some(win.win));
+            }
+            None => self.log(
+                "the keyboard focus moved from an allowed window to a window that isn't allowed",
+            ),
+        }
+    }
+
+    /// Stop using the allowed window in use, if there is one.
+    pub fn stop(&self) {
+        if let Some((class, since)) = self.in_use.replace(None) {
+            self.log(&format!(
+                "stopped using the allowed window {:?} after {} seconds",
+                class,
+                since.elapsed().as_secs()
+            ));
+        }
+    }
+
+    fn started(&self, class: &str) {
+        self.log(&format!("started using the allowed window {:?}", class));
+        self.in_use
+            .replace(Some((String::from(class), Instant::now())));
+    }
+
+    fn log(&self, text: &str) {
+        println!("During the {}, {}", self.tier_name, text);
+        grab::log(&self.cache_dir, &self.tier_name, text);
+    }
+
+    fn pointer_position(&self) -> Option<(i32, i32)> {
+        let root_win = self.x11.get_root_win()?;
+        let reply = xcb::query_pointer(&self.x11.conn, root_win)
+            .get_reply()
+            .ok()?;
+        Some((reply.root_x().into(), reply.root_y().into()))
+    }
+
+    /// The window with the keyboard focus, followed by its ancestors.
+    fn focus_chain(&self) -> Vec<xcb::Window> {
+        let mut chain = vec![];
+        let mut opt_win = xcb::get_input_focus(&self.x11.conn)
+            .get_reply()
+            .ok()
+            .map(|reply| reply.focus());
+        while let Some(win) = opt_win {
+            if win == xcb::NONE || chain.contains(&win) {
+                break;
+            }
+            chain.push(win);
+            opt_win = xcb::query_tree(&self.x11.conn, win)
+                .get_reply()
+                .ok()
+                .map(|reply| reply.parent());
+        }
+        chain
+    }
+
+    fn raise(&self, win: xcb::Window) {
+        let config_window_stack_mode =
+            u16::try_from(xcb::CONFIG_WINDOW_STACK_MODE)
+                .expect("CONFIG_WINDOW_STACK_MODE should fit in a u16");
+        xcb::configure_window(
+            &self.x11.conn,
+            win,
+            &[(config_window_stack_mode, xcb::STACK_MODE_ABOVE)],
+        );
+    }
+
+    fn focus(&self, win: xcb::Window) {
+        let input_focus_parent = u8::try_from(xcb::INPUT_FOCUS_PARENT)
+            .expect("INPUT_FOCUS_PARENT should fit in a u8");
+        xcb::set_input_focus(
+            &self.x11.conn,
+            input_focus_parent,
+            win,
+            xcb::CURRENT_TIME,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_allowed() {
+        let allowed = vec![String::from("zoiper"), String::from("PagerDuty")];
+        assert!(is_allowed(&allowed, "Zoiper", "Zoiper"));
+        assert!(is_allowed(&allowed, "pagerduty-desktop", "pagerduty"));
+        assert!(!is_allowed(&allowed, "Navigator", "Firefox"));
+    }
+
+    fn win(win: xcb::Window, client: xcb::Window, x: i32) -> TopLevelWin {
+        TopLevelWin {
+            win,
+            client,
+            class: String::from("Zoiper"),
+            x,
+            y: 0,
+            width: 100,
+            height: 100,
+        }
+    }
+
+    #[test]
+    fn test_find_active() {
+        let wins = vec![win(1, 2, 0), win(3, 3, 200)];
+
+        // The focus is on a child of the client window of the hovered window.
+        let active = find_active(&wins, Some((50, 50)), &[7, 2, 1, 0], Some(1));
+        assert_eq!(active.map(|win| win.win), Some(1));
+        assert_eq!(
+            find_active(&wins, Some((250, 50)), &[3, 0], Some(3))
+                .map(|win| win.win),
+            Some(3)
+        );
+
+        // The focus moved to a window that isn't allowed.
+        assert!(find_active(&wins, Some((50, 50)), &[9, 0], Some(1)).is_none());
+        // The focus is on an allowed window, but not the hovered one.
+        assert!(find_active(&wins, Some((50, 50)), &[3, 0], Some(1)).is_none());
+        // The pointer isn't over an allowed window.
+        assert!(find_active(&wins, Some((150, 50)), &[1, 0], Some(1)).is_none());
+        assert!(find_active(&wins, None, &[1, 0], Some(1)).is_none());
+        // The hovered window has the focus, but it wasn't clicked since it
+        // was last used.
+        assert!(find_active(&wins, Some((50, 50)), &[1, 0], None).is_none());
+        assert!(find_active(&wins, Some((50, 50)), &[1, 0], Some(3)).is_none());
+    }
+
+    #[test]
+    fn test_changes_wins() {
+        assert!(changes_wins(xcb::MAP_NOTIFY));
+        // Sent by another client.
+        assert!(changes_wins(xcb::DESTROY_NOTIFY | 0x80));
+        // Raising a window only changes the stacking order.
+        assert!(!changes_wins(xcb::CONFIGURE_NOTIFY));
+    }
+}
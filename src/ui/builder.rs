@@ -1,3 +1,45 @@
+// The break screen is built from `glade/ui.glade`, or from `ui.glade` in the
+// config directory if there is one.  A custom Glade file has to have the
+// objects that break-time can't do without, listed below.  The other labels,
+// like `pomodoro_label`, can be left out to hide them.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use super::prelude::*;
+
+const DEFAULT_GLADE_SRC: &str = include_str!("../../glade/ui.glade");
+
+/// The labels that a custom Glade file has to have.  The challenge progress
+/// label is needed to see how to end a break early.
+const REQUIRED_LABELS: &[&str] =
+    &["time_remaining_label", "challenge_progress_label"];
+
+/// Ids that labels used to have, so that older custom Glade files keep
+/// working.  `challenge_progress_label` was `presses_remaining_label` back
+/// when the only challenge was pressing a key.
+const OLD_LABEL_IDS: &[(&str, &str)] =
+    &[("challenge_progress_label", "presses_remaining_label")];
+
+/// Get the label with `id` from `builder`, falling back to its old id.
+fn find_label(builder: &gtk::Builder, id: &str) -> Option<gtk::Label> {
+    builder.get_object(id).or_else(|| {
+        OLD_LABEL_IDS
+            .iter()
+            .find(|(new_id, _)| *new_id == id)
+            .and_then(|(_, old_id)| builder.get_object(old_id))
+    })
+}
+
+/// Get the label with `id` from `builder`, which has been checked to have it.
+#[must_use]
+pub fn get_label(builder: &gtk::Builder, id: &str) -> gtk::Label {
+    find_label(builder, id).expect(&format!(
+        "Expected to get \"{}\" from the builder, but failed.",
+        id
+    ))
+}
+
 #[allow(clippy::module_name_repetitions)]
 pub trait BuilderExtManualGetObjectExpect {
     fn get_object_expect<T: glib::object::IsA<glib::object::Object>>(
@@ -21,8 +63,58 @@ where
     }
 }
 
+/// Check that `glade_src` builds, and that it has all the required objects.
+fn check(glade_src: &str) -> Result<(), String> {
+    let builder = gtk::Builder::new();
+    builder
+        .add_from_string(glade_src)
+        .map_err(|err| err.to_string())?;
+
+    let mut missing = vec![];
+    if builder
+        .get_object::<gtk::ApplicationWindow>("app_win")
+        .is_none()
+    {
+        missing.push(String::from("app_win (a GtkApplicationWindow)"));
+    }
+    for id in REQUIRED_LABELS {
+        if find_label(&builder, id).is_none() {
+            missing.push(format!("{} (a GtkLabel)", id));
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("it is missing {}", missing.join(", ")))
+    }
+}
+
+/// Load the Glade file for the break screen, using `ui.glade` from
+/// `conf_dir` if there is one and it is usable.
+#[must_use]
+pub fn load_src(conf_dir: &Path) -> Cow<'static, str> {
+    let glade_path = conf_dir.join("ui.glade");
+    let glade_src = match std::fs::read_to_string(&glade_path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Cow::Borrowed(DEFAULT_GLADE_SRC)
+        }
+        Err(err) => Err(err.to_string()),
+        Ok(glade_src) => check(&glade_src).map(|()| glade_src),
+    };
+    match glade_src {
+        Err(err) => {
+            println!(
+                "Not using the Glade file at {:?}, because {}.  Using the built-in one instead.",
+                glade_path, err
+            );
+            Cow::Borrowed(DEFAULT_GLADE_SRC)
+        }
+        Ok(glade_src) => Cow::Owned(glade_src),
+    }
+}
+
 #[must_use]
-pub fn create() -> gtk::Builder {
-    let glade_src = include_str!("../../glade/ui.glade");
+pub fn create(glade_src: &str) -> gtk::Builder {
     gtk::Builder::new_from_string(glade_src)
 }
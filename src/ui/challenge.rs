@@ -0,0 +1,467 @@
+// This module defines the challenges for ending a break early.  The break
+// screen shows the challenge's title, progress, and details, and passes it
+// the keys the user presses.  Once the challenge is done, the break ends.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{Local, NaiveDate};
+
+use super::prelude::*;
+use crate::config::{ChallengeKind, ChallengeSettings};
+
+/// A key that was pressed or released on the break screen.
+#[derive(Clone, Copy, Debug)]
+pub struct Key {
+    pub keyval: u32,
+    /// The character the key types, if any.
+    pub ch: Option<char>,
+}
+
+pub trait Challenge: std::fmt::Debug {
+    /// A short title, shown under the time remaining, like "Spaces left:".
+    fn title(&self) -> String;
+
+    /// The progress so far, shown next to the title, like "397".
+    fn progress(&self) -> String;
+
+    /// Longer text, like the sentence to type.
+    fn details(&self) -> String {
+        String::new()
+    }
+
+    fn key_pressed(&mut self, _key: Key, _now: Instant) {}
+
+    fn key_released(&mut self, _key: Key, _now: Instant) {}
+
+    /// Called regularly, for challenges that depend on time.
+    fn tick(&mut self, _now: Instant) {}
+
+    fn is_done(&self) -> bool;
+}
+
+/// Create the configured challenge.
+///
+/// `presses` is the number of presses for the `press-key` challenge, `keyval`
+/// is the key for the `press-key` and `hold-key` challenges, and `difficulty`
+/// is how much harder than normal to make the challenge, starting at 0.
+#[must_use]
+pub fn create(
+    settings: &ChallengeSettings,
+    presses: u32,
+    keyval: u32,
+    difficulty: u32,
+) -> Box<dyn Challenge> {
+    match settings.kind {
+        ChallengeKind::PressKey => Box::new(PressKey {
+            keyval,
+            key_name: settings.key.clone(),
+            presses_remaining: presses + presses * difficulty / 2,
+        }),
+        ChallengeKind::TypeSentence => Box::new(TypeSentence {
+            sentence: settings.sentence.clone(),
+            typed: String::new(),
+            times_remaining: 1 + difficulty,
+        }),
+        ChallengeKind::Arithmetic => {
+            let mut arithmetic = Arithmetic {
+                rng: Rng::from_time(),
+                difficulty,
+                problem: (0, '+', 0),
+                answer: String::new(),
+                problems_remaining: settings.problems + difficulty,
+            };
+            arithmetic.next_problem();
+            Box::new(arithmetic)
+        }
+        ChallengeKind::HoldKey => Box::new(HoldKey {
+            keyval,
+            key_name: settings.key.clone(),
+            hold_for: Duration::from_secs(
+                (settings.hold_seconds * (2 + difficulty) / 2).into(),
+            ),
+            held_since: None,
+            held_for: Duration::from_secs(0),
+        }),
+    }
+}
+
+/// Press a key a number of times.
+#[derive(Debug)]
+struct PressKey {
+    keyval: u32,
+    key_name: String,
+    presses_remaining: u32,
+}
+
+impl Challenge for PressKey {
+    fn title(&self) -> String {
+        if self.key_name == "space" {
+            String::from("Spaces left:")
+        } else {
+            format!("Presses of {} left:", self.key_name)
+        }
+    }
+
+    fn progress(&self) -> String {
+        self.presses_remaining.to_string()
+    }
+
+    fn key_released(&mut self, key: Key, _now: Instant) {
+        if key.keyval == self.keyval && self.presses_remaining > 0 {
+            self.presses_remaining -= 1;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.presses_remaining == 0
+    }
+}
+
+/// Type a sentence exactly.  A wrong character starts the sentence over.
+#[derive(Debug)]
+struct TypeSentence {
+    sentence: String,
+    typed: String,
+    times_remaining: u32,
+}
+
+impl Challenge for TypeSentence {
+    fn title(&self) -> String {
+        String::from("Sentences left:")
+    }
+
+    fn progress(&self) -> String {
+        self.times_remaining.to_string()
+    }
+
+    fn details(&self) -> String {
+        format!("{}\n{}_", self.sentence, self.typed)
+    }
+
+    fn key_pressed(&mut self, key: Key, _now: Instant) {
+        if key.keyval == gdk::enums::key::BackSpace {
+            self.typed.pop();
+            return;
+        }
+        let ch = match key.ch {
+            Some(ch) if !ch.is_control() => ch,
+            _ => return,
+        };
+        self.typed.push(ch);
+        if !self.sentence.starts_with(&self.typed) {
+            self.typed.clear();
+        } else if self.typed == self.sentence && self.times_remaining > 0 {
+            self.typed.clear();
+            self.times_remaining -= 1;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.times_remaining == 0
+    }
+}
+
+/// Solve arithmetic problems.  A wrong answer gets a new problem.
+#[derive(Debug)]
+struct Arithmetic {
+    rng: Rng,
+    difficulty: u32,
+    problem: (u32, char, u32),
+    answer: String,
+    problems_remaining: u32,
+}
+
+impl Arithmetic {
+    fn next_problem(&mut self) {
+        let max = 20 * (self.difficulty + 1);
+        self.problem = match self.rng.below(3) {
+            0 => (self.rng.below(max) + 1, '+', self.rng.below(max) + 1),
+            1 => {
+                let a = self.rng.below(max) + 1;
+                let b = self.rng.below(max) + 1;
+                (std::cmp::max(a, b), '-', std::cmp::min(a, b))
+            }
+            _ => {
+                let max = 10 + 3 * self.difficulty;
+                (self.rng.below(max) + 2, '×', self.rng.below(max) + 2)
+            }
+        };
+        self.answer.clear();
+    }
+
+    const fn solution(&self) -> u32 {
+        match self.problem {
+            (a, '+', b) => a + b,
+            (a, '-', b) => a - b,
+            (a, _, b) => a * b,
+        }
+    }
+}
+
+impl Challenge for Arithmetic {
+    fn title(&self) -> String {
+        String::from("Problems left:")
+    }
+
+    fn progress(&self) -> String {
+        self.problems_remaining.to_string()
+    }
+
+    fn details(&self) -> String {
+        let (a, op, b) = self.problem;
+        format!("{} {} {} = {}_", a, op, b, self.answer)
+    }
+
+    fn key_pressed(&mut self, key: Key, _now: Instant) {
+        match key.keyval {
+            gdk::enums::key::BackSpace => {
+                self.answer.pop();
+            }
+            gdk::enums::key::Return | gdk::enums::key::KP_Enter => {
+                if self.problems_remaining == 0 {
+                    return;
+                }
+                if self.answer.parse() == Ok(self.solution()) {
+                    self.problems_remaining -= 1;
+                }
+                self.next_problem();
+            }
+            _ => match key.ch {
+                Some(ch) if ch.is_ascii_digit() && self.answer.len() < 6 => {
+                    self.answer.push(ch);
+                }
+                _ => (),
+            },
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.problems_remaining == 0
+    }
+}
+
+/// Hold a key down for a while without letting go.
+#[derive(Debug)]
+struct HoldKey {
+    keyval: u32,
+    key_name: String,
+    hold_for: Duration,
+    held_since: Option<Instant>,
+    held_for: Duration,
+}
+
+impl Challenge for HoldKey {
+    fn title(&self) -> String {
+        format!("Hold {} for:", self.key_name)
+    }
+
+    fn progress(&self) -> String {
+        let remaining = self
+            .hold_for
+            .checked_sub(self.held_for)
+            .unwrap_or_else(|| Duration::from_secs(0));
+        format!("{}s", remaining.as_secs())
+    }
+
+    fn key_pressed(&mut self, key: Key, now: Instant) {
+        if key.keyval == self.keyval && self.held_since.is_none() {
+            self.held_since = Some(now);
+        }
+    }
+
+    fn key_released(&mut self, key: Key, _now: Instant) {
+        if key.keyval == self.keyval && !self.is_done() {
+            self.held_since = None;
+            self.held_for = Duration::from_secs(0);
+        }
+    }
+
+    fn tick(&mut self, now: Instant) {
+        if let Some(held_since) = self.held_since {
+            self.held_for = now.duration_since(held_since);
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.held_for >= self.hold_for
+    }
+}
+
+/// A small xorshift random number generator.  This only needs to make
+/// problems that are hard to guess, so it doesn't need to be any good.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.subsec_nanos());
+        Self(u64::from(nanos) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A random number from 0 up to, but not including, `max`.
+    fn below(&mut self, max: u32) -> u32 {
+        u32::try_from(self.next() % u64::from(std::cmp::max(max, 1)))
+            .expect("A number below a u32 should always fit in a u32")
+    }
+}
+
+/// How many breaks have been ended early today.  This is kept in the cache
+/// directory, so that restarting break-time doesn't reset it.
+pub struct EarlyExits {
+    path: PathBuf,
+    date: NaiveDate,
+    count: u32,
+}
+
+impl EarlyExits {
+    #[must_use]
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join("early_exits");
+        let today = Local::today().naive_local();
+        let opt_count = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| {
+                let mut words = contents.split_whitespace();
+                let date: NaiveDate = words.next()?.parse().ok()?;
+                let count: u32 = words.next()?.parse().ok()?;
+                Some((date, count))
+            })
+            .filter(|(date, _)| *date == today)
+            .map(|(_, count)| count);
+        Self {
+            path,
+            date: today,
+            count: opt_count.unwrap_or(0),
+        }
+    }
+
+    fn roll_over(&mut self) {
+        let today = Local::today().naive_local();
+        if self.date != today {
+            self.date = today;
+            self.count = 0;
+        }
+    }
+
+    pub fn today(&mut self) -> u32 {
+        self.roll_over();
+        self.count
+    }
+
+    pub fn record(&mut self) {
+        self.roll_over();
+        self.count += 1;
+        let contents = format!("{} {}\n", self.date, self.count);
+        if let Err(err) = std::fs::write(&self.path, contents) {
+            println!(
+                "Could not save the number of breaks ended early to {:?}: {}",
+                self.path, err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(kind: ChallengeKind) -> ChallengeSettings {
+        ChallengeSettings {
+            kind,
+            sentence: String::from("ok go"),
+            ..ChallengeSettings::default()
+        }
+    }
+
+    fn type_str(challenge: &mut dyn Challenge, s: &str, now: Instant) {
+        for ch in s.chars() {
+            challenge.key_pressed(
+                Key {
+                    keyval: 0,
+                    ch: Some(ch),
+                },
+                now,
+            );
+        }
+    }
+
+    #[test]
+    fn test_challenges() {
+        let now = Instant::now();
+        let space = Key {
+            keyval: gdk::enums::key::space,
+            ch: Some(' '),
+        };
+
+        let mut press_key = create(
+            &settings(ChallengeKind::PressKey),
+            2,
+            gdk::enums::key::space,
+            2,
+        );
+        assert_eq!(press_key.progress(), "4");
+        for _ in 0..4 {
+            assert!(!press_key.is_done());
+            press_key.key_released(space, now);
+        }
+        assert!(press_key.is_done());
+
+        let mut type_sentence =
+            create(&settings(ChallengeKind::TypeSentence), 0, 0, 1);
+        type_str(&mut *type_sentence, "ok gx", now);
+        assert_eq!(type_sentence.details(), "ok go\n_");
+        type_str(&mut *type_sentence, "ok go", now);
+        assert!(!type_sentence.is_done());
+        type_str(&mut *type_sentence, "ok go", now);
+        assert!(type_sentence.is_done());
+
+        let mut hold_key = create(
+            &settings(ChallengeKind::HoldKey),
+            0,
+            gdk::enums::key::space,
+            0,
+        );
+        hold_key.key_pressed(space, now);
+        hold_key.tick(now + Duration::from_secs(5));
+        hold_key.key_released(space, now + Duration::from_secs(5));
+        hold_key.key_pressed(space, now + Duration::from_secs(6));
+        hold_key.tick(now + Duration::from_secs(15));
+        assert!(!hold_key.is_done());
+        hold_key.tick(now + Duration::from_secs(16));
+        assert!(hold_key.is_done());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut arithmetic = Arithmetic {
+            rng: Rng(1),
+            difficulty: 0,
+            problem: (0, '+', 0),
+            answer: String::new(),
+            problems_remaining: 1,
+        };
+        arithmetic.next_problem();
+        let enter = Key {
+            keyval: gdk::enums::key::Return,
+            ch: None,
+        };
+
+        type_str(&mut arithmetic, "100000000", Instant::now());
+        assert_eq!(arithmetic.answer.len(), 6);
+        arithmetic.answer.clear();
+        let solution = arithmetic.solution().to_string();
+        type_str(&mut arithmetic, &solution, Instant::now());
+        arithmetic.key_pressed(enter, Instant::now());
+        assert!(arithmetic.is_done());
+    }
+}
@@ -1,6 +1,30 @@
+use std::path::Path;
+
 use super::prelude::*;
 
-pub fn setup(window: &gtk::Window) {
+const DEFAULT_CSS_SRC: &str = include_str!("../../style/style.css");
+
+/// Create the CSS provider for the break screen, using `style.css` from
+/// `conf_dir` if there is one and it is usable.
+pub fn create_provider(conf_dir: &Path) -> gtk::CssProvider {
+    let css_provider = gtk::CssProvider::new();
+    let css_path = conf_dir.join("style.css");
+    if css_path.exists() {
+        match css_provider.load_from_path(&css_path.to_string_lossy()) {
+            Err(err) => println!(
+                "Not using the CSS file at {:?}, because {}.  Using the built-in one instead.",
+                css_path, err
+            ),
+            Ok(_) => return css_provider,
+        }
+    }
+    if let Err(err) = css_provider.load_from_data(DEFAULT_CSS_SRC.as_bytes()) {
+        println!("Failed to load css provider from data: {}", err);
+    }
+    css_provider
+}
+
+pub fn setup(window: &gtk::Window, css_provider: &gtk::CssProvider) {
     let screen: gdk::Screen = match window.get_screen() {
         Some(screen) => screen,
         None => {
@@ -8,16 +32,9 @@ pub fn setup(window: &gtk::Window) {
             return;
         }
     };
-    let css_provider = gtk::CssProvider::new();
-    let css_src = include_str!("../../style/style.css");
-    match css_provider.load_from_data(css_src.as_bytes()) {
-        Err(err) => println!("Failed to load css provider from data: {}", err),
-        Ok(_) => {
-            gtk::StyleContext::add_provider_for_screen(
-                &screen,
-                &css_provider,
-                gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
-            );
-        }
-    }
+    gtk::StyleContext::add_provider_for_screen(
+        &screen,
+        css_provider,
+        gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+    );
 }
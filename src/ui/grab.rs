@@ -0,0 +1,435 @@
+// This module keeps the keyboard and mouse grabbed during a locked break.
+//
+// The break screen grabs them through GDK.  Other clients can break that
+// grab later on, like a notification popup or a restarting compositor, so
+// the grab is taken again whenever it is broken.  If GDK can't grab them,
+// they are grabbed through a separate XCB connection instead.  The break
+// screen doesn't get key presses then, so they are read from that connection
+// and passed on to the challenge, along with the mouse clicks.
+//
+// Nothing tells us when an XCB grab is taken away, so it is checked by
+// grabbing again on every tick, which only works while this connection
+// still holds the grab.
+//
+// The time spent without a grab is kept, to record whether the break was
+// fully enforced.  The grab is also let go on purpose while an allowed window
+// is used, which isn't counted.
+
+#![allow(unsafe_code)]
+
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::challenge::Key;
+use crate::x11::X11;
+
+/// A key press or release, or a mouse click, while grabbing through XCB.
+#[derive(Clone, Copy, Debug)]
+pub enum XcbEvent {
+    /// A key, with whether it was pressed.
+    Key(Key, bool),
+    /// A mouse button press, at a position on the root window.
+    Click(i32, i32),
+}
+
+/// What is holding the keyboard and mouse grab.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Holder {
+    Nothing,
+    Gdk,
+    Xcb,
+}
+
+/// A keyboard and mouse grab through XCB, with what is needed to turn the
+/// key codes it gets into key values.
+struct XcbGrab {
+    x11: X11,
+    min_keycode: xcb::Keycode,
+    keysyms_per_keycode: usize,
+    keysyms: Vec<xcb::Keysym>,
+}
+
+/// Grab the keyboard and mouse on the root window.  Grabbing them again
+/// while this connection already has them succeeds.
+fn grab_devices(x11: &X11) -> Result<(), String> {
+    let grab_mode_async = u8::try_from(xcb::GRAB_MODE_ASYNC)
+        .expect("GRAB_MODE_ASYNC should fit in a u8");
+    let event_mask_button_press = u16::try_from(xcb::EVENT_MASK_BUTTON_PRESS)
+        .expect("EVENT_MASK_BUTTON_PRESS should fit in a u16");
+    let root_win = x11
+        .get_root_win()
+        .ok_or_else(|| String::from("could not get the root window"))?;
+
+    let keyboard_status = xcb::grab_keyboard(
+        &x11.conn,
+        false,
+        root_win,
+        xcb::CURRENT_TIME,
+        grab_mode_async,
+        grab_mode_async,
+    )
+    .get_reply()
+    .map_err(|err| format!("could not grab the keyboard: {:?}", err))?
+    .status();
+    if u32::from(keyboard_status) != xcb::GRAB_STATUS_SUCCESS {
+        return Err(format!(
+            "could not grab the keyboard, status {}",
+            keyboard_status
+        ));
+    }
+
+    let pointer_status = xcb::grab_pointer(
+        &x11.conn,
+        false,
+        root_win,
+        event_mask_button_press,
+        grab_mode_async,
+        grab_mode_async,
+        xcb::NONE,
+        xcb::NONE,
+        xcb::CURRENT_TIME,
+    )
+    .get_reply()
+    .map_err(|err| format!("could not grab the mouse: {:?}", err))?
+    .status();
+    if u32::from(pointer_status) != xcb::GRAB_STATUS_SUCCESS {
+        return Err(format!(
+            "could not grab the mouse, status {}",
+            pointer_status
+        ));
+    }
+
+    Ok(())
+}
+
+impl XcbGrab {
+    fn grab() -> Result<Self, String> {
+        let x11 = X11::connect();
+        grab_devices(&x11)?;
+
+        let setup = x11.conn.get_setup();
+        let min_keycode = setup.min_keycode();
+        let keycode_count = setup.max_keycode() - min_keycode + 1;
+        let mapping =
+            xcb::get_keyboard_mapping(&x11.conn, min_keycode, keycode_count)
+                .get_reply()
+                .map_err(|err| {
+                    format!("could not get the keyboard mapping: {:?}", err)
+                })?;
+        let keysyms_per_keycode = usize::from(mapping.keysyms_per_keycode());
+        let keysyms = mapping.keysyms().to_vec();
+
+        Ok(Self {
+            x11,
+            min_keycode,
+            keysyms_per_keycode,
+            keysyms,
+        })
+    }
+
+    /// The key value for `keycode`, with the modifiers in `state` held down.
+    fn keyval(&self, keycode: xcb::Keycode, state: u16) -> u32 {
+        let index = usize::from(keycode.saturating_sub(self.min_keycode))
+            * self.keysyms_per_keycode;
+        let keysyms = self
+            .keysyms
+            .get(index..index + self.keysyms_per_keycode)
+            .unwrap_or(&[]);
+        let is_shifted =
+            u32::from(state) & (xcb::MOD_MASK_SHIFT | xcb::MOD_MASK_LOCK) != 0;
+        match (keysyms.first(), keysyms.get(1)) {
+            (_, Some(&shifted)) if is_shifted && shifted != 0 => shifted,
+            (Some(&unshifted), _) => unshifted,
+            _ => 0,
+        }
+    }
+
+    /// The keys pressed and released and the mouse clicks since the last
+    /// call.
+    fn events(&self) -> Vec<XcbEvent> {
+        let mut events = vec![];
+        while let Some(event) = self.x11.conn.poll_for_event() {
+            let response_type = event.response_type() & !0x80;
+            if response_type == xcb::BUTTON_PRESS {
+                let button_event: &xcb::ButtonPressEvent =
+                    unsafe { xcb::cast_event(&event) };
+                events.push(XcbEvent::Click(
+                    button_event.root_x().into(),
+                    button_event.root_y().into(),
+                ));
+                continue;
+            }
+            if response_type != xcb::KEY_PRESS
+                && response_type != xcb::KEY_RELEASE
+            {
+                continue;
+            }
+            let key_event: &xcb::KeyPressEvent =
+                unsafe { xcb::cast_event(&event) };
+            let keyval = self.keyval(key_event.detail(), key_event.state());
+            events.push(XcbEvent::Key(
+                Key {
+                    keyval,
+                    ch: gdk::keyval_to_unicode(keyval),
+                },
+                response_type == xcb::KEY_PRESS,
+            ));
+        }
+        events
+    }
+}
+
+pub struct Grab {
+    holder: Cell<Holder>,
+    /// Whether a GDK grab is being tried.
+    is_grabbing: Cell<bool>,
+    /// When the grab was lost, if it isn't held now.
+    ungrabbed_since: Cell<Option<Instant>>,
+    /// The time spent without a grab before `ungrabbed_since`.
+    ungrabbed_for: Cell<Duration>,
+    xcb: RefCell<Option<XcbGrab>>,
+}
+
+impl std::fmt::Debug for Grab {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Grab")
+            .field("holder", &self.holder.get())
+            .field("ungrabbed_for", &self.ungrabbed_for(Instant::now()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Grab {
+    /// A grab that isn't held yet, for a break starting at `now`.
+    pub const fn new(now: Instant) -> Self {
+        Self {
+            holder: Cell::new(Holder::Nothing),
+            is_grabbing: Cell::new(false),
+            ungrabbed_since: Cell::new(Some(now)),
+            ungrabbed_for: Cell::new(Duration::from_secs(0)),
+            xcb: RefCell::new(None),
+        }
+    }
+
+    /// Start trying to grab through GDK.  Returns `false` if that is already
+    /// being tried, or if the grab is already held.
+    pub fn start_grabbing(&self) -> bool {
+        if self.is_grabbing.get() || self.holder.get() != Holder::Nothing {
+            return false;
+        }
+        self.is_grabbing.set(true);
+        true
+    }
+
+    pub fn grabbed_with_gdk(&self, now: Instant) {
+        self.is_grabbing.set(false);
+        self.held_by(Holder::Gdk, now);
+    }
+
+    /// Give up on grabbing through GDK, and grab through XCB instead.
+    pub fn grab_with_xcb(&self) -> Result<(), String> {
+        self.is_grabbing.set(false);
+        let xcb_grab = XcbGrab::grab()?;
+        self.xcb.replace(Some(xcb_grab));
+        self.held_by(Holder::Xcb, Instant::now());
+        Ok(())
+    }
+
+    fn held_by(&self, holder: Holder, now: Instant) {
+        self.stop_counting(now);
+        self.holder.set(holder);
+    }
+
+    /// Add the time since the grab was lost to `ungrabbed_for`.
+    fn stop_counting(&self, now: Instant) {
+        if let Some(ungrabbed_since) = self.ungrabbed_since.take() {
+            self.ungrabbed_for.set(
+                self.ungrabbed_for.get()
+                    + now.saturating_duration_since(ungrabbed_since),
+            );
+        }
+    }
+
+    fn lost_by(&self, holder: Holder, now: Instant) {
+        if self.holder.get() == holder {
+            self.holder.set(Holder::Nothing);
+            self.ungrabbed_since.set(Some(now));
+        }
+    }
+
+    /// The GDK grab was broken by another client.
+    pub fn lost(&self, now: Instant) {
+        self.lost_by(Holder::Gdk, now);
+    }
+
+    /// Check that the XCB grab, if there is one, is still held.  Returns
+    /// `false` if it has been lost, after letting go of it.
+    pub fn check_xcb(&self, now: Instant) -> bool {
+        let res = match &*self.xcb.borrow() {
+            None => return true,
+            Some(xcb_grab) => grab_devices(&xcb_grab.x11),
+        };
+        match res {
+            Ok(()) => true,
+            Err(err) => {
+                println!(
+                    "The keyboard/mouse grab through XCB was lost: {}",
+                    err
+                );
+                self.release();
+                self.lost_by(Holder::Xcb, now);
+                false
+            }
+        }
+    }
+
+    /// Let go of the grab on purpose, while an allowed window is used.  The
+    /// time until `resume` isn't counted as time without a grab.
+    pub fn suspend(&self, now: Instant) {
+        self.release();
+        self.is_grabbing.set(false);
+        self.holder.set(Holder::Nothing);
+        self.stop_counting(now);
+    }
+
+    /// Start counting the time without a grab again, after `suspend`.
+    pub fn resume(&self, now: Instant) {
+        if self.holder.get() == Holder::Nothing {
+            self.ungrabbed_since.set(Some(now));
+        }
+    }
+
+    /// The keys pressed and released and the mouse clicks while grabbing
+    /// through XCB.
+    pub fn xcb_events(&self) -> Vec<XcbEvent> {
+        self.xcb
+            .borrow()
+            .as_ref()
+            .map(XcbGrab::events)
+            .unwrap_or_default()
+    }
+
+    /// Let go of the XCB grab, if there is one.
+    pub fn release(&self) {
+        if let Some(xcb_grab) = self.xcb.replace(None) {
+            xcb::ungrab_keyboard(&xcb_grab.x11.conn, xcb::CURRENT_TIME);
+            xcb::ungrab_pointer(&xcb_grab.x11.conn, xcb::CURRENT_TIME);
+            xcb_grab.x11.conn.flush();
+        }
+    }
+
+    /// How long the break has gone without a grab, up to `now`.
+    pub fn ungrabbed_for(&self, now: Instant) -> Duration {
+        self.ungrabbed_for.get()
+            + self.ungrabbed_since.get().map_or_else(
+                || Duration::from_secs(0),
+                |since| now.saturating_duration_since(since),
+            )
+    }
+
+    /// Log whether the break from the break tier called `tier_name` was fully
+    /// enforced, and add it to `enforcement.log` in `cache_dir`.
+    pub fn record(&self, cache_dir: &Path, tier_name: &str, now: Instant) {
+        let ungrabbed_secs = self.ungrabbed_for(now).as_secs();
+        let result = if ungrabbed_secs == 0 {
+            String::from("fully enforced")
+        } else {
+            format!(
+                "not enforced for {} seconds, without a keyboard and mouse grab",
+                ungrabbed_secs
+            )
+        };
+        println!("The {} was {}", tier_name, result);
+        log(cache_dir, tier_name, &result);
+    }
+}
+
+/// Add `text` about the break from the break tier called `tier_name` to
+/// `enforcement.log` in `cache_dir`.
+pub fn log(cache_dir: &Path, tier_name: &str, text: &str) {
+    let log_path = cache_dir.join("enforcement.log");
+    let res = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .and_then(|mut log_file| {
+            writeln!(
+                log_file,
+                "{} {}: {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                tier_name,
+                text
+            )
+        });
+    if let Err(err) = res {
+        println!("Could not write to {:?}: {}", log_path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ungrabbed_for() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let grab = Grab::new(start);
+
+        // Taking 2 seconds to grab counts as time without a grab.
+        assert!(grab.start_grabbing());
+        assert!(!grab.start_grabbing());
+        grab.grabbed_with_gdk(at(2));
+        assert!(!grab.start_grabbing());
+        assert_eq!(grab.ungrabbed_for(at(10)), Duration::from_secs(2));
+
+        // So does the time after another client breaks the grab.
+        grab.lost(at(10));
+        assert_eq!(grab.ungrabbed_for(at(13)), Duration::from_secs(5));
+        assert!(grab.start_grabbing());
+        grab.grabbed_with_gdk(at(13));
+
+        // Using an allowed window doesn't.
+        grab.suspend(at(20));
+        grab.resume(at(50));
+        assert_eq!(grab.ungrabbed_for(at(50)), Duration::from_secs(5));
+        assert!(grab.start_grabbing());
+        grab.grabbed_with_gdk(at(51));
+        assert_eq!(grab.ungrabbed_for(at(60)), Duration::from_secs(6));
+
+        // Losing a grab that isn't held doesn't start counting again.
+        grab.suspend(at(60));
+        grab.lost(at(60));
+        assert_eq!(grab.ungrabbed_for(at(90)), Duration::from_secs(6));
+    }
+
+    #[test]
+    fn test_record() {
+        let cache_dir = std::env::temp_dir()
+            .join(format!("break-time-grab-{}", std::process::id()));
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        let start = Instant::now();
+
+        let grab = Grab::new(start);
+        grab.grabbed_with_gdk(start);
+        grab.record(&cache_dir, "short break", start);
+
+        let grab = Grab::new(start);
+        grab.grabbed_with_gdk(start + Duration::from_secs(5));
+        grab.record(&cache_dir, "long break", start);
+
+        let log =
+            std::fs::read_to_string(cache_dir.join("enforcement.log")).unwrap();
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" short break: fully enforced"));
+        assert!(lines[1].ends_with(
+            " long break: not enforced for 5 seconds, without a keyboard and mouse grab"
+        ));
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+}
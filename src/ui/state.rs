@@ -1,9 +1,15 @@
+use super::allowed_windows::AllowedWindows;
 use super::builder;
+use super::challenge::Challenge;
+use super::grab::Grab;
 use super::prelude::*;
-use std::sync::{Arc, RwLock, RwLockReadGuard};
-use std::time::SystemTime;
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
-use crate::config::Config;
+use crate::config::Activity;
 use crate::Msg;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -56,88 +62,139 @@ impl AsRef<gdk::Monitor> for Monitor {
     }
 }
 
+/// The break screen window on one monitor.
+#[derive(Clone, Debug)]
+pub struct Screen {
+    pub builder: gtk::Builder,
+    pub monitor: Monitor,
+}
+
+impl Screen {
+    pub fn app_win(&self) -> gtk::ApplicationWindow {
+        self.builder.get_object_expect("app_win")
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct State {
-    pub builders: Vec<gtk::Builder>,
-    pub monitors: Vec<Monitor>,
+    /// The break screens, one for each monitor.  Screens are added and
+    /// removed while the break is running, as monitors are plugged in and
+    /// unplugged.
+    pub screens: Rc<RefCell<Vec<Screen>>>,
+    glade_src: Rc<str>,
+    /// The signal handlers that follow the monitors, to disconnect when the
+    /// break ends.
+    pub monitor_signal_handlers:
+        Rc<RefCell<Vec<(glib::Object, glib::SignalHandlerId)>>>,
     pub sender: glib::Sender<Message>,
-    pub presses_remaining: Arc<RwLock<u32>>,
+    pub challenge: Rc<RefCell<Box<dyn Challenge>>>,
+    /// Whether the break was ended early, by doing the challenge.
+    pub ended_early: Arc<AtomicBool>,
+    pub grab: Rc<Grab>,
+    pub allowed_windows: Rc<AllowedWindows>,
+    pub activity: Option<Activity>,
     pub start_time: SystemTime,
     pub app_sender: glib::Sender<Msg>,
 }
 
 impl State {
     pub fn new(
-        config: &Config,
+        glade_src: &str,
+        challenge: Box<dyn Challenge>,
+        activity: Option<Activity>,
+        allowed_windows: AllowedWindows,
         app_sender: glib::Sender<Msg>,
         sender: glib::Sender<Message>,
     ) -> Self {
-        let monitors = Monitor::all();
-        let monitors_num = monitors.len();
-
-        let builders = std::iter::repeat_with(builder::create)
-            .take(monitors_num)
+        let glade_src: Rc<str> = Rc::from(glade_src);
+        let screens = Monitor::all()
+            .into_iter()
+            .map(|monitor| Screen {
+                builder: builder::create(&glade_src),
+                monitor,
+            })
             .collect();
 
         Self {
-            builders,
-            monitors,
+            screens: Rc::new(RefCell::new(screens)),
+            glade_src,
+            monitor_signal_handlers: Rc::new(RefCell::new(vec![])),
             sender,
-            presses_remaining: Arc::new(RwLock::new(
-                config.settings.clicks_to_end_break_early,
-            )),
+            challenge: Rc::new(RefCell::new(challenge)),
+            ended_early: Arc::new(AtomicBool::new(false)),
+            grab: Rc::new(Grab::new(Instant::now())),
+            allowed_windows: Rc::new(allowed_windows),
+            activity,
             start_time: SystemTime::now(),
             app_sender,
         }
     }
 
-    pub fn read_presses_remaining(&self) -> RwLockReadGuard<u32> {
-        self.presses_remaining.read().unwrap()
+    pub fn read_challenge(&self) -> Ref<Box<dyn Challenge>> {
+        self.challenge.borrow()
     }
 
-    /// Decrements the number of presses remaining by 1.
-    pub fn decrement_presses_remaining(&self) -> u32 {
-        let state_presses_remaining: &mut u32 =
-            &mut *self.presses_remaining.write().unwrap();
+    pub fn write_challenge(&self) -> RefMut<Box<dyn Challenge>> {
+        self.challenge.borrow_mut()
+    }
 
-        if *state_presses_remaining > 0 {
-            *state_presses_remaining -= 1;
+    /// Create a break screen for `monitor`.  It isn't added to `screens`.
+    pub fn create_screen(&self, monitor: Monitor) -> Screen {
+        Screen {
+            builder: builder::create(&self.glade_src),
+            monitor,
         }
+    }
 
-        *state_presses_remaining
+    fn builders(&self) -> Vec<gtk::Builder> {
+        self.screens
+            .borrow()
+            .iter()
+            .map(|screen| screen.builder.clone())
+            .collect()
     }
 
     pub fn get_app_wins(&self) -> Vec<gtk::ApplicationWindow> {
-        self.builders
+        self.screens.borrow().iter().map(Screen::app_win).collect()
+    }
+
+    pub fn get_time_remaining_labels(&self) -> Vec<gtk::Label> {
+        self.builders()
             .iter()
-            .map(|builder| builder.get_object_expect("app_win"))
+            .map(|builder| builder.get_object_expect("time_remaining_label"))
             .collect()
     }
 
-    pub fn get_app_wins_with_monitors(
+    /// The challenge progress label of each screen, and its challenge
+    /// label, which custom Glade files can leave out.
+    pub fn get_challenge_labels(
         &self,
-    ) -> Vec<(gtk::ApplicationWindow, Monitor)> {
-        self.builders
+    ) -> Vec<(gtk::Label, Option<gtk::Label>)> {
+        self.builders()
             .iter()
-            .zip(&self.monitors)
-            .map(|(builder, monitor)| {
-                (builder.get_object_expect("app_win"), monitor.clone())
+            .map(|screen_builder| {
+                (
+                    builder::get_label(
+                        screen_builder,
+                        "challenge_progress_label",
+                    ),
+                    screen_builder.get_object("challenge_label"),
+                )
             })
             .collect()
     }
 
-    pub fn get_time_remaining_labels(&self) -> Vec<gtk::Label> {
-        self.builders
+    pub fn get_activity_labels(&self) -> Vec<gtk::Label> {
+        self.builders()
             .iter()
-            .map(|builder| builder.get_object_expect("time_remaining_label"))
+            .filter_map(|builder| builder.get_object("activity_label"))
             .collect()
     }
 
-    pub fn get_presses_remaining_labels(&self) -> Vec<gtk::Label> {
-        self.builders
-            .iter()
-            .map(|builder| builder.get_object_expect("presses_remaining_label"))
-            .collect()
+    pub fn notify_app_ended_early(&self) {
+        self.app_sender.send(Msg::BreakEndedEarly).expect(
+            "TODO: figure out what to do about channels potentially failing",
+        );
     }
 
     pub fn notify_app_end(&self) {
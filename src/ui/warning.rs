@@ -0,0 +1,205 @@
+// This module shows a small banner shortly before a break, counting down to
+// it.  The banner stays on top of the other windows without grabbing the
+// keyboard and mouse, and lets the user put the break off or start it now.
+// The warning is also sent as a desktop notification, which is closed again
+// with the banner.
+
+use glib::clone;
+use glib::source::Continue;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::prelude::*;
+use crate::config::Config;
+use crate::notification::{Notification, Notifier};
+use crate::Msg;
+
+/// How far from the top of the monitor the banner is shown.
+const BANNER_TOP_MARGIN: i32 = 40;
+
+/// How often the countdown on the banner is updated.
+const COUNTDOWN_STEP_MILLISECONDS: u32 = 200;
+
+/// The text of the banner, `remaining` before a break from the break tier
+/// called `tier_name`.
+fn countdown_text(tier_name: &str, remaining: Duration) -> String {
+    // Round up, so that the countdown ends at 0:01 instead of 0:00.
+    let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+    format!(
+        "Your {} starts in {}:{:02}",
+        tier_name,
+        secs / 60,
+        secs % 60
+    )
+}
+
+pub struct Warning {
+    lead_time: Duration,
+    /// How long the "Postpone" button puts the break off for.
+    postpone: Rc<Cell<Duration>>,
+    window: gtk::Window,
+    label: gtk::Label,
+    tier_name: Rc<RefCell<String>>,
+    /// When the break starts, while the banner is shown.
+    break_time: Rc<Cell<Option<Instant>>>,
+    /// The timeout updating the countdown, while the banner is shown.
+    source: RefCell<Option<glib::SourceId>>,
+    notifier: Notifier,
+    /// The notification sent with the banner, while it is shown.
+    notification: RefCell<Option<Notification>>,
+}
+
+impl Warning {
+    #[must_use]
+    pub fn new(
+        config: &Config,
+        app_sender: glib::Sender<Msg>,
+        notifier: Notifier,
+    ) -> Self {
+        let window = gtk::Window::new(gtk::WindowType::Toplevel);
+        window.set_title("Break-Time warning");
+        window.set_decorated(false);
+        window.set_resizable(false);
+        window.set_keep_above(true);
+        window.set_skip_taskbar_hint(true);
+        window.set_skip_pager_hint(true);
+        window.set_accept_focus(false);
+        window.set_focus_on_map(false);
+        window.set_type_hint(gdk::WindowTypeHint::Notification);
+        window.get_style_context().add_class("warning");
+
+        let label = gtk::Label::new(None);
+        let postpone_button = gtk::Button::new_with_label("Postpone");
+        let start_now_button = gtk::Button::new_with_label("Start now");
+        let postpone = Rc::new(Cell::new(Duration::from_secs(
+            config.settings.postpone_break_seconds.into(),
+        )));
+        postpone_button.connect_clicked(
+            clone!(@strong app_sender, @strong postpone => move |_| {
+                app_sender.send(Msg::PostponeBreak(postpone.get())).expect(
+                    "TODO: figure out what to do about channels potentially failing",
+                );
+            }),
+        );
+        start_now_button.connect_clicked(move |_| {
+            app_sender.send(Msg::StartBreakNow).expect(
+                "TODO: figure out what to do about channels potentially failing",
+            );
+        });
+
+        let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+        hbox.set_border_width(12);
+        hbox.pack_start(&label, true, true, 0);
+        hbox.pack_start(&postpone_button, false, false, 0);
+        hbox.pack_start(&start_now_button, false, false, 0);
+        window.add(&hbox);
+
+        Self {
+            lead_time: Duration::from_secs(
+                config.settings.warning_before_break_seconds.into(),
+            ),
+            postpone,
+            window,
+            label,
+            tier_name: Rc::new(RefCell::new(String::new())),
+            break_time: Rc::new(Cell::new(None)),
+            source: RefCell::new(None),
+            notifier,
+            notification: RefCell::new(None),
+        }
+    }
+
+    /// Use the warning settings from `config`, after switching profiles.
+    pub fn reconfigure(&mut self, config: &Config) {
+        self.lead_time = Duration::from_secs(
+            config.settings.warning_before_break_seconds.into(),
+        );
+        self.postpone.set(Duration::from_secs(
+            config.settings.postpone_break_seconds.into(),
+        ));
+    }
+
+    /// Show the banner if a break from the break tier called `tier_name` is
+    /// `remaining` away and that is within the warning time.  Otherwise, hide
+    /// it.
+    pub fn update(&self, remaining: Duration, tier_name: &str) {
+        if remaining > self.lead_time || remaining == Duration::from_secs(0) {
+            self.hide();
+            return;
+        }
+
+        let was_shown = self.break_time.get().is_some();
+        self.break_time.set(Some(Instant::now() + remaining));
+        self.tier_name.replace(String::from(tier_name));
+        self.label.set_text(&countdown_text(tier_name, remaining));
+        if was_shown {
+            return;
+        }
+
+        *self.notification.borrow_mut() = Some(
+            self.notifier
+                .notify("Break-Time!", &countdown_text(tier_name, remaining)),
+        );
+        self.show();
+        let label = self.label.clone();
+        let tier_name = self.tier_name.clone();
+        let break_time = self.break_time.clone();
+        let source_id =
+            gtk::timeout_add(COUNTDOWN_STEP_MILLISECONDS, move || {
+                if let Some(break_time) = break_time.get() {
+                    let remaining =
+                        break_time.saturating_duration_since(Instant::now());
+                    label.set_text(&countdown_text(
+                        &tier_name.borrow(),
+                        remaining,
+                    ));
+                }
+                Continue(true)
+            });
+        *self.source.borrow_mut() = Some(source_id);
+    }
+
+    fn show(&self) {
+        self.window.show_all();
+        let opt_monitor = gdk::Display::get_default()
+            .and_then(|display| display.get_primary_monitor());
+        if let Some(monitor) = opt_monitor {
+            let monitor_rect = monitor.get_geometry();
+            let (width, _) = self.window.get_size();
+            self.window.move_(
+                monitor_rect.x + (monitor_rect.width - width) / 2,
+                monitor_rect.y + BANNER_TOP_MARGIN,
+            );
+        }
+    }
+
+    /// Hide the banner and close its notification.
+    pub fn hide(&self) {
+        self.break_time.set(None);
+        if let Some(source_id) = self.source.borrow_mut().take() {
+            glib::source_remove(source_id);
+        }
+        if let Some(notification) = self.notification.borrow_mut().take() {
+            self.notifier.close(&notification);
+        }
+        self.window.hide();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_countdown_text() {
+        assert_eq!(
+            countdown_text("long break", Duration::from_millis(59_500)),
+            "Your long break starts in 1:00"
+        );
+        assert_eq!(
+            countdown_text("break", Duration::from_secs(5)),
+            "Your break starts in 0:05"
+        );
+    }
+}
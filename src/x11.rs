@@ -73,4 +73,48 @@ impl X11 {
             Some(window)
         }
     }
+
+    /// How long it has been since the last keyboard or mouse input, from the
+    /// screensaver extension.
+    pub fn ms_since_user_input(&self) -> Option<u32> {
+        let root_win = self.get_root_win()?;
+        xcb::screensaver::query_info(&self.conn, root_win)
+            .get_reply()
+            .ok()
+            .map(|reply| reply.ms_since_user_input())
+    }
+
+    /// The names of the outputs that have a monitor connected, like `eDP-1`
+    /// or `DP-1`.
+    pub fn connected_outputs(&self) -> Vec<String> {
+        let opt_resources = self.get_root_win().and_then(|root_win| {
+            xcb::randr::get_screen_resources_current(&self.conn, root_win)
+                .get_reply()
+                .ok()
+        });
+        let resources = match opt_resources {
+            None => return vec![],
+            Some(resources) => resources,
+        };
+        resources
+            .outputs()
+            .iter()
+            .filter_map(|&output| {
+                xcb::randr::get_output_info(
+                    &self.conn,
+                    output,
+                    resources.config_timestamp(),
+                )
+                .get_reply()
+                .ok()
+            })
+            .filter(|output_info| {
+                u32::from(output_info.connection())
+                    == xcb::randr::CONNECTION_CONNECTED
+            })
+            .map(|output_info| {
+                String::from_utf8_lossy(output_info.name()).into_owned()
+            })
+            .collect()
+    }
 }